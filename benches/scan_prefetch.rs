@@ -0,0 +1,51 @@
+//! Times a full-series export from a file-backed store, reading one chunk per query
+//! against reading eight. Run with `cargo bench --bench scan_prefetch`.
+
+use std::time::{Duration, Instant};
+
+use kelpie::{DataPoint, Kelpie};
+
+const HOURS: i64 = 2_000;
+const POINTS_PER_HOUR: i64 = 100;
+const RUNS: usize = 5;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let path = std::env::temp_dir().join(format!("kelpie-scan-prefetch-{}", std::process::id()));
+    let res = bench(&path);
+    let _ = std::fs::remove_file(&path);
+    res
+}
+
+fn bench(path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut kelpie = Kelpie::new_path(path)?;
+    for i in 0..HOURS * POINTS_PER_HOUR {
+        let point = DataPoint {
+            time: i * (3_600_000 / POINTS_PER_HOUR) + 7,
+            value: (i as f64).sin(),
+        };
+        kelpie.insert(0, point)?;
+    }
+    kelpie.close()?;
+
+    let mut kelpie = Kelpie::new_path(path)?;
+    for prefetch in [1, 8] {
+        kelpie.set_prefetch_chunks(prefetch);
+        let mut best = Duration::MAX;
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            let mut exported = 0;
+            for point in kelpie.query_iter(0, 0, HOURS * 3_600_000)? {
+                point?;
+                exported += 1;
+            }
+            assert_eq!(exported, HOURS * POINTS_PER_HOUR);
+            best = best.min(start.elapsed());
+        }
+        println!(
+            "prefetch {prefetch}: best of {RUNS} exports of {} points took {:?}",
+            HOURS * POINTS_PER_HOUR,
+            best
+        );
+    }
+    Ok(())
+}
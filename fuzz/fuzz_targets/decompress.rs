@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use kelpie::Chunk;
+
+// arbitrary bytes must be rejected with an error, never a panic
+fuzz_target!(|data: Vec<u8>| {
+    let chunk = Chunk::from_bytes(data);
+    let _ = chunk.decompress();
+    let _ = chunk.decompress_times();
+});
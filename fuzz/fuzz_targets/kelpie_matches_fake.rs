@@ -1,5 +1,7 @@
 #![no_main]
 
+use std::mem::discriminant;
+
 use libfuzzer_sys::fuzz_target;
 use kelpie::{Kelpie, KelpieFake, DataPoint};
 
@@ -38,8 +40,11 @@ fuzz_target!(|data: Vec<Cmd>| {
     for cmd in data.into_iter() {
         match cmd {
             Cmd::Insert { series_key, point } => {
-                kelpie.insert(series_key, point.into());
-                fake.insert(series_key, point.into());
+                match (kelpie.insert(series_key, point.into()), fake.insert(series_key, point.into())) {
+                    (Ok(_), Ok(_)) => {}
+                    (Err(a), Err(b)) => assert_eq!(discriminant(&a), discriminant(&b), "{a} vs {b}"),
+                    (a, b) => panic!("insert results not matching: {a:?} vs {b:?}"),
+                }
             },
             Cmd::Query { series_key, start, stop } => {
                 if stop < start { continue; }
@@ -0,0 +1,127 @@
+use crate::DataPoint;
+
+/// How `Kelpie::query_aggregate` reduces the points of a bucket to one value.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Aggregator {
+    Mean,
+    Min,
+    Max,
+    Sum,
+    Count,
+    /// The value of the latest point in the bucket.
+    Last,
+    /// The value of the earliest point in the bucket.
+    First,
+}
+
+/// How `Kelpie::query_filled` fills grid timestamps without a point.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FillPolicy {
+    /// Leaves the timestamp empty.
+    None,
+    /// Repeats the latest earlier point.
+    Previous,
+    /// Interpolates linearly between the latest earlier and the earliest later point.
+    Linear,
+    /// Uses the given value.
+    Constant(f64),
+}
+
+impl FillPolicy {
+    /// Fills `time` from the points around it. Either is `None` when the series has no
+    /// point on that side, and the fill is then `None` unless it needs no neighbours.
+    pub(crate) fn fill(
+        self,
+        time: i64,
+        prev: Option<DataPoint>,
+        next: Option<DataPoint>,
+    ) -> Option<f64> {
+        match self {
+            FillPolicy::None => None,
+            FillPolicy::Previous => prev.map(|prev| prev.value),
+            FillPolicy::Linear => {
+                let (prev, next) = (prev?, next?);
+                let progress = (time - prev.time) as f64 / (next.time - prev.time) as f64;
+                Some(prev.value + (next.value - prev.value) * progress)
+            }
+            FillPolicy::Constant(value) => Some(value),
+        }
+    }
+}
+
+/// The start of the bucket `time` falls into, buckets being aligned to time 0.
+pub(crate) fn bucket_start(time: i64, bucket: i64) -> i64 {
+    time.div_euclid(bucket) * bucket
+}
+
+/// How much a counter grew from `prev` to `next`. A decrease is a reset to zero, after
+/// which the counter grew to `next`.
+pub(crate) fn counter_increase(prev: f64, next: f64) -> f64 {
+    if next < prev {
+        next
+    } else {
+        next - prev
+    }
+}
+
+// running state of every aggregator over the values of one bucket, in time order
+pub(crate) struct Bucket {
+    pub(crate) start: i64,
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+    first: f64,
+    last: f64,
+    first_time: i64,
+    last_time: i64,
+    // counter increase, see `Kelpie::query_rate`
+    increase: f64,
+}
+
+impl Bucket {
+    pub(crate) fn new(start: i64, point: DataPoint) -> Self {
+        let value = point.value;
+        Bucket {
+            start,
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+            first: value,
+            last: value,
+            first_time: point.time,
+            last_time: point.time,
+            increase: 0.0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, point: DataPoint) {
+        let value = point.value;
+        self.increase += counter_increase(self.last, value);
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.last = value;
+        self.last_time = point.time;
+    }
+
+    pub(crate) fn value(&self, agg: Aggregator) -> f64 {
+        match agg {
+            Aggregator::Mean => self.sum / self.count as f64,
+            Aggregator::Min => self.min,
+            Aggregator::Max => self.max,
+            Aggregator::Sum => self.sum,
+            Aggregator::Count => self.count as f64,
+            Aggregator::Last => self.last,
+            Aggregator::First => self.first,
+        }
+    }
+
+    /// The counter increase per unit of time, or `None` for a single point.
+    pub(crate) fn rate(&self) -> Option<f64> {
+        let elapsed = self.last_time - self.first_time;
+        (elapsed > 0).then(|| self.increase / elapsed as f64)
+    }
+}
@@ -0,0 +1,142 @@
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::{Kelpie, KelpieChunkStore, SetChunkError, SharedKelpie, SqliteChunkStore};
+
+/// A `SharedKelpie` flushed periodically by a thread, see `Kelpie::spawn_autoflush`.
+///
+/// The flush thread goes through the same lock as every other access, so inserts and
+/// flushes never interleave. Only chunks changed since they were last written are
+/// flushed, so an idle `Kelpie` costs nothing per tick. Dropping the handle stops the
+/// thread; the `Kelpie` then flushes on drop as usual, ignoring errors. Call
+/// `shutdown` to see them.
+pub struct AutoflushHandle<S: KelpieChunkStore + Send + Sync + 'static = SqliteChunkStore> {
+    shared: SharedKelpie<S>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<S: KelpieChunkStore + Send + Sync + 'static> AutoflushHandle<S> {
+    pub(crate) fn spawn(kelpie: Kelpie<S>, interval: Duration) -> Self {
+        let shared = SharedKelpie::new(kelpie);
+        let (stop, stopped) = channel();
+        let flushed = shared.clone();
+        let thread = std::thread::spawn(move || {
+            // a message or a dropped sender both mean stop
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                // errors are left for the next flush or `shutdown` to report
+                let _ = flushed.flush_all();
+            }
+        });
+        Self {
+            shared,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// The `Kelpie` being flushed, to insert and query through. Clones may be handed
+    /// to other threads.
+    pub fn shared(&self) -> &SharedKelpie<S> {
+        &self.shared
+    }
+
+    /// Stops the flush thread, then flushes and closes the `Kelpie`, see `Kelpie::close`.
+    /// If clones of `shared` are still alive, the `Kelpie` is only flushed and stays
+    /// open for them.
+    pub fn shutdown(mut self) -> Result<(), SetChunkError> {
+        self.stop_thread();
+        let shared = self.shared.clone();
+        drop(self);
+        match shared.try_into_inner() {
+            Ok(kelpie) => kelpie.close(),
+            Err(shared) => shared.flush_all(),
+        }
+    }
+
+    fn stop_thread(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<S: KelpieChunkStore + Send + Sync + 'static> Drop for AutoflushHandle<S> {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::tests::TempPath;
+    use crate::{DataPoint, Kelpie, KelpieChunkStore};
+
+    #[test]
+    fn should_flush_in_background() -> Result<(), Box<dyn std::error::Error>> {
+        let kelpie = Kelpie::new_memory()?;
+        let handle = kelpie.spawn_autoflush(Duration::from_millis(5));
+        handle.shared().insert(
+            0,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        )?;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while handle
+            .shared()
+            .with(|kelpie| kelpie.chunk_store.list_chunks(0))?
+            .is_empty()
+        {
+            if Instant::now() > deadline {
+                Err("chunk was never flushed")?;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(handle.shared().query(0, 0, 100)?.data.len(), 1);
+        handle.shutdown()?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_persist_points_inserted_before_shutdown() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let handle = Kelpie::new_path(temp.path())?.spawn_autoflush(Duration::from_secs(3600));
+        for time in 0..100 {
+            handle.shared().insert(0, DataPoint { time, value: 1.0 })?;
+        }
+        handle.shutdown()?;
+
+        let kelpie = Kelpie::new_path_read_only(temp.path())?;
+        assert_eq!(kelpie.query(0, 0, 100)?.data.len(), 100);
+        Ok(())
+    }
+
+    #[test]
+    fn should_flush_on_shutdown_while_shared() -> Result<(), Box<dyn std::error::Error>> {
+        let handle = Kelpie::new_memory()?.spawn_autoflush(Duration::from_secs(3600));
+        let shared = handle.shared().clone();
+        shared.insert(
+            0,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        )?;
+        handle.shutdown()?;
+
+        assert_eq!(
+            shared
+                .with(|kelpie| kelpie.chunk_store.list_chunks(0))?
+                .len(),
+            1
+        );
+        assert_eq!(shared.query(0, 0, 100)?.data.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! UTC calendar math on millisecond timestamps, for calendar-aligned chunks.
+
+use crate::CalendarUnit;
+
+pub(crate) const DAY: i64 = 24 * 60 * 60 * 1000;
+
+// days since 1970-01-01 of a date in the proleptic Gregorian calendar, `month` in 1..=12,
+// after http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // years start in March, so the leap day is the last day of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// the year and month, in 1..=12, of a day counted from 1970-01-01
+fn year_month_from_days(days: i64) -> (i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+    (year, month)
+}
+
+/// Returns the `[start, stop)` of the day, week or month holding `time`, in milliseconds
+/// since the Unix epoch. Weeks start on Monday. Like fixed-width chunks, the buckets at
+/// either end of the i64 range are cut short instead of overflowing.
+pub(crate) fn bucket(unit: CalendarUnit, time: i64) -> (i64, i64) {
+    let days = time.div_euclid(DAY);
+    let (start_day, stop_day) = match unit {
+        CalendarUnit::Day => (days, days + 1),
+        CalendarUnit::Week => {
+            // 1970-01-01 was a Thursday
+            let start = days - (days + 3).rem_euclid(7);
+            (start, start + 7)
+        }
+        CalendarUnit::Month => {
+            let (year, month) = year_month_from_days(days);
+            let (next_year, next_month) = if month == 12 {
+                (year + 1, 1)
+            } else {
+                (year, month + 1)
+            };
+            (
+                days_from_civil(year, month, 1),
+                days_from_civil(next_year, next_month, 1),
+            )
+        }
+    };
+    (start_day.saturating_mul(DAY), stop_day.saturating_mul(DAY))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket, days_from_civil, year_month_from_days, DAY};
+    use crate::CalendarUnit;
+
+    // 2024-02-01T00:00:00Z
+    const FEB_2024: i64 = 1_706_745_600_000;
+
+    #[test]
+    fn should_convert_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2024, 2, 1) * DAY, FEB_2024);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        for days in (-800_000..800_000).step_by(997) {
+            let (year, month) = year_month_from_days(days);
+            let first = days_from_civil(year, month, 1);
+            assert!(first <= days && days - first < 31, "{days}");
+        }
+    }
+
+    #[test]
+    fn should_bucket_months_of_varying_length() {
+        let month = |time| bucket(CalendarUnit::Month, time);
+        let mar_2024 = FEB_2024 + 29 * DAY;
+        // leap year
+        assert_eq!(month(FEB_2024), (FEB_2024, mar_2024));
+        assert_eq!(month(mar_2024 - 1), (FEB_2024, mar_2024));
+        assert_eq!(month(mar_2024), (mar_2024, mar_2024 + 31 * DAY));
+        assert_eq!(month(FEB_2024 - 1), (FEB_2024 - 31 * DAY, FEB_2024));
+        // the end of the year
+        let jan_2025 = days_from_civil(2025, 1, 1) * DAY;
+        assert_eq!(month(jan_2025 - 1), (jan_2025 - 31 * DAY, jan_2025));
+        for (year, days) in [(2023, 28), (2024, 29), (2000, 29), (2100, 28)] {
+            let start = days_from_civil(year, 2, 1) * DAY;
+            assert_eq!(month(start + DAY), (start, start + days * DAY), "{year}");
+        }
+    }
+
+    #[test]
+    fn should_bucket_days_and_weeks() {
+        assert_eq!(
+            bucket(CalendarUnit::Day, FEB_2024 + 5),
+            (FEB_2024, FEB_2024 + DAY)
+        );
+        assert_eq!(bucket(CalendarUnit::Day, -1), (-DAY, 0));
+        // 2024-02-01 was a Thursday, the week started on Monday 2024-01-29
+        let monday = FEB_2024 - 3 * DAY;
+        assert_eq!(
+            bucket(CalendarUnit::Week, FEB_2024),
+            (monday, monday + 7 * DAY)
+        );
+        assert_eq!(
+            bucket(CalendarUnit::Week, monday - 1),
+            (monday - 7 * DAY, monday)
+        );
+        assert_eq!(bucket(CalendarUnit::Week, 0), (-3 * DAY, 4 * DAY));
+    }
+
+    #[test]
+    fn should_cut_buckets_at_the_ends_of_the_range() {
+        for unit in [CalendarUnit::Day, CalendarUnit::Week, CalendarUnit::Month] {
+            let (start, stop) = bucket(unit, i64::MAX);
+            assert!(start > i64::MAX - 32 * DAY && stop == i64::MAX, "{unit:?}");
+            let (start, stop) = bucket(unit, i64::MIN);
+            assert!(start == i64::MIN && stop > i64::MIN, "{unit:?}");
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{BatchError, DataPoint, GetChunkError, Kelpie};
+
+// written by `export_csv`, skipped by `import_csv` if it is the first row
+const HEADER: &str = "time,value";
+
+#[derive(thiserror::Error, Debug)]
+pub enum CsvError {
+    #[error("Line {line}: {reason}")]
+    Malformed { line: usize, reason: String },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Get(#[from] GetChunkError),
+    #[error(transparent)]
+    Batch(#[from] BatchError),
+}
+
+pub(crate) fn write_header<W: Write>(w: &mut W) -> io::Result<()> {
+    writeln!(w, "{HEADER}")
+}
+
+// values are written in their shortest form that parses back to the same f64
+pub(crate) fn write_point<W: Write>(w: &mut W, point: DataPoint) -> io::Result<()> {
+    writeln!(w, "{},{}", point.time, point.value)
+}
+
+/// Reads `time,value` rows. Blank lines and a `time,value` header on the first
+/// non-blank line are skipped, anything else that isn't a row of a storable point is
+/// an error.
+pub(crate) fn read_points<R: BufRead>(r: R) -> Result<Vec<DataPoint>, CsvError> {
+    let mut points = vec![];
+    let mut first = true;
+    for (i, line) in r.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let is_header = first && line.eq_ignore_ascii_case(HEADER);
+        first = false;
+        if is_header {
+            continue;
+        }
+        let malformed = |reason: String| CsvError::Malformed {
+            line: i + 1,
+            reason,
+        };
+        let Some((time, value)) = line.split_once(',') else {
+            return Err(malformed(format!("expected time,value, got {line:?}")));
+        };
+        let time = time
+            .trim()
+            .parse()
+            .map_err(|e| malformed(format!("time {time:?}: {e}")))?;
+        let value = value
+            .trim()
+            .parse()
+            .map_err(|e| malformed(format!("value {value:?}: {e}")))?;
+        let point = DataPoint { time, value };
+        <Kelpie>::validate(&point).map_err(|e| malformed(e.to_string()))?;
+        points.push(point);
+    }
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_points, CsvError};
+    use crate::DataPoint;
+
+    #[test]
+    fn should_skip_header_and_blank_lines() -> Result<(), Box<dyn std::error::Error>> {
+        let csv = "\n Time,Value \n10, 1.5\n\n20,-2\n";
+        let points = read_points(csv.as_bytes())?;
+        assert_eq!(
+            points,
+            vec![
+                DataPoint {
+                    time: 10,
+                    value: 1.5
+                },
+                DataPoint {
+                    time: 20,
+                    value: -2.0
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_report_malformed_line() {
+        for (csv, bad_line) in [
+            ("time,value\n1,2\n3\n", 3),
+            ("1,2\n\nx,2\n", 3),
+            ("1,2,3\n", 1),
+            ("1,2\ntime,value\n", 2),
+            ("1,2\n2,NaN\n", 2),
+            ("-1,2\n", 1),
+            ("1,2\n\n9223372036854775807,2\n", 3),
+        ] {
+            match read_points(csv.as_bytes()) {
+                Err(CsvError::Malformed { line, .. }) => assert_eq!(line, bad_line, "{csv:?}"),
+                res => panic!("{csv:?} should be malformed, got {res:?}"),
+            }
+        }
+    }
+}
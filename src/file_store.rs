@@ -0,0 +1,717 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use crate::{Chunk, ChunkMeta, GetChunkError, KelpieChunkStore, SetChunkError};
+
+// record kinds
+const CHUNKS: u8 = 0;
+const METADATA: u8 = 1;
+const NAME: u8 = 2;
+const DELETE_SERIES: u8 = 3;
+const TRUNCATE: u8 = 4;
+const SCHEDULE: u8 = 5;
+
+// length and kind of a record
+const HEADER_LEN: u64 = 5;
+
+/// Chunk store appending every change as a record to a single log file, without SQLite.
+///
+/// Records are `[payload length: u32][kind: u8][payload]`, little endian. A chunk write
+/// is a record of the chunks it removes followed by the `(series, start, stop, blob)`
+/// of the chunks it adds. Opening a file scans it once to rebuild an in-memory index
+/// of where the latest copy of each chunk lives, so reads are a single seek. Replaced
+/// and deleted chunks stay in the file until `compact` rewrites it.
+///
+/// Every change is one record, so it is applied entirely or not at all: a record cut
+/// short by a crash is dropped the next time the file is opened. Records reach the OS
+/// as they are written, but the disk only when the OS flushes them, unless `set_sync`
+/// is enabled and every write waits for an fsync.
+pub struct FileChunkStore {
+    path: PathBuf,
+    file: File,
+    // (series, start, stop) -> offset and length of the chunk blob
+    index: BTreeMap<(i64, i64, i64), (u64, u64)>,
+    metadata: HashMap<i64, String>,
+    names: HashMap<i64, String>,
+    schedules: HashMap<i64, String>,
+    // end of the last complete record
+    len: u64,
+    sync: bool,
+}
+
+impl FileChunkStore {
+    /// Opens or creates a log at `path`. An incomplete record at the end of the file,
+    /// left by a crash during a write, is truncated away.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut store = Self {
+            path,
+            file,
+            index: BTreeMap::new(),
+            metadata: HashMap::new(),
+            names: HashMap::new(),
+            schedules: HashMap::new(),
+            len: 0,
+            sync: false,
+        };
+        store.replay()?;
+        Ok(store)
+    }
+
+    /// Whether every write is fsynced before it returns. Off by default.
+    pub fn set_sync(&mut self, sync: bool) {
+        self.sync = sync;
+    }
+
+    /// Rewrites the file with only the current chunks and metadata, dropping every
+    /// replaced or deleted record.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let mut temp_path = OsString::from(self.path.as_os_str());
+        temp_path.push(".compact");
+        let temp_path = PathBuf::from(temp_path);
+        let _ = std::fs::remove_file(&temp_path);
+
+        let mut compacted = Self::open(&temp_path)?;
+        for (&series_key, json) in &self.metadata {
+            compacted.append(METADATA, &encode_text(series_key, json)?)?;
+        }
+        for (&series_key, name) in &self.names {
+            compacted.append(NAME, &encode_text(series_key, name)?)?;
+        }
+        for (&series_key, schedule) in &self.schedules {
+            compacted.append(SCHEDULE, &encode_text(series_key, schedule)?)?;
+        }
+        for (&(series_key, start, stop), &(offset, len)) in &self.index {
+            let meta = ChunkMeta {
+                series_key,
+                start,
+                stop,
+            };
+            let chunk = Chunk::from_bytes(self.read_at(offset, len)?);
+            compacted.append(CHUNKS, &encode_chunks(&[], &[(meta, chunk)])?)?;
+        }
+        compacted.file.sync_all()?;
+        std::fs::rename(&temp_path, &self.path)?;
+
+        compacted.path = std::mem::take(&mut self.path);
+        compacted.sync = self.sync;
+        *self = compacted;
+        Ok(())
+    }
+
+    fn replay(&mut self) -> io::Result<()> {
+        let file_len = self.file.metadata()?.len();
+        let mut reader = BufReader::new(self.file.try_clone()?);
+        reader.seek(SeekFrom::Start(0))?;
+        let mut offset = 0;
+        loop {
+            let mut header = [0; HEADER_LEN as usize];
+            if !read_record_part(&mut reader, &mut header)? {
+                break;
+            }
+            let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as u64;
+            // a length past the end of the file belongs to a record cut short
+            if len > file_len - offset - HEADER_LEN {
+                break;
+            }
+            let mut payload = vec![0; len as usize];
+            if !read_record_part(&mut reader, &mut payload)? {
+                break;
+            }
+            self.apply(header[4], &payload, offset + HEADER_LEN)?;
+            offset += HEADER_LEN + len;
+        }
+        if file_len > offset {
+            self.file.set_len(offset)?;
+        }
+        self.len = offset;
+        Ok(())
+    }
+
+    // updates the in-memory state with a record whose payload starts at `payload_offset`
+    fn apply(&mut self, kind: u8, payload: &[u8], payload_offset: u64) -> io::Result<()> {
+        let mut decoder = Decoder {
+            data: payload,
+            pos: 0,
+        };
+        match kind {
+            CHUNKS => {
+                for _ in 0..decoder.u32()? {
+                    let key = (decoder.i64()?, decoder.i64()?, decoder.i64()?);
+                    self.index.remove(&key);
+                }
+                for _ in 0..decoder.u32()? {
+                    let key = (decoder.i64()?, decoder.i64()?, decoder.i64()?);
+                    let (pos, blob) = decoder.bytes()?;
+                    let location = (payload_offset + pos as u64, blob.len() as u64);
+                    self.index.insert(key, location);
+                }
+            }
+            METADATA => {
+                let series_key = decoder.i64()?;
+                self.metadata.insert(series_key, decoder.text()?);
+            }
+            NAME => {
+                let series_key = decoder.i64()?;
+                self.names.insert(series_key, decoder.text()?);
+            }
+            SCHEDULE => {
+                let series_key = decoder.i64()?;
+                self.schedules.insert(series_key, decoder.text()?);
+            }
+            DELETE_SERIES => {
+                let series_key = decoder.i64()?;
+                self.index
+                    .retain(|&(chunk_series, _, _), _| chunk_series != series_key);
+                self.metadata.remove(&series_key);
+                self.names.remove(&series_key);
+                self.schedules.remove(&series_key);
+            }
+            TRUNCATE => {
+                self.index.clear();
+                self.metadata.clear();
+                self.names.clear();
+                self.schedules.clear();
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown record kind {kind}"),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, kind: u8, payload: &[u8]) -> io::Result<()> {
+        let mut record = Vec::with_capacity(HEADER_LEN as usize + payload.len());
+        put_len(&mut record, payload.len())?;
+        record.push(kind);
+        record.extend_from_slice(payload);
+        let written = self.file.write_all(&record).and_then(|()| {
+            if self.sync {
+                self.file.sync_data()?;
+            }
+            Ok(())
+        });
+        if let Err(e) = written {
+            // drop a partial record so later records stay readable
+            let _ = self.file.set_len(self.len);
+            return Err(e);
+        }
+        let payload_offset = self.len + HEADER_LEN;
+        self.len += record.len() as u64;
+        self.apply(kind, payload, payload_offset)
+    }
+
+    // positional reads leave the cursor alone, so reads through `&self` never race
+    #[cfg(unix)]
+    fn read_at(&self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut data = vec![0; len as usize];
+        self.file.read_exact_at(&mut data, offset)?;
+        Ok(data)
+    }
+
+    #[cfg(windows)]
+    fn read_at(&self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut data = vec![0; len as usize];
+        let mut read = 0;
+        while read < data.len() {
+            match self
+                .file
+                .seek_read(&mut data[read..], offset + read as u64)?
+            {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => read += n,
+            }
+        }
+        Ok(data)
+    }
+
+    fn read_chunk(&self, key: (i64, i64, i64)) -> Result<(ChunkMeta, Chunk), GetChunkError> {
+        let (offset, len) = self.index[&key];
+        let compressed_data = self
+            .read_at(offset, len)
+            .map_err(|e| GetChunkError::Driver(e.into()))?;
+        let (series_key, start, stop) = key;
+        let meta = ChunkMeta {
+            series_key,
+            start,
+            stop,
+        };
+        Ok((meta, Chunk::from_bytes(compressed_data)))
+    }
+
+    fn series_keys(&self, series_key: i64) -> impl Iterator<Item = (i64, i64, i64)> + '_ {
+        self.index
+            .range((series_key, i64::MIN, i64::MIN)..=(series_key, i64::MAX, i64::MAX))
+            .map(|(&key, _)| key)
+    }
+
+    fn write(&mut self, kind: u8, payload: io::Result<Vec<u8>>) -> Result<(), SetChunkError> {
+        payload
+            .and_then(|payload| self.append(kind, &payload))
+            .map_err(|e| SetChunkError::Driver(e.into()))
+    }
+}
+
+// fills `buf`, or returns false if the file ends first
+fn read_record_part(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// appends a length prefix, failing for lengths the format cannot hold
+fn put_len(payload: &mut Vec<u8>, len: usize) -> io::Result<()> {
+    let len = u32::try_from(len).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{len} bytes do not fit in a record"),
+        )
+    })?;
+    payload.extend_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+fn encode_chunks(remove: &[ChunkMeta], add: &[(ChunkMeta, Chunk)]) -> io::Result<Vec<u8>> {
+    let mut payload = vec![];
+    put_len(&mut payload, remove.len())?;
+    for meta in remove {
+        for n in [meta.series_key, meta.start, meta.stop] {
+            payload.extend_from_slice(&n.to_le_bytes());
+        }
+    }
+    put_len(&mut payload, add.len())?;
+    for (meta, chunk) in add {
+        for n in [meta.series_key, meta.start, meta.stop] {
+            payload.extend_from_slice(&n.to_le_bytes());
+        }
+        put_len(&mut payload, chunk.compressed_data.len())?;
+        payload.extend_from_slice(&chunk.compressed_data);
+    }
+    Ok(payload)
+}
+
+fn encode_text(series_key: i64, text: &str) -> io::Result<Vec<u8>> {
+    let mut payload = series_key.to_le_bytes().to_vec();
+    put_len(&mut payload, text.len())?;
+    payload.extend_from_slice(text.as_bytes());
+    Ok(payload)
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let data = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "record too short"))?;
+        self.pos += len;
+        Ok(data)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    // a length-prefixed byte string and its position in the payload
+    fn bytes(&mut self) -> io::Result<(usize, &'a [u8])> {
+        let len = self.u32()? as usize;
+        let pos = self.pos;
+        Ok((pos, self.take(len)?))
+    }
+
+    fn text(&mut self) -> io::Result<String> {
+        let (_, bytes) = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl KelpieChunkStore for FileChunkStore {
+    fn get_chunk(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<Option<(ChunkMeta, Chunk)>, GetChunkError> {
+        // the containing chunk with the latest start, then the earliest stop, walking
+        // back from `start` until a containing chunk's start is passed
+        let mut found: Option<(i64, i64, i64)> = None;
+        let candidates = self
+            .index
+            .range((series_key, i64::MIN, i64::MIN)..=(series_key, start, i64::MAX))
+            .rev();
+        for (&key @ (_, chunk_start, chunk_stop), _) in candidates {
+            if found.is_some_and(|(_, found_start, _)| chunk_start < found_start) {
+                break;
+            }
+            if stop <= chunk_stop {
+                found = Some(key);
+            }
+        }
+        found.map(|key| self.read_chunk(key)).transpose()
+    }
+
+    fn get_chunks_in_range(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(ChunkMeta, Chunk)>, GetChunkError> {
+        self.series_keys(series_key)
+            .filter(|&(_, chunk_start, chunk_stop)| chunk_start < stop && start < chunk_stop)
+            .map(|key| self.read_chunk(key))
+            .collect()
+    }
+
+    fn set_chunk(
+        &mut self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        chunk: &Chunk,
+    ) -> Result<(), SetChunkError> {
+        let meta = ChunkMeta {
+            series_key,
+            start,
+            stop,
+        };
+        self.write(CHUNKS, encode_chunks(&[], &[(meta, chunk.clone())]))
+    }
+
+    fn set_chunks(&mut self, chunks: &[(ChunkMeta, Chunk)]) -> Result<(), SetChunkError> {
+        self.replace_chunks(&[], chunks)
+    }
+
+    fn replace_chunks(
+        &mut self,
+        remove: &[ChunkMeta],
+        add: &[(ChunkMeta, Chunk)],
+    ) -> Result<(), SetChunkError> {
+        self.write(CHUNKS, encode_chunks(remove, add))
+    }
+
+    fn truncate(&mut self) -> Result<(), SetChunkError> {
+        self.write(TRUNCATE, Ok(vec![]))
+    }
+
+    fn delete_series(&mut self, series_key: i64) -> Result<(), SetChunkError> {
+        self.write(DELETE_SERIES, Ok(series_key.to_le_bytes().to_vec()))
+    }
+
+    fn delete_chunks_before(&mut self, series_key: i64, time: i64) -> Result<usize, SetChunkError> {
+        let remove: Vec<ChunkMeta> = self
+            .series_keys(series_key)
+            .filter(|&(_, _, stop)| stop <= time)
+            .map(|(series_key, start, stop)| ChunkMeta {
+                series_key,
+                start,
+                stop,
+            })
+            .collect();
+        if !remove.is_empty() {
+            self.replace_chunks(&remove, &[])?;
+        }
+        Ok(remove.len())
+    }
+
+    fn set_series_metadata(&mut self, series_key: i64, json: &str) -> Result<(), SetChunkError> {
+        self.write(METADATA, encode_text(series_key, json))
+    }
+
+    fn get_series_metadata(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+        Ok(self.metadata.get(&series_key).cloned())
+    }
+
+    fn set_series_name(&mut self, series_key: i64, name: &str) -> Result<(), SetChunkError> {
+        self.write(NAME, encode_text(series_key, name))
+    }
+
+    fn get_series_name(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+        Ok(self.names.get(&series_key).cloned())
+    }
+
+    fn set_series_schedule(
+        &mut self,
+        series_key: i64,
+        schedule: &str,
+    ) -> Result<(), SetChunkError> {
+        self.write(SCHEDULE, encode_text(series_key, schedule))
+    }
+
+    fn get_series_schedule(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+        Ok(self.schedules.get(&series_key).cloned())
+    }
+
+    fn list_all_chunks(&self) -> Result<Vec<ChunkMeta>, GetChunkError> {
+        Ok(self
+            .index
+            .keys()
+            .map(|&(series_key, start, stop)| ChunkMeta {
+                series_key,
+                start,
+                stop,
+            })
+            .collect())
+    }
+
+    fn list_chunks(&self, series_key: i64) -> Result<Vec<ChunkMeta>, GetChunkError> {
+        Ok(self
+            .series_keys(series_key)
+            .map(|(series_key, start, stop)| ChunkMeta {
+                series_key,
+                start,
+                stop,
+            })
+            .collect())
+    }
+
+    fn list_series(&self) -> Result<Vec<i64>, GetChunkError> {
+        let mut res: Vec<i64> = self
+            .index
+            .keys()
+            .map(|&(series_key, _, _)| series_key)
+            .collect();
+        res.dedup();
+        Ok(res)
+    }
+
+    fn time_bounds(&self, series_key: i64) -> Result<Option<(i64, i64)>, GetChunkError> {
+        let start = self
+            .series_keys(series_key)
+            .map(|(_, start, _)| start)
+            .min();
+        let stop = self.series_keys(series_key).map(|(_, _, stop)| stop).max();
+        Ok(start.zip(stop))
+    }
+
+    fn checkpoint(&mut self) -> Result<(), SetChunkError> {
+        self.file
+            .sync_data()
+            .map_err(|e| SetChunkError::Driver(e.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{put_len, FileChunkStore};
+    use crate::tests::TempPath;
+    use crate::{Chunk, ChunkMeta, DataPoint, Kelpie, KelpieChunkStore, ScheduleConfig};
+
+    fn chunk(data: &[u8]) -> Chunk {
+        Chunk::from_bytes(data.to_vec())
+    }
+
+    #[test]
+    fn should_rebuild_index_on_reopen() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        {
+            let mut store = FileChunkStore::open(temp.path())?;
+            store.set_chunk(0, 0, 100, &chunk(&[1]))?;
+            store.set_chunk(0, 100, 200, &chunk(&[2]))?;
+            store.set_chunk(0, 0, 100, &chunk(&[3, 3]))?;
+            store.set_chunk(1, 0, 100, &chunk(&[4]))?;
+            store.set_series_name(1, "cpu")?;
+            store.set_series_schedule(1, "100+0")?;
+            store.delete_series(1)?;
+            store.set_series_metadata(0, "{}")?;
+            store.set_series_schedule(0, "day+0")?;
+        }
+        let store = FileChunkStore::open(temp.path())?;
+        let (_, stored) = store.get_chunk(0, 10, 20)?.ok_or("no chunk found")?;
+        assert_eq!(stored.compressed_data, vec![3, 3]);
+        let starts: Vec<i64> = store.list_chunks(0)?.iter().map(|m| m.start).collect();
+        assert_eq!(starts, vec![0, 100]);
+        assert!(store.list_chunks(1)?.is_empty());
+        assert_eq!(store.get_series_name(1)?, None);
+        assert_eq!(store.get_series_metadata(0)?.as_deref(), Some("{}"));
+        assert_eq!(store.get_series_schedule(0)?.as_deref(), Some("day+0"));
+        assert_eq!(store.get_series_schedule(1)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn should_drop_torn_record() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        {
+            let mut store = FileChunkStore::open(temp.path())?;
+            store.set_chunk(0, 0, 100, &chunk(&[1]))?;
+        }
+        // a record header promising more than was written
+        let mut file = std::fs::OpenOptions::new().append(true).open(temp.path())?;
+        file.write_all(&[200, 0, 0, 0, 0, 1, 2])?;
+        drop(file);
+
+        let mut store = FileChunkStore::open(temp.path())?;
+        assert_eq!(store.list_all_chunks()?.len(), 1);
+        store.set_chunk(0, 100, 200, &chunk(&[2]))?;
+        let store = FileChunkStore::open(temp.path())?;
+        assert_eq!(store.list_all_chunks()?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn should_drop_record_longer_than_file() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        {
+            let mut store = FileChunkStore::open(temp.path())?;
+            store.set_chunk(0, 0, 100, &chunk(&[1]))?;
+        }
+        let len = std::fs::metadata(temp.path())?.len();
+        // a header promising 4 GiB, which must not be allocated
+        let mut file = std::fs::OpenOptions::new().append(true).open(temp.path())?;
+        file.write_all(&[255, 255, 255, 255, 0])?;
+        drop(file);
+
+        let store = FileChunkStore::open(temp.path())?;
+        assert_eq!(store.list_all_chunks()?.len(), 1);
+        assert_eq!(std::fs::metadata(temp.path())?.len(), len);
+        Ok(())
+    }
+
+    #[test]
+    fn should_get_latest_containing_chunk() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let mut store = FileChunkStore::open(temp.path())?;
+        for (start, stop) in [(0, 100), (0, 50), (10, 30), (40, 200), (300, 400)] {
+            store.set_chunk(0, start, stop, &chunk(&[1]))?;
+        }
+        store.set_chunk(1, 0, 1000, &chunk(&[1]))?;
+        for ((start, stop), expected) in [
+            ((20, 25), Some((10, 30))),
+            ((45, 50), Some((40, 200))),
+            ((35, 38), Some((0, 50))),
+            ((35, 60), Some((0, 100))),
+            ((250, 260), None),
+            ((-10, 0), None),
+        ] {
+            let found = store
+                .get_chunk(0, start, stop)?
+                .map(|(meta, _)| (meta.start, meta.stop));
+            assert_eq!(found, expected, "{start}..{stop}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_compact_superseded_records() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let mut store = FileChunkStore::open(temp.path())?;
+        for i in 0..10 {
+            store.set_chunk(0, 0, 100, &chunk(&[i; 64]))?;
+        }
+        let meta = ChunkMeta {
+            series_key: 0,
+            start: 100,
+            stop: 200,
+        };
+        store.set_chunks(&[(meta, chunk(&[7]))])?;
+        store.replace_chunks(&[meta], &[])?;
+        let before = std::fs::metadata(temp.path())?.len();
+        store.compact()?;
+        let after = std::fs::metadata(temp.path())?.len();
+        assert!(after < before / 5, "{after} not much below {before}");
+
+        store.set_chunk(0, 200, 300, &chunk(&[8]))?;
+        let store = FileChunkStore::open(temp.path())?;
+        let bounds: Vec<(i64, i64)> = store
+            .get_chunks_in_range(0, 0, 1000)?
+            .iter()
+            .map(|(meta, chunk)| {
+                assert_eq!(
+                    chunk.compressed_data.len(),
+                    if meta.start == 0 { 64 } else { 1 }
+                );
+                (meta.start, meta.stop)
+            })
+            .collect();
+        assert_eq!(bounds, vec![(0, 100), (200, 300)]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_read_from_many_threads() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let mut store = FileChunkStore::open(temp.path())?;
+        for start in 0..8 {
+            store.set_chunk(
+                0,
+                start * 100,
+                start * 100 + 100,
+                &chunk(&[start as u8; 32]),
+            )?;
+        }
+        let store = &store;
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(move || {
+                    for _ in 0..100 {
+                        for start in 0..8 {
+                            let (_, stored) = store
+                                .get_chunk(0, start * 100, start * 100)
+                                .unwrap()
+                                .unwrap();
+                            assert_eq!(stored.compressed_data, vec![start as u8; 32]);
+                        }
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_lengths_over_u32() {
+        let mut payload = vec![];
+        assert!(put_len(&mut payload, u32::MAX as usize).is_ok());
+        assert!(put_len(&mut payload, u32::MAX as usize + 1).is_err());
+        assert_eq!(payload.len(), 4);
+    }
+
+    #[test]
+    fn should_back_kelpie() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        {
+            let mut store = FileChunkStore::open(temp.path())?;
+            store.set_sync(true);
+            let mut kelpie = Kelpie::with_store(store, ScheduleConfig::default());
+            for time in 0..1000 {
+                let point = DataPoint {
+                    time: time * 10_000,
+                    value: time as f64,
+                };
+                kelpie.insert(0, point)?;
+            }
+            kelpie.close()?;
+        }
+        let store = FileChunkStore::open(temp.path())?;
+        let kelpie = Kelpie::with_store(store, ScheduleConfig::default());
+        assert_eq!(kelpie.count(0, 0, i64::MAX)?, 1000);
+        Ok(())
+    }
+}
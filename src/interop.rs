@@ -0,0 +1,3 @@
+//! Helpers for moving data between Kelpie and other time-series formats.
+
+pub mod line_protocol;
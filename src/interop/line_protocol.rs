@@ -0,0 +1,281 @@
+//! Ingestion of InfluxDB line protocol.
+//!
+//! The supported subset is one point per line:
+//!
+//! ```text
+//! measurement[,tag=value...] field=value[,field=value...] [timestamp]
+//! ```
+//!
+//! - Commas, spaces and equals signs in the measurement, tag keys, tag values and field
+//!   keys are escaped with a backslash.
+//! - Field values are floats (`1.5`, `-2e3`), integers (`7i`) or unsigned integers
+//!   (`7u`), all stored as `f64`. Strings and booleans are rejected.
+//! - The timestamp is taken as milliseconds, as if written with `precision=ms`. Without
+//!   one, the current wall-clock time is used.
+//! - Blank lines and `#` comments insert nothing.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{DataPoint, InsertError, Kelpie, KelpieChunkStore};
+
+#[derive(thiserror::Error, Debug)]
+pub enum LineProtocolError {
+    #[error("Malformed line: {0}")]
+    Syntax(String),
+    #[error("Field {0:?} is not numeric")]
+    NonNumericField(String),
+    #[error("Invalid timestamp {0:?}")]
+    InvalidTimestamp(String),
+    #[error(transparent)]
+    Insert(#[from] InsertError),
+}
+
+/// Parses one line and inserts every field as a point, returning how many were inserted.
+///
+/// Each field is its own series, keyed by `key_fn(measurement, tags)` where `tags` are
+/// the unescaped tags sorted by key, followed by `("_field", field key)`. The whole
+/// line is parsed and its points checked as by `insert` before anything is inserted, so
+/// a malformed line or one with an unstorable time or value inserts nothing. A store
+/// failure while inserting can still leave the fields before it inserted.
+pub fn parse_and_insert<S: KelpieChunkStore>(
+    kelpie: &mut Kelpie<S>,
+    line: &str,
+    key_fn: impl Fn(&str, &[(String, String)]) -> i64,
+) -> Result<usize, LineProtocolError> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(0);
+    }
+    let sections: Vec<&str> = split_unescaped(line, ' ')
+        .into_iter()
+        .filter(|section| !section.is_empty())
+        .collect();
+    let (series, fields, timestamp) = match sections[..] {
+        [series, fields] => (series, fields, None),
+        [series, fields, timestamp] => (series, fields, Some(timestamp)),
+        _ => {
+            return Err(syntax(
+                "expected measurement, fields and an optional timestamp",
+            ));
+        }
+    };
+
+    let mut series = split_unescaped(series, ',').into_iter();
+    let measurement = unescape(series.next().unwrap_or_default());
+    if measurement.is_empty() {
+        return Err(syntax("missing measurement"));
+    }
+    let mut tags = series.map(key_value).collect::<Result<Vec<_>, _>>()?;
+    tags.sort();
+
+    let fields = split_unescaped(fields, ',')
+        .into_iter()
+        .map(|field| {
+            let (key, value) = key_value(field)?;
+            let value = field_value(&key, &value)?;
+            Ok((key, value))
+        })
+        .collect::<Result<Vec<_>, LineProtocolError>>()?;
+
+    let time = match timestamp {
+        Some(timestamp) => timestamp
+            .parse()
+            .map_err(|_| LineProtocolError::InvalidTimestamp(timestamp.to_string()))?,
+        None => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64),
+    };
+
+    for &(_, value) in &fields {
+        <Kelpie>::validate(&DataPoint { time, value })?;
+    }
+    for (field, value) in &fields {
+        tags.push(("_field".to_string(), field.clone()));
+        let series_key = key_fn(&measurement, &tags);
+        tags.pop();
+        kelpie.insert(
+            series_key,
+            DataPoint {
+                time,
+                value: *value,
+            },
+        )?;
+    }
+    Ok(fields.len())
+}
+
+fn syntax(reason: &str) -> LineProtocolError {
+    LineProtocolError::Syntax(reason.to_string())
+}
+
+// splits a `key=value` pair at its unescaped equals sign and unescapes both sides
+fn key_value(pair: &str) -> Result<(String, String), LineProtocolError> {
+    let parts = split_unescaped(pair, '=');
+    let [key, value] = parts[..] else {
+        return Err(LineProtocolError::Syntax(format!(
+            "expected key=value, got {pair:?}"
+        )));
+    };
+    if key.is_empty() {
+        return Err(LineProtocolError::Syntax(format!("empty key in {pair:?}")));
+    }
+    Ok((unescape(key), unescape(value)))
+}
+
+fn field_value(key: &str, value: &str) -> Result<f64, LineProtocolError> {
+    let non_numeric = || LineProtocolError::NonNumericField(key.to_string());
+    if value.starts_with('"') {
+        return Err(non_numeric());
+    }
+    let parsed = if let Some(int) = value.strip_suffix('i') {
+        int.parse::<i64>().map(|int| int as f64).ok()
+    } else if let Some(uint) = value.strip_suffix('u') {
+        uint.parse::<u64>().map(|uint| uint as f64).ok()
+    } else {
+        // booleans fail to parse, the `inf` and `NaN` that Rust would accept are dropped
+        value.parse::<f64>().ok().filter(|value| value.is_finite())
+    };
+    parsed.ok_or_else(non_numeric)
+}
+
+// splits at every `sep` that is neither escaped with a backslash nor within quotes
+fn split_unescaped(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            c if c == sep && !in_quotes => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+// drops the backslash before escaped commas, spaces and equals signs, keeping other ones
+fn unescape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(&next) = chars
+                .peek()
+                .filter(|&&next| matches!(next, ',' | ' ' | '='))
+            {
+                res.push(next);
+                chars.next();
+                continue;
+            }
+        }
+        res.push(c);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::{parse_and_insert, LineProtocolError};
+    use crate::Kelpie;
+
+    #[test]
+    fn should_insert_every_field() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let seen = RefCell::new(vec![]);
+        let key_fn = |measurement: &str, tags: &[(String, String)]| {
+            seen.borrow_mut()
+                .push((measurement.to_string(), tags.to_vec()));
+            seen.borrow().len() as i64
+        };
+        let line = "cpu,zone=eu,host=a usage=0.5,cores=8i,uptime=12u 1000";
+        assert_eq!(parse_and_insert(&mut kelpie, line, key_fn)?, 3);
+
+        let tag = |key: &str, value: &str| (key.to_string(), value.to_string());
+        let seen = seen.into_inner();
+        assert_eq!(seen[0].0, "cpu");
+        assert_eq!(
+            seen[0].1,
+            [tag("host", "a"), tag("zone", "eu"), tag("_field", "usage")]
+        );
+        assert_eq!(seen[2].1[2], tag("_field", "uptime"));
+        for (series_key, value) in [(1, 0.5), (2, 8.0), (3, 12.0)] {
+            let res = kelpie.query(series_key, 0, 2000)?;
+            assert_eq!(res.get(1000), Some(value));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_unescape_names() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let seen = RefCell::new(vec![]);
+        let line = r"disk\ io,path=C:\\data,dev=sd\,a\ b\=c read\ bytes=3 10";
+        parse_and_insert(&mut kelpie, line, |measurement, tags| {
+            seen.borrow_mut()
+                .push((measurement.to_string(), tags.to_vec()));
+            0
+        })?;
+        let (measurement, tags) = &seen.borrow()[0];
+        assert_eq!(measurement, "disk io");
+        assert_eq!(tags[0], ("dev".to_string(), "sd,a b=c".to_string()));
+        assert_eq!(tags[1], ("path".to_string(), r"C:\\data".to_string()));
+        assert_eq!(tags[2], ("_field".to_string(), "read bytes".to_string()));
+        assert_eq!(kelpie.query(0, 0, 100)?.get(10), Some(3.0));
+        Ok(())
+    }
+
+    #[test]
+    fn should_default_to_now() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        assert_eq!(parse_and_insert(&mut kelpie, "m v=1", |_, _| 0)?, 1);
+        assert_eq!(kelpie.query_last_wallclock(0, 60_000)?.len(), 1);
+        assert_eq!(parse_and_insert(&mut kelpie, "  # comment", |_, _| 0)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_unsupported_lines() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        for line in [
+            r#"m ok=1,text="a b" 10"#,
+            "m ok=1,on=true 10",
+            "m ok=1,big=inf 10",
+            "m ok=1,small=-inf 10",
+        ] {
+            match parse_and_insert(&mut kelpie, line, |_, _| 0) {
+                Err(LineProtocolError::NonNumericField(_)) => {}
+                res => Err(format!("{line:?} should be rejected, got {res:?}"))?,
+            }
+        }
+        for line in ["m", "m,tag v=1", "m v 10", ",t=1 v=1", "m v=1 10 extra"] {
+            match parse_and_insert(&mut kelpie, line, |_, _| 0) {
+                Err(LineProtocolError::Syntax(_)) => {}
+                res => Err(format!("{line:?} should be malformed, got {res:?}"))?,
+            }
+        }
+        if parse_and_insert(&mut kelpie, "m v=1 soon", |_, _| 0).is_ok() {
+            Err("timestamp should be rejected")?;
+        }
+        for line in ["m a=1,b=2 -5", "m a=1,b=2 9223372036854775807"] {
+            let key = |_: &str, tags: &[(String, String)]| i64::from(tags[0].1 == "b");
+            match parse_and_insert(&mut kelpie, line, key) {
+                Err(LineProtocolError::Insert(_)) => {}
+                res => Err(format!("{line:?} should be rejected, got {res:?}"))?,
+            }
+        }
+        // nothing of a rejected line is inserted
+        assert!(kelpie.query(0, 0, 100)?.is_empty());
+        assert!(kelpie.list_series()?.is_empty());
+        Ok(())
+    }
+}
@@ -1,4 +1,15 @@
+mod aggregate;
+mod autoflush;
+mod calendar;
+mod csv;
+mod file_store;
+pub mod interop;
+pub mod named;
+mod pooled_store;
+mod query_cache;
+mod scan;
 pub mod series;
+mod shared;
 pub mod store;
 
 #[cfg(test)]
@@ -8,54 +19,228 @@ extern crate quickcheck;
 #[macro_use]
 extern crate quickcheck_macros;
 
-use std::{
-    collections::{BTreeMap, HashMap},
-    i64,
-};
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, LockResult, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-pub use series::{Chunk, DataPoint, DecompressError, RawSeries};
-pub use store::{ChunkMeta, GetChunkError, KelpieChunkStore, SetChunkError, SqliteChunkStore};
+use aggregate::{bucket_start, counter_increase, Bucket};
+pub use aggregate::{Aggregator, FillPolicy};
+pub use autoflush::AutoflushHandle;
+pub use csv::CsvError;
+pub use file_store::FileChunkStore;
+pub use named::NamedKelpie;
+pub use pooled_store::PooledSqliteChunkStore;
+use query_cache::QueryCache;
+pub use scan::ChunkScan;
+#[cfg(feature = "zstd")]
+pub use series::ZstdLevel;
+pub use series::{
+    Chunk, DataPoint, DecompressError, RawSeries, SeriesSummary, CHUNK_FORMAT_VERSION,
+    DEFAULT_COMPRESSION_LEVEL, MAX_COMPRESSION_LEVEL, ZSTD_CHUNK_FORMAT_VERSION,
+};
+pub use shared::SharedKelpie;
+pub use store::{
+    ChunkMeta, GetChunkError, JournalMode, KelpieChunkStore, MemoryChunkStore, SetChunkError,
+    SqliteChunkStore, StoreOptions, Synchronous,
+};
 
 #[derive(Debug)]
 pub struct Series {
     schedule: Schedule,
     data: RawSeries,
+    // inserts since the chunk was last written, see `DurabilityMode`
+    unsaved: usize,
+    // tick of the last insert into the chunk, to find the least recently used one
+    last_used: u64,
+    // started empty without reading the store, see `IngestMode::AppendOnly`, so the
+    // store may hold points the cache lacks
+    partial: bool,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Schedule {
     chunk_start: i64,
     chunk_end: i64,
 }
 
 impl Schedule {
+    /// First time of the chunk.
+    pub fn start(&self) -> i64 {
+        self.chunk_start
+    }
+
+    /// End of the chunk, exclusive.
+    pub fn stop(&self) -> i64 {
+        self.chunk_end
+    }
+
     fn contains(&self, time: i64) -> bool {
         (self.chunk_start..self.chunk_end).contains(&time)
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+/// How the time of a series is cut into chunks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChunkAlignment {
+    /// Chunks of the given width in key space, starting at multiples of it.
+    Fixed(i64),
+    /// Chunks of one calendar unit each, reading times as milliseconds since the Unix
+    /// epoch in UTC. Chunks vary in width, e.g. with the length of a month.
+    Calendar(CalendarUnit),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CalendarUnit {
+    Day,
+    /// Starting on Monday.
+    Week,
+    Month,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ScheduleConfig {
-    // the chunk size in key space.
-    // a chunk with chunk_size c and start s contains
-    // values between s..s+c
-    // should never be negative
-    chunk_size: i64,
+    // a fixed chunk with width c and start s contains values between s..s+c
+    // the width is always positive, checked in `new`
+    alignment: ChunkAlignment,
+    // every chunk start is moved this far past its aligned position, checked in
+    // `with_align_offset` to be less than a chunk
+    align_offset: i64,
 }
 
 impl Default for ScheduleConfig {
     fn default() -> Self {
         Self {
-            chunk_size: 60 * 60 * 1000,
+            alignment: ChunkAlignment::Fixed(60 * 60 * 1000),
+            align_offset: 0,
         }
     }
 }
 
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum ScheduleConfigError {
+    #[error("Chunk size must be positive, got {0}")]
+    NonPositiveChunkSize(i64),
+    #[error("Alignment offset must be in [0, {max}), got {offset}")]
+    AlignOffsetOutOfRange { offset: i64, max: i64 },
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum CompressionLevelError {
+    #[error("Compression level must be at most {MAX_COMPRESSION_LEVEL}, got {0}")]
+    AboveMax(u32),
+}
+
 impl ScheduleConfig {
+    /// Creates a schedule of chunks covering `chunk_size` units of time each.
+    pub fn new(chunk_size: i64) -> Result<Self, ScheduleConfigError> {
+        if chunk_size <= 0 {
+            return Err(ScheduleConfigError::NonPositiveChunkSize(chunk_size));
+        }
+        Ok(Self {
+            alignment: ChunkAlignment::Fixed(chunk_size),
+            align_offset: 0,
+        })
+    }
+
+    /// Creates a schedule of chunks covering one calendar day, week or month each.
+    pub fn calendar(unit: CalendarUnit) -> Self {
+        Self {
+            alignment: ChunkAlignment::Calendar(unit),
+            align_offset: 0,
+        }
+    }
+
+    /// Moves every chunk start `offset` past its aligned position, so fixed chunks start
+    /// at `(point - offset).div_euclid(chunk_size) * chunk_size + offset`, e.g. hourly
+    /// chunks at half past. Calendar chunks start `offset` past midnight UTC, e.g. to
+    /// follow the days of another time zone.
+    ///
+    /// `offset` must be in `[0, chunk_size)` for fixed chunks and less than a day for
+    /// calendar chunks. Stored chunks are not moved, so a store should be opened with
+    /// the offset it was written with.
+    pub fn with_align_offset(self, offset: i64) -> Result<Self, ScheduleConfigError> {
+        let max = match self.alignment {
+            ChunkAlignment::Fixed(chunk_size) => chunk_size,
+            ChunkAlignment::Calendar(_) => calendar::DAY,
+        };
+        if !(0..max).contains(&offset) {
+            return Err(ScheduleConfigError::AlignOffsetOutOfRange { offset, max });
+        }
+        Ok(Self {
+            align_offset: offset,
+            ..self
+        })
+    }
+
+    pub fn alignment(&self) -> ChunkAlignment {
+        self.alignment
+    }
+
+    pub fn align_offset(&self) -> i64 {
+        self.align_offset
+    }
+
+    /// The width of every chunk. Calendar chunks vary in width, so for them this is the
+    /// longest one, e.g. 31 days for months; see `alignment` to tell them apart.
+    pub fn chunk_size(&self) -> i64 {
+        match self.alignment {
+            ChunkAlignment::Fixed(chunk_size) => chunk_size,
+            ChunkAlignment::Calendar(CalendarUnit::Day) => calendar::DAY,
+            ChunkAlignment::Calendar(CalendarUnit::Week) => 7 * calendar::DAY,
+            ChunkAlignment::Calendar(CalendarUnit::Month) => 31 * calendar::DAY,
+        }
+    }
+
+    // `<chunk size or calendar unit>+<offset>`, as kept by the chunk store
+    fn encode(&self) -> String {
+        let alignment = match self.alignment {
+            ChunkAlignment::Fixed(chunk_size) => chunk_size.to_string(),
+            ChunkAlignment::Calendar(CalendarUnit::Day) => "day".to_string(),
+            ChunkAlignment::Calendar(CalendarUnit::Week) => "week".to_string(),
+            ChunkAlignment::Calendar(CalendarUnit::Month) => "month".to_string(),
+        };
+        format!("{alignment}+{}", self.align_offset)
+    }
+
+    fn decode(encoded: &str) -> Option<Self> {
+        let (alignment, offset) = encoded.split_once('+')?;
+        let config = match alignment {
+            "day" => Self::calendar(CalendarUnit::Day),
+            "week" => Self::calendar(CalendarUnit::Week),
+            "month" => Self::calendar(CalendarUnit::Month),
+            chunk_size => Self::new(chunk_size.parse().ok()?).ok()?,
+        };
+        config.with_align_offset(offset.parse().ok()?).ok()
+    }
+
     fn init_schedule_from_time(&self, point: i64) -> Schedule {
-        // implicitly round down
-        let chunk_start = point / self.chunk_size * self.chunk_size;
-        let chunk_end = chunk_start.saturating_add(self.chunk_size);
+        let (chunk_start, chunk_end) = match self.alignment {
+            ChunkAlignment::Fixed(chunk_size) => {
+                debug_assert!(chunk_size > 0, "chunk size must be positive");
+                // round down, also for negative points, in i128 so that subtracting
+                // the offset can't overflow
+                let offset = (i128::from(point) - i128::from(self.align_offset))
+                    .rem_euclid(i128::from(chunk_size)) as i64;
+                // the chunks at either end of the i64 range are cut short instead of
+                // overflowing
+                (
+                    point.saturating_sub(offset),
+                    point.saturating_add(chunk_size - offset),
+                )
+            }
+            ChunkAlignment::Calendar(unit) => {
+                let (start, stop) = calendar::bucket(unit, point.saturating_sub(self.align_offset));
+                // cut short at the start of the range as well, where the shift saturates
+                (
+                    start.saturating_add(self.align_offset).min(point),
+                    stop.saturating_add(self.align_offset),
+                )
+            }
+        };
         Schedule {
             chunk_start,
             chunk_end,
@@ -63,34 +248,374 @@ impl ScheduleConfig {
     }
 }
 
+/// What `insert` did with an accepted point.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum InsertStatus {
+    /// The point fell outside the series' cached chunks and the cache was full. Its
+    /// least recently used chunk, with the given schedule, was written to the store
+    /// and evicted to cache the chunk of the point.
     CompactmentPending(Schedule),
+    /// The point was written to a cached chunk, which may have just been loaded into
+    /// a cache with room to spare, or by evicting a chunk of another series.
     Cached,
 }
 
+/// Why `insert` rejected a point.
+#[derive(thiserror::Error, Debug)]
+pub enum InsertError {
+    #[error("Point value is NaN")]
+    NanValue,
+    #[error("Point time {0} is negative")]
+    NegativeTime(i64),
+    #[error("Point time {0} is past the last storable timestamp")]
+    TimestampOutOfRange(i64),
+    /// The series already has a point at this time, see `DuplicatePolicy::Error`.
+    #[error("Point time {0} is already taken")]
+    DuplicateTime(i64),
+    /// The point is not after the latest point of the series, see
+    /// `Kelpie::set_reject_out_of_order`.
+    #[error("Point time {time} is not after the latest time {last}")]
+    OutOfOrder { time: i64, last: i64 },
+    #[error(transparent)]
+    Store(#[from] GetChunkError),
+    /// Writing through to the store failed, see `DurabilityMode`. The point stays
+    /// cached and is written with its chunk later.
+    #[error(transparent)]
+    Write(#[from] SetChunkError),
+}
+
+/// How `insert_many` resolves points within one batch that share a timestamp.
+/// Points already stored are always overwritten by the batch.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum BatchDedup {
+    KeepFirst,
+    #[default]
+    KeepLast,
+    Error,
+}
+
+/// What `insert` does with a point whose time the series already has a point at.
+/// Batches follow `BatchDedup` instead.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// The new value replaces the old one.
+    #[default]
+    LastWins,
+    /// The old value stays and the new point is dropped.
+    FirstWins,
+    /// The new point is rejected with `InsertError::DuplicateTime`.
+    Error,
+    /// The new value is added to the old one. A sum that is NaN, from adding opposite
+    /// infinities, is rejected with `InsertError::NanValue`.
+    Sum,
+}
+
+impl DuplicatePolicy {
+    // the point to store given the value at its time, if any, or `None` to drop it
+    fn resolve(
+        self,
+        existing: Option<f64>,
+        point: DataPoint,
+    ) -> Result<Option<DataPoint>, InsertError> {
+        let Some(existing) = existing else {
+            return Ok(Some(point));
+        };
+        match self {
+            DuplicatePolicy::LastWins => Ok(Some(point)),
+            DuplicatePolicy::FirstWins => Ok(None),
+            DuplicatePolicy::Error => Err(InsertError::DuplicateTime(point.time)),
+            DuplicatePolicy::Sum => {
+                let value = existing + point.value;
+                if value.is_nan() {
+                    return Err(InsertError::NanValue);
+                }
+                Ok(Some(DataPoint { value, ..point }))
+            }
+        }
+    }
+}
+
+/// A comparison of point values against constants, see `Kelpie::query_value_filtered`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ValuePredicate {
+    Lt(f64),
+    Le(f64),
+    Gt(f64),
+    Ge(f64),
+    /// Between both bounds, inclusive.
+    Between(f64, f64),
+}
+
+impl ValuePredicate {
+    pub fn matches(self, value: f64) -> bool {
+        match self {
+            ValuePredicate::Lt(x) => value < x,
+            ValuePredicate::Le(x) => value <= x,
+            ValuePredicate::Gt(x) => value > x,
+            ValuePredicate::Ge(x) => value >= x,
+            ValuePredicate::Between(min, max) => min <= value && value <= max,
+        }
+    }
+
+    // the smallest closed range holding every matching value
+    fn bounds(self) -> (f64, f64) {
+        match self {
+            ValuePredicate::Lt(x) | ValuePredicate::Le(x) => (f64::NEG_INFINITY, x),
+            ValuePredicate::Gt(x) | ValuePredicate::Ge(x) => (x, f64::INFINITY),
+            ValuePredicate::Between(min, max) => (min, max),
+        }
+    }
+}
+
+/// Whether `insert` reads a chunk from the store before writing to it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum IngestMode {
+    /// Loads the stored chunk and merges new points into it.
+    #[default]
+    Safe,
+    /// Starts every chunk empty without reading the store, so inserts never wait on a
+    /// read. Points already stored in the chunk are merged in when it is queried or
+    /// written back, the cached points winning on equal times.
+    AppendOnly,
+}
+
+/// When cached chunks are written to the store, besides `flush` and eviction.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DurabilityMode {
+    /// Chunks are only written when evicted or flushed, so a crash loses every
+    /// unflushed point.
+    #[default]
+    BufferedUntilRoll,
+    /// A chunk is also written after every `n` inserts into it, so a crash loses at
+    /// most `n - 1` points per cached chunk. Each write recompresses the whole chunk:
+    /// with small `n`, filling a chunk of `m` points costs about `m / n` compressions
+    /// of up to `m` points each.
+    WriteThroughEveryN(usize),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BatchError {
+    #[error("Duplicate timestamp {0} in batch")]
+    DuplicateTime(i64),
+    #[error(transparent)]
+    Store(#[from] GetChunkError),
+    #[error(transparent)]
+    Write(#[from] SetChunkError),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AlignmentError {
+    #[error("{} chunks are not aligned to their schedule", .0.len())]
+    Misaligned(Vec<ChunkMeta>),
+    #[error(transparent)]
+    Store(#[from] GetChunkError),
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct InsertReport {
+    // points that were written
+    pub accepted: usize,
+    // points dropped because they can't be stored (NaN, negative or max time)
+    pub rejected: usize,
+    pub chunks_written: usize,
+}
+
+/// Where the time of a query went, see `Kelpie::query_profiled`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct QueryProfile {
+    // stored chunks read and their compressed size
+    pub chunks_read: usize,
+    pub bytes_read: usize,
+    // unflushed chunks served from the cache
+    pub cache_hits: usize,
+    pub decompress_time: Duration,
+    pub total_time: Duration,
+}
+
 impl Series {
     fn new(schedule: Schedule) -> Self {
         let data = RawSeries::new();
-        Self { data, schedule }
+        Self {
+            data,
+            schedule,
+            unsaved: 0,
+            last_used: 0,
+            partial: false,
+        }
     }
 
     fn try_insert(&mut self, data_point: DataPoint) -> bool {
         if self.schedule.contains(data_point.time) {
             self.data.insert(data_point);
+            self.unsaved += 1;
             return true;
         }
         false
     }
 }
 
-pub struct Kelpie {
-    chunk_store: SqliteChunkStore,
-    series: HashMap<i64, Series>,
+/// How many chunks `Kelpie` keeps decompressed in memory, see `Kelpie::set_cache_config`.
+///
+/// Inserts go to cached chunks, which are written to the store when evicted or
+/// flushed. Loading a chunk past either limit writes and evicts the least recently
+/// used chunk, of the same series for `chunks_per_series` and of any series for
+/// `max_chunks`.
+///
+/// The default caches one chunk of every series written. More than one avoids
+/// reloading chunks when out of order points straddle a chunk boundary.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Chunks cached per series, at least 1.
+    pub chunks_per_series: usize,
+    /// Chunks cached across all series, keyed by series and chunk start. Unbounded
+    /// if `None`, at least 1 otherwise.
+    pub max_chunks: Option<usize>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            chunks_per_series: 1,
+            max_chunks: None,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// A single cache of up to `max_chunks` chunks shared by all series, however many
+    /// of them belong to one series.
+    pub fn lru(max_chunks: usize) -> Self {
+        Self {
+            chunks_per_series: usize::MAX,
+            max_chunks: Some(max_chunks),
+        }
+    }
+}
+
+/// A time series store caching the chunks being written to in memory.
+///
+/// Queries take `&self`, so a `Kelpie` whose chunk store is `Sync` can be queried from
+/// several threads at once, see `SharedKelpie`.
+pub struct Kelpie<S: KelpieChunkStore = SqliteChunkStore> {
+    chunk_store: S,
+    // cached chunks of each series, least recently used first
+    series: HashMap<i64, Vec<Series>>,
+    cache_config: CacheConfig,
+    // advanced on every use of a cached chunk, see `Series::last_used`
+    cache_ticks: u64,
     schedule_config: ScheduleConfig,
+    // schedule overrides of the series looked up so far
+    series_configs: Mutex<HashMap<i64, ScheduleConfig>>,
+    // series looked up so far without an override, bounded by `DEFAULT_SCHEDULES_CAPACITY`
+    default_schedules: Mutex<HashSet<i64>>,
+    batch_dedup: BatchDedup,
+    duplicate_policy: DuplicatePolicy,
+    reject_out_of_order: bool,
+    // latest point time of each series, tracked while rejecting out-of-order points
+    last_times: HashMap<i64, i64>,
+    auto_compact: Option<usize>,
+    compaction_target: CompactionTarget,
+    compression: Compression,
+    query_cache: Mutex<QueryCache>,
+    prefetch_chunks: usize,
+    ingest_mode: IngestMode,
+    durability_mode: DurabilityMode,
+    subscribers: HashMap<i64, Vec<Sender<DataPoint>>>,
+}
+
+// the caches behind a mutex are consistent between calls, so a panic while holding
+// one leaves nothing half-written
+fn unpoison<T>(res: LockResult<T>) -> T {
+    res.unwrap_or_else(PoisonError::into_inner)
+}
+
+// how chunks are written, copied out where the chunk being compressed is borrowed
+#[derive(Copy, Clone)]
+struct Compression {
+    level: u32,
+    #[cfg(feature = "zstd")]
+    zstd: Option<ZstdLevel>,
+}
+
+impl Compression {
+    fn compress(self, data: &RawSeries) -> Chunk {
+        #[cfg(feature = "zstd")]
+        if let Some(zstd) = self.zstd {
+            return Chunk::compress_series_zstd(data, self.level, zstd);
+        }
+        Chunk::compress_series_with_level(data, self.level)
+    }
+}
+
+// series remembered to use the default schedule, so that many series can't grow the
+// set forever
+const DEFAULT_SCHEDULES_CAPACITY: usize = 1 << 16;
+
+/// Point budget of chunks merged by `compact_series` and automatic compaction, unless
+/// changed with `set_compaction_target`.
+pub const DEFAULT_COMPACT_MAX_POINTS: usize = 1 << 16;
+
+/// Size limit for the chunks written by compaction. Chunks already above the limit are
+/// left as they are.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CompactionTarget {
+    MaxPoints(usize),
+    /// Limit on the compressed size in bytes.
+    MaxBytes(usize),
+}
+
+/// Totals of a `Kelpie::compact_all` sweep.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompactionReport {
+    // series that had at least one run merged
+    pub series_compacted: usize,
+    pub chunks_before: usize,
+    pub chunks_after: usize,
+    // difference in total compressed chunk size
+    pub bytes_reclaimed: usize,
+}
+
+/// Totals of a `Kelpie::recompress_series` run.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecompressStats {
+    pub chunks: usize,
+    // total compressed chunk size
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+impl RecompressStats {
+    /// The new size relative to the old one, below 1 if recompressing saved space.
+    /// `None` if there was nothing to recompress.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.bytes_before > 0).then(|| self.bytes_after as f64 / self.bytes_before as f64)
+    }
+}
+
+impl CompactionTarget {
+    // whether merging chunks with these totals of points and compressed bytes is expected
+    // to stay within the limit. The compressed size is only known after compressing, so
+    // it is estimated by the sizes of the parts and checked by `fits_chunk` once a whole
+    // run is merged.
+    fn fits(&self, points: usize, bytes: usize) -> bool {
+        match *self {
+            CompactionTarget::MaxPoints(max_points) => points <= max_points,
+            CompactionTarget::MaxBytes(max_bytes) => bytes <= max_bytes,
+        }
+    }
+
+    fn fits_chunk(&self, chunk: &Chunk) -> bool {
+        match *self {
+            CompactionTarget::MaxPoints(_) => true,
+            CompactionTarget::MaxBytes(max_bytes) => chunk.as_bytes().len() <= max_bytes,
+        }
+    }
 }
 
 pub struct KelpieFake {
     series: HashMap<i64, RawSeries>,
+    batch_dedup: BatchDedup,
+    duplicate_policy: DuplicatePolicy,
 }
 
 impl Default for KelpieFake {
@@ -103,23 +628,77 @@ impl KelpieFake {
     pub fn new() -> Self {
         Self {
             series: HashMap::new(),
+            batch_dedup: BatchDedup::default(),
+            duplicate_policy: DuplicatePolicy::default(),
         }
     }
 
-    pub fn insert(&mut self, series_key: i64, data_point: DataPoint) {
-        if data_point.value.is_nan() {
-            return;
-        }
-        if data_point.time < 0 {
-            return;
-        }
-        // because we work with exclusive ranges in the other impl, we skip here
-        // we don't want to hit max timestamps anyways
-        if data_point.time == i64::MAX {
-            return;
+    pub fn set_batch_dedup(&mut self, policy: BatchDedup) {
+        self.batch_dedup = policy;
+    }
+
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.duplicate_policy = policy;
+    }
+
+    pub fn insert_many(&mut self, series_key: i64, points: &[DataPoint]) -> Result<(), BatchError> {
+        let mut batch = BTreeMap::new();
+        for point in points {
+            if point.value.is_nan() || point.time < 0 || point.time == i64::MAX {
+                continue;
+            }
+            match self.batch_dedup {
+                BatchDedup::KeepFirst => {
+                    batch.entry(point.time).or_insert(point.value);
+                }
+                BatchDedup::KeepLast => {
+                    batch.insert(point.time, point.value);
+                }
+                BatchDedup::Error => {
+                    if batch.insert(point.time, point.value).is_some() {
+                        return Err(BatchError::DuplicateTime(point.time));
+                    }
+                }
+            }
         }
         let series = self.series.entry(series_key).or_default();
-        series.data.insert(data_point.time, data_point.value);
+        series.data.append(&mut batch);
+        Ok(())
+    }
+
+    pub fn insert(
+        &mut self,
+        series_key: i64,
+        data_point: DataPoint,
+    ) -> Result<InsertStatus, InsertError> {
+        <Kelpie>::validate(&data_point)?;
+        let series = self.series.entry(series_key).or_default();
+        let value = match (series.get(data_point.time), self.duplicate_policy) {
+            (None, _) | (Some(_), DuplicatePolicy::LastWins) => data_point.value,
+            (Some(_), DuplicatePolicy::FirstWins) => return Ok(InsertStatus::Cached),
+            (Some(_), DuplicatePolicy::Error) => {
+                return Err(InsertError::DuplicateTime(data_point.time))
+            }
+            (Some(old), DuplicatePolicy::Sum) if (old + data_point.value).is_nan() => {
+                return Err(InsertError::NanValue)
+            }
+            (Some(old), DuplicatePolicy::Sum) => old + data_point.value,
+        };
+        series.data.insert(data_point.time, value);
+        Ok(InsertStatus::Cached)
+    }
+
+    /// Like `insert`, but points that can't be stored are dropped without an error,
+    /// see `Kelpie::try_insert_lossy`.
+    pub fn try_insert_lossy(
+        &mut self,
+        series_key: i64,
+        data_point: DataPoint,
+    ) -> Result<(), InsertError> {
+        match self.insert(series_key, data_point) {
+            Err(e @ (InsertError::Store(_) | InsertError::Write(_))) => Err(e),
+            _ => Ok(()),
+        }
     }
 
     pub fn query(
@@ -140,17 +719,215 @@ impl KelpieFake {
         let map = BTreeMap::from_iter(range.map(|(&k, &v)| (k, v)));
         Ok(RawSeries { data: map })
     }
+
+    pub fn count(&self, series_key: i64, start: i64, stop: i64) -> Result<usize, GetChunkError> {
+        if start > stop {
+            return Ok(0);
+        }
+        Ok(self
+            .series
+            .get(&series_key)
+            .map_or(0, |series| series.data.range(start..stop).count()))
+    }
+
+    pub fn first(&self, series_key: i64, start: i64) -> Result<Option<DataPoint>, GetChunkError> {
+        let series = self.series.get(&series_key);
+        let first = series.and_then(|series| series.data.range(start..).next());
+        Ok(first.map(|(&time, &value)| DataPoint { time, value }))
+    }
+
+    pub fn last(&self, series_key: i64, stop: i64) -> Result<Option<DataPoint>, GetChunkError> {
+        let series = self.series.get(&series_key);
+        let last = series.and_then(|series| series.data.range(..=stop).next_back());
+        Ok(last.map(|(&time, &value)| DataPoint { time, value }))
+    }
+
+    pub fn query_aggregate(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        bucket: i64,
+        agg: Aggregator,
+    ) -> Result<RawSeries, GetChunkError> {
+        let bucket = bucket.max(1);
+        let mut buckets: BTreeMap<i64, Vec<f64>> = BTreeMap::new();
+        for point in self.query(series_key, start, stop)? {
+            buckets
+                .entry(bucket_start(point.time, bucket))
+                .or_default()
+                .push(point.value);
+        }
+        let aggregate = |values: Vec<f64>| match agg {
+            Aggregator::Mean => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregator::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Aggregator::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Aggregator::Sum => values.iter().sum(),
+            Aggregator::Count => values.len() as f64,
+            Aggregator::Last => values[values.len() - 1],
+            Aggregator::First => values[0],
+        };
+        Ok(buckets
+            .into_iter()
+            .map(|(time, values)| DataPoint {
+                time,
+                value: aggregate(values),
+            })
+            .collect())
+    }
+
+    pub fn query_rate(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        bucket: i64,
+    ) -> Result<RawSeries, GetChunkError> {
+        let bucket = bucket.max(1);
+        let mut buckets: BTreeMap<i64, Vec<DataPoint>> = BTreeMap::new();
+        for point in self.query(series_key, start, stop)? {
+            buckets
+                .entry(bucket_start(point.time, bucket))
+                .or_default()
+                .push(point);
+        }
+        let mut res = RawSeries::new();
+        for (time, points) in buckets {
+            let (first, last) = (points[0], points[points.len() - 1]);
+            if first.time == last.time {
+                continue;
+            }
+            let increase: f64 = points
+                .windows(2)
+                .map(|pair| counter_increase(pair[0].value, pair[1].value))
+                .sum();
+            res.insert(DataPoint {
+                time,
+                value: increase / (last.time - first.time) as f64,
+            });
+        }
+        Ok(res)
+    }
+
+    pub fn query_filled(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        step: i64,
+        fill: FillPolicy,
+    ) -> Result<Vec<(i64, Option<f64>)>, GetChunkError> {
+        let step = step.max(1);
+        let empty = RawSeries::new();
+        let data = &self.series.get(&series_key).unwrap_or(&empty).data;
+        let mut res = vec![];
+        // in i128, so grid timestamps around the ends of i64 can't overflow
+        let mut grid = (start as i128).div_euclid(step as i128) * step as i128;
+        while grid < stop as i128 {
+            if grid >= start as i128 {
+                let time = grid as i64;
+                let value = match data.get(&time) {
+                    Some(&value) => Some(value),
+                    None => {
+                        let point = |(&time, &value): (&i64, &f64)| DataPoint { time, value };
+                        let prev = data.range(..time).next_back().map(point);
+                        let next = data.range(time..).next().map(point);
+                        fill.fill(time, prev, next)
+                    }
+                };
+                res.push((time, value));
+            }
+            grid += step as i128;
+        }
+        Ok(res)
+    }
+
+    pub fn delete_series(&mut self, series_key: i64) {
+        self.series.remove(&series_key);
+    }
+
+    pub fn delete_range(&mut self, series_key: i64, start: i64, stop: i64) {
+        if let Some(series) = self.series.get_mut(&series_key) {
+            series.delete_range(start, stop);
+        }
+    }
 }
 
 impl Kelpie {
     pub fn new_memory() -> Result<Self, sqlite::Error> {
         let chunk_store = SqliteChunkStore::new_memory()?;
-        let series = HashMap::new();
-        Ok(Self {
+        Ok(Self::with_store(chunk_store, ScheduleConfig::default()))
+    }
+
+    pub fn new_memory_with_config(config: ScheduleConfig) -> Result<Self, sqlite::Error> {
+        let mut kelpie = Self::new_memory()?;
+        kelpie.schedule_config = config;
+        Ok(kelpie)
+    }
+
+    pub fn new_path<A: AsRef<std::path::Path>>(path: A) -> Result<Self, sqlite::Error> {
+        let chunk_store = SqliteChunkStore::new_path(path)?;
+        Ok(Self::with_store(chunk_store, ScheduleConfig::default()))
+    }
+
+    /// Opens a database with chunks of the configured size. `query` and `query_iter`
+    /// read chunks of any width, but inserts and lookups like `time_bounds` find
+    /// chunks by their schedule, so a database should always be opened with the same
+    /// config.
+    pub fn new_path_with_config<A: AsRef<std::path::Path>>(
+        path: A,
+        config: ScheduleConfig,
+    ) -> Result<Self, sqlite::Error> {
+        let mut kelpie = Self::new_path(path)?;
+        kelpie.schedule_config = config;
+        Ok(kelpie)
+    }
+
+    pub fn new_path_with<A: AsRef<std::path::Path>>(
+        path: A,
+        options: StoreOptions,
+    ) -> Result<Self, sqlite::Error> {
+        let chunk_store = SqliteChunkStore::new_path_with(path, options)?;
+        Ok(Self::with_store(chunk_store, ScheduleConfig::default()))
+    }
+
+    /// Opens an existing database without write access, e.g. an archived snapshot.
+    /// Queries work as usual, anything that writes to the store panics or fails.
+    pub fn new_path_read_only<A: AsRef<std::path::Path>>(path: A) -> Result<Self, sqlite::Error> {
+        let chunk_store = SqliteChunkStore::new_path_read_only(path)?;
+        Ok(Self::with_store(chunk_store, ScheduleConfig::default()))
+    }
+}
+
+impl<S: KelpieChunkStore> Kelpie<S> {
+    /// Runs on top of any chunk store. The store must always be used with the same
+    /// config, as chunks are looked up by their schedule.
+    pub fn with_store(chunk_store: S, config: ScheduleConfig) -> Self {
+        Self {
             chunk_store,
-            series,
-            schedule_config: ScheduleConfig::default(),
-        })
+            series: HashMap::new(),
+            cache_config: CacheConfig::default(),
+            cache_ticks: 0,
+            schedule_config: config,
+            series_configs: Mutex::default(),
+            default_schedules: Mutex::default(),
+            batch_dedup: BatchDedup::default(),
+            duplicate_policy: DuplicatePolicy::default(),
+            reject_out_of_order: false,
+            last_times: HashMap::new(),
+            auto_compact: None,
+            compaction_target: CompactionTarget::MaxPoints(DEFAULT_COMPACT_MAX_POINTS),
+            compression: Compression {
+                level: DEFAULT_COMPRESSION_LEVEL,
+                #[cfg(feature = "zstd")]
+                zstd: None,
+            },
+            query_cache: Mutex::default(),
+            prefetch_chunks: 1,
+            ingest_mode: IngestMode::default(),
+            durability_mode: DurabilityMode::default(),
+            subscribers: HashMap::new(),
+        }
     }
 
     pub fn query_exact_chunk(
@@ -159,175 +936,4861 @@ impl Kelpie {
         start: i64,
         stop: i64,
     ) -> Result<Option<(ChunkMeta, RawSeries)>, GetChunkError> {
-        // check cache first
-        if let Some(series) = self.series.get(&series_key) {
-            // end checks should not be required because constant chunk siszes
-            if series.schedule.chunk_start == start {
-                let meta = ChunkMeta {
-                    series_key,
-                    start,
-                    stop,
-                };
-                let series = series.data.clone();
-                return Ok(Some((meta, series)));
-            }
+        // check cache first, a cached chunk may be a compacted one spanning several schedules
+        if let Some(series) = self.cached_containing(series_key, start, stop) {
+            let Schedule {
+                chunk_start,
+                chunk_end,
+            } = series.schedule;
+            let meta = ChunkMeta {
+                series_key,
+                start: chunk_start,
+                stop: chunk_end,
+            };
+            let data = self.complete_data(series_key, series)?;
+            return Ok(Some((meta, data.into_owned())));
         }
 
         if let Some((meta, chunk)) = self.chunk_store.get_chunk(series_key, start, stop)? {
-            let series = chunk.decompress().unwrap();
+            let series = chunk.decompress().map_err(GetChunkError::Corrupt)?;
             return Ok(Some((meta, series)));
         }
         Ok(None)
     }
 
+    /// Returns all points of a series in `[start, stop)`.
+    ///
+    /// Every stored chunk overlapping the range is read, whatever its width, so compacted
+    /// and manually stored chunks are handled alike.
+    ///
+    /// Should overlapping chunks hold the same time, chunks are merged by ascending start,
+    /// then ascending stop, and the value of the chunk merged last wins.
+    ///
+    /// Unflushed writes are always visible: a cached chunk is loaded from the store
+    /// before it accepts inserts, so it holds everything stored for its range and its
+    /// points take precedence over the stored ones.
     pub fn query(
         &self,
         series_key: i64,
         start: i64,
         stop: i64,
     ) -> Result<RawSeries, GetChunkError> {
+        self.query_profiled(series_key, start, stop)
+            .map(|(series, _profile)| series)
+    }
+
+    /// Like `query`, but also reports how many chunks were read and where time was spent.
+    pub fn query_profiled(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<(RawSeries, QueryProfile), GetChunkError> {
+        let query_start = Instant::now();
+        let mut profile = QueryProfile::default();
         let mut map = BTreeMap::new();
-        let mut cur_start = start;
-        while cur_start < stop {
-            let cur_chunk = self.schedule_config.init_schedule_from_time(cur_start);
-            let closest =
-                self.query_exact_chunk(series_key, cur_chunk.chunk_start, cur_chunk.chunk_end)?;
-            match closest {
-                Some((_meta, mut chunk)) => {
-                    map.append(&mut chunk.data);
-                    cur_start = cur_chunk.chunk_end;
-                }
-                None => cur_start = cur_chunk.chunk_end,
+        if start >= stop {
+            return Ok((RawSeries { data: map }, profile));
+        }
+        let mut chunks = self
+            .chunk_store
+            .get_chunks_in_range(series_key, start, stop)?;
+        // fix the merge order here rather than relying on the store's ordering
+        chunks.sort_by_key(|(meta, _chunk)| (meta.start, meta.stop));
+        for (_meta, chunk) in chunks {
+            profile.chunks_read += 1;
+            profile.bytes_read += chunk.as_bytes().len();
+            let decompress_start = Instant::now();
+            let mut series = chunk.decompress().map_err(GetChunkError::Corrupt)?;
+            profile.decompress_time += decompress_start.elapsed();
+            // later chunks overwrite earlier ones
+            map.append(&mut series.data);
+        }
+        for series in self.cached(series_key) {
+            let Schedule {
+                chunk_start,
+                chunk_end,
+            } = series.schedule;
+            if chunk_start < stop && start < chunk_end {
+                profile.cache_hits += 1;
+                map.extend(series.data.data.range(start..stop));
             }
         }
 
         // cleanup any leftovers from unaligned chunks
         map.retain(|&k, _v| start <= k && k < stop);
-        Ok(RawSeries { data: map })
-    }
-
-    pub fn new_path<A: AsRef<std::path::Path>>(path: A) -> Result<Self, sqlite::Error> {
-        let chunk_store = SqliteChunkStore::new_path(path)?;
-        let series = HashMap::new();
-        Ok(Self {
-            chunk_store,
-            series,
-            schedule_config: ScheduleConfig::default(),
-        })
+        profile.total_time = query_start.elapsed();
+        Ok((RawSeries { data: map }, profile))
     }
 
-    fn save_series(&mut self, series_key: i64) {
-        let Some(series) = self.series.remove(&series_key) else {
-            return;
+    /// Returns the points of a series in `[start, stop)` whose value matches `predicate`,
+    /// the same as filtering the result of `query`.
+    ///
+    /// Stores that index the smallest and largest value of each chunk, like
+    /// `SqliteChunkStore`, leave out stored chunks that can't hold a match, so those are
+    /// neither read nor decompressed. The one exception is a left-out chunk overlapping
+    /// a chunk merged before it: its times are decompressed, as its points take the
+    /// place of matching ones.
+    pub fn query_value_filtered(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        predicate: ValuePredicate,
+    ) -> Result<RawSeries, GetChunkError> {
+        let mut map = BTreeMap::new();
+        if start >= stop {
+            return Ok(RawSeries { data: map });
+        }
+        let mut merge = |time: i64, value: f64| {
+            if predicate.matches(value) {
+                map.insert(time, value);
+            } else {
+                map.remove(&time);
+            }
         };
-        let chunk = Chunk::compress_series(&series.data);
-        let Schedule {
-            chunk_start: start,
-            chunk_end: stop,
-        } = series.schedule;
-        self.chunk_store
-            .set_chunk(series_key, start, stop, &chunk)
-            .unwrap();
-    }
 
-    fn load_series(&mut self, series_key: i64, schedule: Schedule) {
-        self.save_series(series_key);
-        let chunk_res = self
+        let (min, max) = predicate.bounds();
+        let mut kept = self
             .chunk_store
-            .get_chunk(series_key, schedule.chunk_start, schedule.chunk_end)
-            .unwrap();
-        match chunk_res {
-            Some((_meta, chunk)) => {
-                let raw_series = chunk.decompress().unwrap();
-                let series = Series {
-                    schedule,
-                    data: raw_series,
+            .get_chunks_in_value_range(series_key, start, stop, min, max)?;
+        kept.sort_by_key(|(meta, _chunk)| (meta.start, meta.stop));
+        let mut kept = kept.into_iter().peekable();
+        let mut stored = self.chunk_store.list_chunks(series_key)?;
+        stored.retain(|meta| meta.start < stop && start < meta.stop);
+        stored.sort_by_key(|meta| (meta.start, meta.stop));
+        let mut merged: Vec<ChunkMeta> = vec![];
+        for meta in stored {
+            if let Some((_meta, chunk)) = kept.next_if(|(kept, _chunk)| *kept == meta) {
+                let series = chunk.decompress().map_err(GetChunkError::Corrupt)?;
+                for (time, value) in series.data {
+                    merge(time, value);
+                }
+                merged.push(meta);
+            } else if merged
+                .iter()
+                .any(|m| m.start < meta.stop && meta.start < m.stop)
+            {
+                let chunk = self
+                    .chunk_store
+                    .get_chunk(series_key, meta.start, meta.stop)?;
+                if let Some((_meta, chunk)) = chunk.filter(|(found, _chunk)| *found == meta) {
+                    let times = chunk.decompress_times().map_err(GetChunkError::Corrupt)?;
+                    for time in times {
+                        // none of its values match, and NaN matches no predicate either
+                        merge(time, f64::NAN);
+                    }
+                }
+            }
+        }
+        for series in self.cached(series_key) {
+            let Schedule {
+                chunk_start,
+                chunk_end,
+            } = series.schedule;
+            if chunk_start < stop && start < chunk_end {
+                for (&time, &value) in series.data.data.range(start..stop) {
+                    merge(time, value);
+                }
+            }
+        }
+
+        map.retain(|&k, _v| start <= k && k < stop);
+        Ok(RawSeries { data: map })
+    }
+
+    /// Scans the chunks of a series overlapping `[start, stop)` in time order, yielding
+    /// the points of each chunk within the range. Unflushed writes are included.
+    pub fn iter_chunks(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<ChunkScan<'_, S>, GetChunkError> {
+        ChunkScan::new(self, series_key, start, stop)
+    }
+
+    /// Iterates the points of a series in `[start, stop)` in time order without
+    /// collecting them. Only one chunk is decompressed at a time, so ranges larger
+    /// than memory can be processed.
+    pub fn query_iter(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<impl Iterator<Item = Result<DataPoint, GetChunkError>> + '_, GetChunkError> {
+        let chunks = self.iter_chunks(series_key, start, stop)?;
+        Ok(chunks.flat_map(|chunk| {
+            let (points, error) = match chunk {
+                Ok(series) => (Some(series.data.into_iter()), None),
+                Err(e) => (None, Some(Err(e))),
+            };
+            points
+                .into_iter()
+                .flatten()
+                .map(|(time, value)| Ok(DataPoint { time, value }))
+                .chain(error)
+        }))
+    }
+
+    /// Sets how many chunks a scan reads from the store at once. Larger values trade
+    /// memory for fewer round trips on slow storage; results are the same.
+    pub fn set_prefetch_chunks(&mut self, prefetch_chunks: usize) {
+        self.prefetch_chunks = prefetch_chunks.max(1);
+    }
+
+    /// Checks whether a series has any point in `[start, stop)`, stopping at the first one
+    /// found. Stored chunks only have their times decompressed.
+    pub fn has_data_in(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<bool, GetChunkError> {
+        let mut chunks = self.iter_chunks(series_key, start, stop)?;
+        while let Some(times) = chunks.next_times() {
+            if !times?.is_empty() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Counts the points of a series in `[start, stop)`. Stored chunks only have their
+    /// times decompressed.
+    pub fn count(&self, series_key: i64, start: i64, stop: i64) -> Result<usize, GetChunkError> {
+        let mut chunks = self.iter_chunks(series_key, start, stop)?;
+        let mut count = 0;
+        while let Some(times) = chunks.next_times() {
+            count += times?.len();
+        }
+        Ok(count)
+    }
+
+    /// Returns the earliest point of a series at or after `start`. Values are only
+    /// decompressed for the stored chunk holding it.
+    pub fn first(&self, series_key: i64, start: i64) -> Result<Option<DataPoint>, GetChunkError> {
+        let mut chunks = self.iter_chunks(series_key, start, i64::MAX)?;
+        if let Some(series) = chunks.next_non_empty() {
+            if let Some((&time, &value)) = series?.data.first_key_value() {
+                return Ok(Some(DataPoint { time, value }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the latest point of a series at or before `stop`. Chunks are read
+    /// backward from `stop` until one holds a point, however far back that is. Values
+    /// are only decompressed for the stored chunk holding it.
+    pub fn last(&self, series_key: i64, stop: i64) -> Result<Option<DataPoint>, GetChunkError> {
+        let mut bounds = self.chunk_spans(series_key)?;
+        bounds.retain(|&(start, _)| start <= stop);
+        for &(start, chunk_stop) in bounds.iter().rev() {
+            let series = if self
+                .cached_containing(series_key, start, chunk_stop)
+                .is_some()
+            {
+                self.query_exact_chunk(series_key, start, chunk_stop)?
+                    .map(|(_meta, series)| series)
+            } else {
+                match self.chunk_store.get_chunk(series_key, start, chunk_stop)? {
+                    // times are sorted, so the first one tells whether any is early enough
+                    Some((_meta, chunk))
+                        if chunk
+                            .decompress_times()
+                            .map_err(GetChunkError::Corrupt)?
+                            .first()
+                            .is_some_and(|&time| time <= stop) =>
+                    {
+                        Some(chunk.decompress().map_err(GetChunkError::Corrupt)?)
+                    }
+                    _ => None,
+                }
+            };
+            let Some(series) = series else {
+                continue;
+            };
+            if let Some((&time, &value)) = series.data.range(..=stop).next_back() {
+                return Ok(Some(DataPoint { time, value }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reduces the points of a series in `[start, stop)` to one point per bucket, keyed
+    /// by the bucket start. Buckets are `bucket` wide and aligned to time 0, so the
+    /// first and last may only be partly covered by the range. Empty buckets are
+    /// omitted. A `bucket` below 1 is treated as 1.
+    ///
+    /// Chunks are scanned one at a time, so the range may hold more points than fit in
+    /// memory.
+    pub fn query_aggregate(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        bucket: i64,
+        agg: Aggregator,
+    ) -> Result<RawSeries, GetChunkError> {
+        let mut res = RawSeries::new();
+        self.for_each_bucket(series_key, start, stop, bucket, |done| {
+            res.insert(DataPoint {
+                time: done.start,
+                value: done.value(agg),
+            })
+        })?;
+        Ok(res)
+    }
+
+    /// Like `query_aggregate`, but computes several aggregators in a single pass. Each
+    /// bucket start maps to the values of `aggs`, in the same order.
+    pub fn query_multi_aggregate(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        bucket: i64,
+        aggs: &[Aggregator],
+    ) -> Result<BTreeMap<i64, Vec<f64>>, GetChunkError> {
+        let mut res = BTreeMap::new();
+        self.for_each_bucket(series_key, start, stop, bucket, |done| {
+            let values = aggs.iter().map(|&agg| done.value(agg)).collect();
+            res.insert(done.start, values);
+        })?;
+        Ok(res)
+    }
+
+    /// Computes the per-bucket rate of a counter in `[start, stop)`, in increase per unit
+    /// of time, keyed by the bucket start. Buckets are aligned like `query_aggregate`.
+    ///
+    /// Only consecutive points within a bucket are compared, so the first point of a
+    /// bucket starts from its own value. Whenever a value is below the one before it, the
+    /// counter is taken to have reset to zero and the whole new value counts as increase.
+    /// The rate is the summed increase divided by the time between the bucket's first and
+    /// last point. Buckets with a single point have no rate and are omitted.
+    pub fn query_rate(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        bucket: i64,
+    ) -> Result<RawSeries, GetChunkError> {
+        let mut res = RawSeries::new();
+        self.for_each_bucket(series_key, start, stop, bucket, |done| {
+            if let Some(value) = done.rate() {
+                res.insert(DataPoint {
+                    time: done.start,
+                    value,
+                })
+            }
+        })?;
+        Ok(res)
+    }
+
+    /// Samples a series on a regular grid: one entry for every multiple of `step` in
+    /// `[start, stop)`, holding the value of the point at exactly that time, or else the
+    /// value given by `fill`. Points between grid timestamps are only used for filling.
+    ///
+    /// `Previous` and `Linear` also look at points outside the range. Where there is no
+    /// earlier point, such as in a leading gap, or no later point for `Linear`, the entry
+    /// is `None`. A `step` below 1 is treated as 1.
+    pub fn query_filled(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        step: i64,
+        fill: FillPolicy,
+    ) -> Result<Vec<(i64, Option<f64>)>, GetChunkError> {
+        let step = step.max(1);
+        let (mut prev, after) = match fill {
+            FillPolicy::Previous | FillPolicy::Linear => (
+                self.last(series_key, start.saturating_sub(1))?,
+                self.first(series_key, stop)?,
+            ),
+            FillPolicy::None | FillPolicy::Constant(_) => (None, None),
+        };
+        let mut points = self
+            .query(series_key, start, stop)?
+            .into_iter()
+            .chain(after)
+            .peekable();
+        let mut res = vec![];
+        // the first multiple of step at or after start
+        let Some(mut time) = start.checked_add((step - start.rem_euclid(step)) % step) else {
+            return Ok(res);
+        };
+        while time < stop {
+            while let Some(point) = points.next_if(|point| point.time < time) {
+                prev = Some(point);
+            }
+            let value = match points.peek() {
+                Some(point) if point.time == time => Some(point.value),
+                next => fill.fill(time, prev, next.copied()),
+            };
+            res.push((time, value));
+            let Some(next_time) = time.checked_add(step) else {
+                break;
+            };
+            time = next_time;
+        }
+        Ok(res)
+    }
+
+    // scans `[start, stop)` once, handing every non-empty bucket to `emit` in time order
+    fn for_each_bucket(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        bucket: i64,
+        mut emit: impl FnMut(&Bucket),
+    ) -> Result<(), GetChunkError> {
+        let bucket = bucket.max(1);
+        let mut current: Option<Bucket> = None;
+        for chunk in self.iter_chunks(series_key, start, stop)? {
+            for point in chunk? {
+                let start = bucket_start(point.time, bucket);
+                match &mut current {
+                    Some(current) if current.start == start => current.push(point),
+                    _ => {
+                        if let Some(done) = current.replace(Bucket::new(start, point)) {
+                            emit(&done);
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(done) = current {
+            emit(&done);
+        }
+        Ok(())
+    }
+
+    /// Like `query`, but returns a shared result. Repeating a query while its series is
+    /// not written to hands out the same allocation instead of querying again.
+    pub fn query_shared(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<Arc<RawSeries>, GetChunkError> {
+        if let Some(res) = unpoison(self.query_cache.lock()).get(series_key, start, stop) {
+            return Ok(res);
+        }
+        let res = Arc::new(self.query(series_key, start, stop)?);
+        unpoison(self.query_cache.lock()).insert(series_key, start, stop, res.clone());
+        Ok(res)
+    }
+
+    /// Queries `[start, stop)` of several series, returning the result of each series
+    /// keyed by its key. Series without points map to an empty result, repeated keys
+    /// are queried once. The first failing series fails the whole call.
+    pub fn query_many(
+        &self,
+        series_keys: &[i64],
+        start: i64,
+        stop: i64,
+    ) -> Result<HashMap<i64, RawSeries>, GetChunkError> {
+        let mut res = HashMap::with_capacity(series_keys.len());
+        for &series_key in series_keys {
+            if let Entry::Vacant(entry) = res.entry(series_key) {
+                entry.insert(self.query(series_key, start, stop)?);
+            }
+        }
+        Ok(res)
+    }
+
+    /// Like `query`, as an Arrow record batch with one row per point in time order and
+    /// the schema
+    ///
+    /// | column  | type                              | nullable |
+    /// |---------|-----------------------------------|----------|
+    /// | `time`  | `Timestamp(Millisecond, None)`    | no       |
+    /// | `value` | `Float64`                         | no       |
+    #[cfg(feature = "arrow")]
+    pub fn query_arrow(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<arrow_array::RecordBatch, GetChunkError> {
+        use arrow_array::{Float64Array, TimestampMillisecondArray};
+        use arrow_schema::{DataType, Field, Schema, TimeUnit};
+
+        let res = self.query(series_key, start, stop)?;
+        let times = TimestampMillisecondArray::from_iter_values(res.data.keys().copied());
+        let values = Float64Array::from_iter_values(res.data.values().copied());
+        let schema = Schema::new(vec![
+            Field::new(
+                "time",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("value", DataType::Float64, false),
+        ]);
+        let batch = arrow_array::RecordBatch::try_new(
+            Arc::new(schema),
+            vec![Arc::new(times), Arc::new(values)],
+        )
+        .expect("columns match the schema");
+        Ok(batch)
+    }
+
+    /// Writes the points of a series in `[start, stop)` as CSV: a `time,value` header,
+    /// then one `time,value` row per point in time order. Values are written so they
+    /// parse back exactly, see `import_csv`.
+    pub fn export_csv<W: Write>(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        w: &mut W,
+    ) -> Result<(), CsvError> {
+        csv::write_header(w)?;
+        for point in self.query_iter(series_key, start, stop)? {
+            csv::write_point(w, point?)?;
+        }
+        Ok(())
+    }
+
+    /// Queries several `[start, stop)` ranges of a series at once, returning one result per
+    /// range. A chunk touched by more than one range is only read and decompressed once.
+    pub fn query_ranges(
+        &self,
+        series_key: i64,
+        ranges: &[(i64, i64)],
+    ) -> Result<Vec<RawSeries>, GetChunkError> {
+        let spans = self.chunk_spans(series_key)?;
+        // chunks read so far, keyed by their bounds
+        let mut fetched: HashMap<(i64, i64), RawSeries> = HashMap::new();
+        let mut results = Vec::with_capacity(ranges.len());
+        for &(start, stop) in ranges {
+            let mut map = BTreeMap::new();
+            for &(chunk_start, chunk_stop) in &spans {
+                if chunk_stop <= start || stop <= chunk_start {
+                    continue;
+                }
+                let chunk = match fetched.entry((chunk_start, chunk_stop)) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let chunk = self
+                            .query_exact_chunk(series_key, chunk_start, chunk_stop)?
+                            .map(|(_meta, chunk)| chunk)
+                            .unwrap_or_default();
+                        entry.insert(chunk)
+                    }
                 };
-                self.series.insert(series_key, series);
+                map.extend(chunk.data.range(start..stop));
             }
-            None => {
-                self.series.insert(series_key, Series::new(schedule));
+            results.push(RawSeries { data: map });
+        }
+        Ok(results)
+    }
+
+    // bounds of the stored and cached chunks of a series, ordered by start
+    fn chunk_spans(&self, series_key: i64) -> Result<Vec<(i64, i64)>, GetChunkError> {
+        let mut spans: Vec<(i64, i64)> = self
+            .chunk_store
+            .list_chunks(series_key)?
+            .into_iter()
+            .map(|meta| (meta.start, meta.stop))
+            .collect();
+        for series in self.cached(series_key) {
+            spans.push((series.schedule.chunk_start, series.schedule.chunk_end));
+        }
+        spans.sort();
+        spans.dedup();
+        Ok(spans)
+    }
+
+    // smallest start and largest stop over the stored and cached chunks of a series
+    fn chunk_bounds(&self, series_key: i64) -> Result<Option<(i64, i64)>, GetChunkError> {
+        let mut bounds = self.chunk_store.time_bounds(series_key)?;
+        for series in self.cached(series_key) {
+            let Schedule {
+                chunk_start,
+                chunk_end,
+            } = series.schedule;
+            bounds = match bounds {
+                Some((start, stop)) => Some((start.min(chunk_start), stop.max(chunk_end))),
+                None => Some((chunk_start, chunk_end)),
+            };
+        }
+        Ok(bounds)
+    }
+
+    /// Returns the times of the first and last point of a series, including unflushed points.
+    pub fn time_bounds(&self, series_key: i64) -> Result<Option<(i64, i64)>, GetChunkError> {
+        let Some(first) = self.first(series_key, i64::MIN)? else {
+            return Ok(None);
+        };
+        let last = self.last(series_key, i64::MAX)?;
+        Ok(last.map(|last| (first.time, last.time)))
+    }
+
+    /// Lists the keys of every series with stored chunks or unflushed points, in
+    /// ascending order.
+    pub fn list_series(&self) -> Result<Vec<i64>, GetChunkError> {
+        let mut keys = self.chunk_store.list_series()?;
+        keys.extend(
+            self.series
+                .iter()
+                .filter(|(_, cached)| cached.iter().any(|series| !series.data.is_empty()))
+                .map(|(&series_key, _)| series_key),
+        );
+        keys.sort_unstable();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    /// Lists stored chunks whose bounds don't fall on schedule boundaries.
+    /// Compacted chunks spanning several whole schedules count as aligned.
+    pub fn misaligned_chunks(&self) -> Result<Vec<ChunkMeta>, GetChunkError> {
+        let mut misaligned = vec![];
+        for meta in self.chunk_store.list_all_chunks()? {
+            let config = self.series_config(meta.series_key)?;
+            let aligned = meta.start < meta.stop
+                && config.init_schedule_from_time(meta.start).chunk_start == meta.start
+                && config.init_schedule_from_time(meta.stop - 1).chunk_end == meta.stop;
+            if !aligned {
+                misaligned.push(meta);
             }
         }
+        Ok(misaligned)
     }
 
-    fn ensure_series_for(&mut self, series_key: i64, time: i64) {
-        if let Some(series) = self.series.get_mut(&series_key) {
-            if series.schedule.contains(time) {
-                return;
+    /// Test helper returning the misaligned chunks as an error, see `misaligned_chunks`.
+    pub fn assert_aligned(&self) -> Result<(), AlignmentError> {
+        let misaligned = self.misaligned_chunks()?;
+        if misaligned.is_empty() {
+            return Ok(());
+        }
+        Err(AlignmentError::Misaligned(misaligned))
+    }
+
+    /// Wipes all series from the cache and the store. The store stays usable.
+    pub fn truncate(&mut self) -> Result<(), SetChunkError> {
+        self.series.clear();
+        self.all_series_written();
+        unpoison(self.series_configs.get_mut()).clear();
+        unpoison(self.default_schedules.get_mut()).clear();
+        self.chunk_store.truncate()
+    }
+
+    /// Removes a series from the cache and the store, including its metadata and its
+    /// schedule.
+    pub fn delete_series(&mut self, series_key: i64) -> Result<(), SetChunkError> {
+        self.series.remove(&series_key);
+        self.series_written(series_key);
+        unpoison(self.series_configs.get_mut()).remove(&series_key);
+        self.chunk_store.delete_series(series_key)
+    }
+
+    /// Expires old data of a series: removes every chunk ending at or before
+    /// `keep_after`, cached or stored, and returns how many stored chunks were removed.
+    ///
+    /// Only whole chunks are removed. A chunk straddling `keep_after` is kept with all
+    /// its points, so points up to one chunk before `keep_after` may remain.
+    pub fn enforce_retention(
+        &mut self,
+        series_key: i64,
+        keep_after: i64,
+    ) -> Result<usize, SetChunkError> {
+        self.series_written(series_key);
+        if let Some(cached) = self.series.get_mut(&series_key) {
+            cached.retain(|series| series.schedule.chunk_end > keep_after);
+            if cached.is_empty() {
+                self.series.remove(&series_key);
             }
         }
-        let schedule = self.schedule_config.init_schedule_from_time(time);
-        self.load_series(series_key, schedule);
+        self.chunk_store
+            .delete_chunks_before(series_key, keep_after)
     }
 
-    pub fn insert(&mut self, series_key: i64, data_point: DataPoint) {
-        if data_point.value.is_nan() {
-            return;
+    /// Like `enforce_retention`, for every series. Returns the total of stored chunks
+    /// removed.
+    pub fn enforce_retention_all(&mut self, keep_after: i64) -> Result<usize, SetChunkError> {
+        let series_keys = self.list_series()?;
+        let mut removed = 0;
+        for series_key in series_keys {
+            removed += self.enforce_retention(series_key, keep_after)?;
         }
-        if data_point.time < 0 {
-            return;
+        Ok(removed)
+    }
+
+    /// Removes the points of a series in `[start, stop)`.
+    ///
+    /// Stored chunks overlapping the range are rewritten without those points, or
+    /// deleted if nothing is left of them, in a single store transaction.
+    pub fn delete_range(
+        &mut self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<(), SetChunkError> {
+        if start >= stop {
+            return Ok(());
         }
-        // skip max value because last chunk will go from last_multiple to max_value exclusive,
-        // so we can never store max value
-        if data_point.time == i64::MAX {
-            return;
+        self.series_written(series_key);
+        for series in self.series.get_mut(&series_key).into_iter().flatten() {
+            series.data.delete_range(start, stop);
+        }
+        let overlapping = self
+            .chunk_store
+            .get_chunks_in_range(series_key, start, stop)?;
+        let mut remove = vec![];
+        let mut add = vec![];
+        for (meta, chunk) in overlapping {
+            if start <= meta.start && meta.stop <= stop {
+                remove.push(meta);
+                continue;
+            }
+            let mut data = chunk.decompress().map_err(SetChunkError::Corrupt)?;
+            if data.delete_range(start, stop) == 0 {
+                continue;
+            }
+            remove.push(meta);
+            if !data.data.is_empty() {
+                add.push((meta, self.compress(&data)));
+            }
         }
-        self.ensure_series_for(series_key, data_point.time);
-        let series = self.series.get_mut(&series_key).unwrap();
-        assert!(series.try_insert(data_point));
+        self.chunk_store.replace_chunks(&remove, &add)
     }
 
-    pub fn flush(&mut self) {
-        let series_keys = self.series.keys().copied().collect::<Vec<_>>();
-        for key in series_keys {
-            self.save_series(key);
+    /// Copies every chunk, the metadata and the schedule of series `from` to series `to`,
+    /// without recompressing. Fails with `SeriesNotEmpty` if `to` already has chunks.
+    pub fn copy_series(&mut self, from: i64, to: i64) -> Result<(), SetChunkError> {
+        self.try_save_series(from)?;
+        self.try_save_series(to)?;
+        if self.chunk_store.time_bounds(to)?.is_some() {
+            return Err(SetChunkError::SeriesNotEmpty(to));
+        }
+        self.series_written(to);
+        let chunks: Vec<(ChunkMeta, Chunk)> = self
+            .chunk_store
+            .get_chunks_in_range(from, i64::MIN, i64::MAX)?
+            .into_iter()
+            .map(|(meta, chunk)| {
+                let meta = ChunkMeta {
+                    series_key: to,
+                    ..meta
+                };
+                (meta, chunk)
+            })
+            .collect();
+        self.chunk_store.set_chunks(&chunks)?;
+        if let Some(json) = self.chunk_store.get_series_metadata(from)? {
+            self.chunk_store.set_series_metadata(to, &json)?;
         }
+        let config = self.series_config(from)?;
+        if config != self.series_config(to)? {
+            self.chunk_store.set_series_schedule(to, &config.encode())?;
+            self.cache_series_config(to, config);
+        }
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use quickcheck::quickcheck;
-    use quickcheck::Arbitrary;
+    /// Moves series `from` to the key `to`, see `copy_series`.
+    pub fn rename_series(&mut self, from: i64, to: i64) -> Result<(), SetChunkError> {
+        if from == to {
+            return Ok(());
+        }
+        self.copy_series(from, to)?;
+        self.delete_series(from)
+    }
+
+    /// Gives a series its own chunk schedule, e.g. small chunks for a high-frequency
+    /// series next to large ones for slow series. The override is kept in the store, so
+    /// it is used again after reopening, and other series keep the default schedule.
+    ///
+    /// Fails with `SeriesNotEmpty` if the series already has points, as these are
+    /// chunked by the schedule they were written with.
+    pub fn set_series_config(
+        &mut self,
+        series_key: i64,
+        config: ScheduleConfig,
+    ) -> Result<(), SetChunkError> {
+        if self.chunk_bounds(series_key)?.is_some() {
+            return Err(SetChunkError::SeriesNotEmpty(series_key));
+        }
+        self.chunk_store
+            .set_series_schedule(series_key, &config.encode())?;
+        self.cache_series_config(series_key, config);
+        Ok(())
+    }
+
+    fn cache_series_config(&mut self, series_key: i64, config: ScheduleConfig) {
+        unpoison(self.default_schedules.get_mut()).remove(&series_key);
+        unpoison(self.series_configs.get_mut()).insert(series_key, config);
+    }
+
+    /// Returns the chunk schedule of a series, set by `set_series_config` or else the
+    /// one the `Kelpie` was opened with.
+    pub fn series_config(&self, series_key: i64) -> Result<ScheduleConfig, GetChunkError> {
+        if let Some(&config) = unpoison(self.series_configs.lock()).get(&series_key) {
+            return Ok(config);
+        }
+        if unpoison(self.default_schedules.lock()).contains(&series_key) {
+            return Ok(self.schedule_config);
+        }
+        let Some(encoded) = self.chunk_store.get_series_schedule(series_key)? else {
+            let mut defaults = unpoison(self.default_schedules.lock());
+            if defaults.len() >= DEFAULT_SCHEDULES_CAPACITY {
+                defaults.clear();
+            }
+            defaults.insert(series_key);
+            return Ok(self.schedule_config);
+        };
+        let config = ScheduleConfig::decode(&encoded).ok_or_else(|| {
+            GetChunkError::Driver(
+                format!("invalid schedule {encoded:?} of series {series_key}").into(),
+            )
+        })?;
+        unpoison(self.series_configs.lock()).insert(series_key, config);
+        Ok(config)
+    }
+
+    /// Attaches an opaque metadata string (e.g. JSON) to a series.
+    pub fn set_series_metadata(
+        &mut self,
+        series_key: i64,
+        json: &str,
+    ) -> Result<(), SetChunkError> {
+        self.chunk_store.set_series_metadata(series_key, json)
+    }
+
+    pub fn get_series_metadata(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+        self.chunk_store.get_series_metadata(series_key)
+    }
+
+    /// Returns the latest `n` points of a series, regardless of their time.
+    ///
+    /// Chunks are read from the newest backward, stopping as soon as `n` points are found.
+    pub fn query_last_n(&self, series_key: i64, n: usize) -> Result<RawSeries, GetChunkError> {
+        let mut map = BTreeMap::new();
+        if n == 0 {
+            return Ok(RawSeries { data: map });
+        }
+        let mut chunks: Vec<(i64, i64)> = self
+            .chunk_store
+            .list_chunks(series_key)?
+            .into_iter()
+            .map(|meta| (meta.start, meta.stop))
+            .collect();
+        for series in self.cached(series_key) {
+            chunks.push((series.schedule.chunk_start, series.schedule.chunk_end));
+        }
+        chunks.sort_unstable();
+        chunks.dedup_by_key(|&mut (start, _stop)| start);
+
+        for (start, stop) in chunks.into_iter().rev() {
+            if map.len() >= n {
+                break;
+            }
+            if let Some((_meta, mut chunk)) = self.query_exact_chunk(series_key, start, stop)? {
+                map.append(&mut chunk.data);
+            }
+        }
+        while map.len() > n {
+            map.pop_first();
+        }
+        Ok(RawSeries { data: map })
+    }
+
+    /// Returns the points of a series in `[start, stop)` from the latest to the earliest,
+    /// the same as `query` in reverse, keeping only the first `limit` if given.
+    ///
+    /// Chunks are read from the newest backward. With a limit, reading stops once `limit`
+    /// points are found and no earlier chunk reaches past the earliest of them, so older
+    /// chunks are neither read nor decompressed.
+    pub fn query_desc(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        limit: Option<usize>,
+    ) -> Result<Vec<DataPoint>, GetChunkError> {
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut map = BTreeMap::new();
+        if start >= stop || limit == 0 {
+            return Ok(vec![]);
+        }
+        // cached chunks take precedence over every stored one, as in `query`
+        for series in self.cached(series_key) {
+            map.extend(series.data.data.range(start..stop));
+        }
+        let mut stored: Vec<ChunkMeta> = self
+            .chunk_store
+            .list_chunks(series_key)?
+            .into_iter()
+            .filter(|meta| meta.start < stop && start < meta.stop)
+            .filter(|meta| {
+                !self.cached(series_key).any(|series| {
+                    (series.schedule.chunk_start, series.schedule.chunk_end)
+                        == (meta.start, meta.stop)
+                })
+            })
+            .collect();
+        stored.sort_by_key(|meta| (meta.start, meta.stop));
+        // the largest stop among each prefix of the chunks, which bounds what the chunks
+        // before a position can still contribute
+        let reach: Vec<i64> = stored
+            .iter()
+            .scan(i64::MIN, |reach, meta| {
+                *reach = (*reach).max(meta.stop);
+                Some(*reach)
+            })
+            .collect();
+
+        for (i, meta) in stored.iter().enumerate().rev() {
+            if map.len() >= limit {
+                // the earliest time that makes it into the result so far
+                let cutoff = map.keys().nth_back(limit - 1).copied();
+                if cutoff.is_some_and(|cutoff| reach[i] - 1 <= cutoff) {
+                    break;
+                }
+            }
+            let chunk = self
+                .chunk_store
+                .get_chunk(series_key, meta.start, meta.stop)?;
+            let Some((_meta, chunk)) = chunk.filter(|(found, _chunk)| found == meta) else {
+                continue;
+            };
+            let series = chunk.decompress().map_err(GetChunkError::Corrupt)?;
+            // chunks merged later in `query` were read first and win
+            for (&time, &value) in series.data.range(start..stop) {
+                map.entry(time).or_insert(value);
+            }
+        }
+        Ok(map
+            .into_iter()
+            .rev()
+            .take(limit)
+            .map(|(time, value)| DataPoint { time, value })
+            .collect())
+    }
+
+    /// Returns the ratio of compressed bytes to raw point bytes (16 per point) over
+    /// all chunks of a series. Lower is better. A series without points has no ratio and
+    /// returns NaN.
+    pub fn series_efficiency(&self, series_key: i64) -> Result<f64, GetChunkError> {
+        let mut compressed_bytes = 0;
+        let mut raw_bytes = 0;
+        for meta in self.chunk_store.list_chunks(series_key)? {
+            // cached chunks supersede their stored versions
+            if self.is_cached(series_key, meta.start) {
+                continue;
+            }
+            let Some((_meta, chunk)) = self
+                .chunk_store
+                .get_chunk(series_key, meta.start, meta.stop)?
+            else {
+                continue;
+            };
+            compressed_bytes += chunk.compressed_data.len();
+            raw_bytes += chunk
+                .decompress()
+                .map_err(GetChunkError::Corrupt)?
+                .serial_size_hint();
+        }
+        for series in self.cached(series_key) {
+            compressed_bytes += self.compress(&series.data).compressed_data.len();
+            raw_bytes += series.data.serial_size_hint();
+        }
+        // 0 / 0 is NaN
+        Ok(compressed_bytes as f64 / raw_bytes as f64)
+    }
+
+    /// Queries `[latest - window, latest]`, anchored at the newest point of the series.
+    /// Returns an empty series if the series holds no points.
+    pub fn query_last(&self, series_key: i64, window: i64) -> Result<RawSeries, GetChunkError> {
+        let Some((_first, latest)) = self.time_bounds(series_key)? else {
+            return Ok(RawSeries::new());
+        };
+        self.query(
+            series_key,
+            latest.saturating_sub(window),
+            latest.saturating_add(1),
+        )
+    }
+
+    /// Queries `[now - window, now]`, anchored at the current wall-clock time in milliseconds.
+    pub fn query_last_wallclock(
+        &self,
+        series_key: i64,
+        window: i64,
+    ) -> Result<RawSeries, GetChunkError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64);
+        self.query(
+            series_key,
+            now.saturating_sub(window),
+            now.saturating_add(1),
+        )
+    }
+
+    // writes and evicts the cached chunks of a series, which stay cached if writing fails
+    fn try_save_series(&mut self, series_key: i64) -> Result<(), SetChunkError> {
+        self.flush(series_key)?;
+        self.series.remove(&series_key);
+        Ok(())
+    }
+
+    // caches the chunk of `schedule`, evicting the least recently used chunk if the cache
+    // is full and returning its schedule if it was of the same series. A chunk that can't be read is left
+    // alone instead of being replaced by an empty one. Fails with whichever of the
+    // insert errors fits, as loading both reads and writes the store.
+    fn load_series<E: From<GetChunkError> + From<SetChunkError>>(
+        &mut self,
+        series_key: i64,
+        schedule: Schedule,
+    ) -> Result<Option<Schedule>, E> {
+        let chunk_res = match self.ingest_mode {
+            IngestMode::Safe => {
+                self.chunk_store
+                    .get_chunk(series_key, schedule.chunk_start, schedule.chunk_end)?
+            }
+            IngestMode::AppendOnly => None,
+        };
+        let series = match chunk_res {
+            Some((meta, chunk)) => {
+                let raw_series = chunk.decompress().map_err(GetChunkError::Corrupt)?;
+                // adopt the stored chunk's bounds so a compacted chunk is written back whole
+                Series {
+                    schedule: Schedule {
+                        chunk_start: meta.start,
+                        chunk_end: meta.stop,
+                    },
+                    data: raw_series,
+                    unsaved: 0,
+                    last_used: 0,
+                    partial: false,
+                }
+            }
+            None => Series {
+                partial: self.ingest_mode == IngestMode::AppendOnly,
+                ..Series::new(schedule)
+            },
+        };
+        let evicted = self.evict_if_full(series_key)?;
+        let last_used = self.tick();
+        self.series.entry(series_key).or_default().push(Series {
+            last_used,
+            ..series
+        });
+        self.auto_compact(series_key)?;
+        Ok(evicted)
+    }
+
+    // makes room to cache another chunk of a series, writing and evicting the least
+    // recently used chunk of the series if it has as many as allowed, or of any series
+    // if the whole cache is full. Returns the evicted schedule if it was of the series.
+    fn evict_if_full(&mut self, series_key: i64) -> Result<Option<Schedule>, SetChunkError> {
+        let CacheConfig {
+            chunks_per_series,
+            max_chunks,
+        } = self.cache_config;
+        let evicted = if self.cached(series_key).count() >= chunks_per_series.max(1) {
+            self.evict_lru(Some(series_key))?
+        } else if max_chunks.is_some_and(|max| self.cached_count() >= max.max(1)) {
+            self.evict_lru(None)?
+        } else {
+            None
+        };
+        Ok(evicted
+            .filter(|&(evicted_key, _)| evicted_key == series_key)
+            .map(|(_, schedule)| schedule))
+    }
+
+    // writes and evicts the least recently used chunk of `series_key`, or of any series
+    // if `None`, and returns its series and schedule. A chunk that can't be written
+    // stays cached.
+    fn evict_lru(
+        &mut self,
+        series_key: Option<i64>,
+    ) -> Result<Option<(i64, Schedule)>, SetChunkError> {
+        let oldest = |cached: &Vec<Series>| cached.first().map(|series| series.last_used);
+        let victim = match series_key {
+            Some(series_key) => self
+                .series
+                .get(&series_key)
+                .and_then(oldest)
+                .map(|_| series_key),
+            None => self
+                .series
+                .iter()
+                .filter_map(|(&series_key, cached)| Some((oldest(cached)?, series_key)))
+                .min()
+                .map(|(_, series_key)| series_key),
+        };
+        let Some(victim) = victim else {
+            return Ok(None);
+        };
+        let cached = self.series.get_mut(&victim).unwrap();
+        let series = cached.remove(0);
+        if cached.is_empty() {
+            self.series.remove(&victim);
+        }
+        let Schedule {
+            chunk_start: start,
+            chunk_end: stop,
+        } = series.schedule;
+        let written = match self.complete_data(victim, &series) {
+            Ok(data) => {
+                let chunk = self.compress(&data);
+                self.chunk_store.set_chunk(victim, start, stop, &chunk)
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = written {
+            self.series.entry(victim).or_default().insert(0, series);
+            return Err(e);
+        }
+        Ok(Some((victim, series.schedule)))
+    }
+
+    // the points of a cached chunk, merged with the stored ones if it started empty in
+    // append-only mode and so lacks them; on equal times the cached points win
+    fn complete_data<'a>(
+        &self,
+        series_key: i64,
+        series: &'a Series,
+    ) -> Result<Cow<'a, RawSeries>, GetChunkError> {
+        if !series.partial {
+            return Ok(Cow::Borrowed(&series.data));
+        }
+        let Schedule {
+            chunk_start,
+            chunk_end,
+        } = series.schedule;
+        let mut data = match self
+            .chunk_store
+            .get_chunk(series_key, chunk_start, chunk_end)?
+        {
+            Some((_meta, chunk)) => {
+                let mut stored = chunk.decompress().map_err(GetChunkError::Corrupt)?;
+                stored
+                    .data
+                    .retain(|&time, _| chunk_start <= time && time < chunk_end);
+                stored
+            }
+            None => RawSeries::new(),
+        };
+        data.data.extend(&series.data.data);
+        Ok(Cow::Owned(data))
+    }
+
+    // number of cached chunks across all series
+    fn cached_count(&self) -> usize {
+        self.series.values().map(Vec::len).sum()
+    }
+
+    // the next tick of the cache's clock, see `Series::last_used`
+    fn tick(&mut self) -> u64 {
+        self.cache_ticks += 1;
+        self.cache_ticks
+    }
+
+    // the cached chunks of a series
+    fn cached(&self, series_key: i64) -> impl Iterator<Item = &Series> {
+        self.series.get(&series_key).into_iter().flatten()
+    }
+
+    // the cached chunk of a series covering all of `[start, stop)`
+    fn cached_containing(&self, series_key: i64, start: i64, stop: i64) -> Option<&Series> {
+        self.cached(series_key).find(|series| {
+            series.schedule.chunk_start <= start && stop <= series.schedule.chunk_end
+        })
+    }
+
+    // whether the chunk of a series starting at `start` is cached
+    fn is_cached(&self, series_key: i64, start: i64) -> bool {
+        self.cached(series_key)
+            .any(|series| series.schedule.chunk_start == start)
+    }
+
+    // compacts the oldest runs of a series once it has more stored chunks than allowed
+    fn auto_compact(&mut self, series_key: i64) -> Result<(), SetChunkError> {
+        let Some(max_chunks) = self.auto_compact else {
+            return Ok(());
+        };
+        let chunks = self.chunk_store.list_chunks(series_key)?.len();
+        if chunks > max_chunks {
+            self.compact_runs(series_key, self.compaction_target, chunks - max_chunks)?;
+        }
+        Ok(())
+    }
+
+    /// Merges runs of adjacent stored chunks of a series into chunks within `target`.
+    /// Cached chunks are never compacted.
+    /// Returns the number of chunks removed.
+    pub fn compact(
+        &mut self,
+        series_key: i64,
+        target: CompactionTarget,
+    ) -> Result<usize, SetChunkError> {
+        self.compact_runs(series_key, target, usize::MAX)
+    }
+
+    /// Like `compact`, with the target set by `set_compaction_target`. Compacting again
+    /// without new writes changes nothing.
+    pub fn compact_series(&mut self, series_key: i64) -> Result<(), SetChunkError> {
+        self.compact(series_key, self.compaction_target)?;
+        Ok(())
+    }
+
+    /// Flushes the cache and compacts every series in the store, see `compact`.
+    ///
+    /// Each merged run is replaced atomically, so a failure leaves every series
+    /// consistent and the sweep can simply be run again.
+    pub fn compact_all(
+        &mut self,
+        target: CompactionTarget,
+    ) -> Result<CompactionReport, SetChunkError> {
+        self.save_all()?;
+        let mut series_keys: Vec<i64> = self
+            .chunk_store
+            .list_all_chunks()?
+            .iter()
+            .map(|meta| meta.series_key)
+            .collect();
+        series_keys.dedup();
+
+        let mut report = CompactionReport::default();
+        for series_key in series_keys {
+            let (chunks, bytes) = self.stored_size(series_key)?;
+            report.chunks_before += chunks;
+            let removed = self.compact(series_key, target)?;
+            if removed > 0 {
+                report.series_compacted += 1;
+            }
+            let (chunks_after, bytes_after) = self.stored_size(series_key)?;
+            report.chunks_after += chunks_after;
+            report.bytes_reclaimed += bytes.saturating_sub(bytes_after);
+        }
+        Ok(report)
+    }
+
+    /// Rewrites every stored chunk of a series compressed at `level`, see
+    /// `RawSeries::compress_with_level`, in a single store transaction. The secondary
+    /// compression, if set, is applied as well.
+    ///
+    /// Chunks written later, by flushes or compaction, use the level set with
+    /// `set_compression_level` again, so this is meant for series that are no longer
+    /// written to.
+    pub fn recompress_series(
+        &mut self,
+        series_key: i64,
+        level: u32,
+    ) -> Result<RecompressStats, SetChunkError> {
+        let chunks = self
+            .chunk_store
+            .get_chunks_in_range(series_key, i64::MIN, i64::MAX)?;
+        let mut stats = RecompressStats {
+            chunks: chunks.len(),
+            ..RecompressStats::default()
+        };
+        let mut compression = self.compression;
+        compression.level = level;
+        let mut remove = Vec::with_capacity(chunks.len());
+        let mut add = Vec::with_capacity(chunks.len());
+        for (meta, chunk) in chunks {
+            let data = chunk.decompress().map_err(SetChunkError::Corrupt)?;
+            let recompressed = compression.compress(&data);
+            stats.bytes_before += chunk.as_bytes().len();
+            stats.bytes_after += recompressed.as_bytes().len();
+            remove.push(meta);
+            add.push((meta, recompressed));
+        }
+        self.chunk_store.replace_chunks(&remove, &add)?;
+        Ok(stats)
+    }
+
+    // number of stored chunks of a series and their total compressed size
+    fn stored_size(&self, series_key: i64) -> Result<(usize, usize), GetChunkError> {
+        let chunks = self
+            .chunk_store
+            .get_chunks_in_range(series_key, i64::MIN, i64::MAX)?;
+        let bytes = chunks.iter().map(|(_, chunk)| chunk.as_bytes().len()).sum();
+        Ok((chunks.len(), bytes))
+    }
+
+    // compacts the oldest runs first, removing at most `budget` chunks
+    fn compact_runs(
+        &mut self,
+        series_key: i64,
+        target: CompactionTarget,
+        budget: usize,
+    ) -> Result<usize, SetChunkError> {
+        let mut removed = 0;
+        let mut run: Vec<ChunkMeta> = vec![];
+        let mut run_data = RawSeries::new();
+        // compressed size of the chunks in the run
+        let mut run_bytes = 0;
+        for meta in self.chunk_store.list_chunks(series_key)? {
+            if removed >= budget {
+                break;
+            }
+            if self.is_cached(series_key, meta.start) {
+                removed += self.write_run(series_key, target, &mut run, &mut run_data)?;
+                continue;
+            }
+            let Some((meta, chunk)) = self
+                .chunk_store
+                .get_chunk(series_key, meta.start, meta.stop)?
+            else {
+                continue;
+            };
+            let bytes = chunk.as_bytes().len();
+            let mut data = chunk.decompress().map_err(SetChunkError::Corrupt)?;
+            let extends = run.last().is_some_and(|last| last.stop == meta.start)
+                && removed + run.len() <= budget
+                && target.fits(run_data.data.len() + data.data.len(), run_bytes + bytes);
+            if !extends {
+                removed += self.write_run(series_key, target, &mut run, &mut run_data)?;
+                run_bytes = 0;
+            }
+            run.push(meta);
+            run_data.data.append(&mut data.data);
+            run_bytes += bytes;
+        }
+        removed += self.write_run(series_key, target, &mut run, &mut run_data)?;
+        Ok(removed)
+    }
+
+    // replaces a run of adjacent chunks with a single merged chunk
+    fn write_run(
+        &mut self,
+        series_key: i64,
+        target: CompactionTarget,
+        run: &mut Vec<ChunkMeta>,
+        run_data: &mut RawSeries,
+    ) -> Result<usize, SetChunkError> {
+        let data = std::mem::take(run_data);
+        let run = std::mem::take(run);
+        self.write_merged(series_key, target, &run, data)
+    }
+
+    // merges the chunks of `run`, holding `data`, splitting the run in halves until the
+    // merged chunks fit `target`
+    fn write_merged(
+        &mut self,
+        series_key: i64,
+        target: CompactionTarget,
+        run: &[ChunkMeta],
+        mut data: RawSeries,
+    ) -> Result<usize, SetChunkError> {
+        let (Some(first), Some(last)) = (run.first(), run.last()) else {
+            return Ok(0);
+        };
+        if run.len() < 2 {
+            return Ok(0);
+        }
+        let chunk = self.compress(&data);
+        if !target.fits_chunk(&chunk) {
+            let (head, tail) = run.split_at(run.len() / 2);
+            // the chunks of a run are adjacent, so each holds the points up to the next
+            let tail_data = RawSeries {
+                data: data.data.split_off(&tail[0].start),
+            };
+            return Ok(self.write_merged(series_key, target, head, data)?
+                + self.write_merged(series_key, target, tail, tail_data)?);
+        }
+        let meta = ChunkMeta {
+            series_key,
+            start: first.start,
+            stop: last.stop,
+        };
+        self.chunk_store.replace_chunks(run, &[(meta, chunk)])?;
+        Ok(run.len() - 1)
+    }
+
+    /// Stores `points` as a single chunk spanning the caller-chosen `[start, stop)` rather
+    /// than the fixed schedule, e.g. to keep a recording session in one chunk.
+    ///
+    /// Points outside the range or not storable are dropped. Stored chunks overlapping the
+    /// range are merged into the new chunk, which grows to cover them; on equal times the
+    /// new points win.
+    pub fn store_chunk(
+        &mut self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        points: &[DataPoint],
+    ) -> Result<(), SetChunkError> {
+        if start >= stop {
+            return Ok(());
+        }
+        self.try_save_series(series_key)?;
+        self.series_written(series_key);
+        let overlapping = self
+            .chunk_store
+            .get_chunks_in_range(series_key, start, stop)?;
+        let mut meta = ChunkMeta {
+            series_key,
+            start,
+            stop,
+        };
+        let mut data = RawSeries::new();
+        let mut remove = vec![];
+        for (old, chunk) in overlapping {
+            let mut old_data = chunk.decompress().map_err(SetChunkError::Corrupt)?;
+            data.data.append(&mut old_data.data);
+            meta.start = meta.start.min(old.start);
+            meta.stop = meta.stop.max(old.stop);
+            remove.push(old);
+        }
+        for point in points {
+            if Self::is_storable(point) && (start..stop).contains(&point.time) {
+                data.insert(*point);
+            }
+        }
+        let chunk = self.compress(&data);
+        self.chunk_store.replace_chunks(&remove, &[(meta, chunk)])
+    }
+
+    // makes the cached chunk for `time` the most recently used one, loading it if it
+    // isn't cached, and returns the schedule of the chunk evicted for it
+    fn ensure_series_for<E: From<GetChunkError> + From<SetChunkError>>(
+        &mut self,
+        series_key: i64,
+        time: i64,
+    ) -> Result<Option<Schedule>, E> {
+        let last_used = self.tick();
+        if let Some(cached) = self.series.get_mut(&series_key) {
+            if let Some(i) = cached
+                .iter()
+                .position(|series| series.schedule.contains(time))
+            {
+                let series = cached.remove(i);
+                cached.push(Series {
+                    last_used,
+                    ..series
+                });
+                return Ok(None);
+            }
+        }
+        let schedule = self
+            .series_config(series_key)?
+            .init_schedule_from_time(time);
+        self.load_series(series_key, schedule)
+    }
+
+    // the most recently used chunk of a series, see `ensure_series_for`
+    fn latest_chunk(&mut self, series_key: i64) -> &mut Series {
+        self.series
+            .get_mut(&series_key)
+            .and_then(|cached| cached.last_mut())
+            .unwrap()
+    }
+
+    fn validate(data_point: &DataPoint) -> Result<(), InsertError> {
+        if data_point.value.is_nan() {
+            return Err(InsertError::NanValue);
+        }
+        if data_point.time < 0 {
+            return Err(InsertError::NegativeTime(data_point.time));
+        }
+        // skip max value because last chunk will go from last_multiple to max_value exclusive,
+        // so we can never store max value
+        if data_point.time == i64::MAX {
+            return Err(InsertError::TimestampOutOfRange(data_point.time));
+        }
+        Ok(())
+    }
+
+    fn is_storable(data_point: &DataPoint) -> bool {
+        Self::validate(data_point).is_ok()
+    }
+
+    pub fn insert(
+        &mut self,
+        series_key: i64,
+        data_point: DataPoint,
+    ) -> Result<InsertStatus, InsertError> {
+        Self::validate(&data_point)?;
+        if self.reject_out_of_order {
+            let last = match self.last_times.get(&series_key) {
+                Some(&last) => Some(last),
+                None => self.last(series_key, i64::MAX)?.map(|point| point.time),
+            };
+            if let Some(last) = last.filter(|&last| data_point.time <= last) {
+                return Err(InsertError::OutOfOrder {
+                    time: data_point.time,
+                    last,
+                });
+            }
+        }
+        self.series_written(series_key);
+        let rolled = self.ensure_series_for::<InsertError>(series_key, data_point.time)?;
+        let policy = self.duplicate_policy;
+        let series = self.latest_chunk(series_key);
+        if let Some(data_point) = policy.resolve(series.data.get(data_point.time), data_point)? {
+            assert!(series.try_insert(data_point));
+            if self.reject_out_of_order {
+                self.last_times.insert(series_key, data_point.time);
+            }
+            self.write_through(series_key)?;
+            self.publish(series_key, data_point);
+        }
+        Ok(match rolled {
+            Some(schedule) => InsertStatus::CompactmentPending(schedule),
+            None => InsertStatus::Cached,
+        })
+    }
+
+    /// Like `insert`, but points that can't be stored (NaN, negative or max time) are
+    /// dropped without an error. Only store failures are reported.
+    pub fn try_insert_lossy(
+        &mut self,
+        series_key: i64,
+        data_point: DataPoint,
+    ) -> Result<(), InsertError> {
+        match self.insert(series_key, data_point) {
+            Err(e @ (InsertError::Store(_) | InsertError::Write(_))) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    // writes the most recently used chunk of a series if the durability mode asks for it
+    fn write_through(&mut self, series_key: i64) -> Result<(), SetChunkError> {
+        let DurabilityMode::WriteThroughEveryN(n) = self.durability_mode else {
+            return Ok(());
+        };
+        let series = self.latest_chunk(series_key);
+        if series.unsaved < n.max(1) {
+            return Ok(());
+        }
+        series.unsaved = 0;
+        let series = self.cached(series_key).last().unwrap();
+        let chunk = self.compress(&*self.complete_data(series_key, series)?);
+        let Schedule {
+            chunk_start,
+            chunk_end,
+        } = series.schedule;
+        self.chunk_store
+            .set_chunk(series_key, chunk_start, chunk_end, &chunk)
+    }
+
+    /// Whether inserting at `time` would load another chunk of a series, possibly
+    /// evicting a cached one, rather than land in a cached chunk.
+    pub fn would_create_chunk(&self, series_key: i64, time: i64) -> bool {
+        !self
+            .cached(series_key)
+            .any(|series| series.schedule.contains(time))
+    }
+
+    /// Returns a channel receiving every point of a series accepted by `insert`,
+    /// `insert_many` or `insert_batch` from now on. Each subscriber gets its own copy;
+    /// dropping the receiver unsubscribes.
+    pub fn subscribe(&mut self, series_key: i64) -> Receiver<DataPoint> {
+        let (sender, receiver) = channel();
+        self.subscribers.entry(series_key).or_default().push(sender);
+        receiver
+    }
+
+    fn publish(&mut self, series_key: i64, point: DataPoint) {
+        let Some(senders) = self.subscribers.get_mut(&series_key) else {
+            return;
+        };
+        // a failed send means the receiver was dropped
+        senders.retain(|sender| sender.send(point).is_ok());
+        if senders.is_empty() {
+            self.subscribers.remove(&series_key);
+        }
+    }
+
+    pub fn set_batch_dedup(&mut self, policy: BatchDedup) {
+        self.batch_dedup = policy;
+    }
+
+    /// Sets what `insert` does with a point at a time the series already has a point
+    /// at. Defaults to `DuplicatePolicy::LastWins`.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.duplicate_policy = policy;
+    }
+
+    /// Makes `insert` reject a point with `InsertError::OutOfOrder` unless it is later
+    /// than every point of its series, including points in other chunks and points
+    /// written before the `Kelpie` was opened. Off by default.
+    ///
+    /// The latest time is looked up with `last` on the first insert into a series, and
+    /// again after the series was written by anything but `insert`, e.g. a batch or a
+    /// deletion. Batches themselves are not checked.
+    pub fn set_reject_out_of_order(&mut self, reject_out_of_order: bool) {
+        self.reject_out_of_order = reject_out_of_order;
+        self.last_times.clear();
+    }
+
+    // forgets what was derived from the points of a series, as they were just written
+    fn series_written(&mut self, series_key: i64) {
+        unpoison(self.query_cache.get_mut()).invalidate(series_key);
+        self.last_times.remove(&series_key);
+    }
+
+    fn all_series_written(&mut self) {
+        unpoison(self.query_cache.get_mut()).clear();
+        self.last_times.clear();
+    }
+
+    /// Sets how many chunks are kept in the cache, see `CacheConfig`. Chunks already
+    /// cached past the new limits are written and evicted, least recently used first.
+    /// Fails if one can't be written, which then stays cached.
+    pub fn set_cache_config(&mut self, config: CacheConfig) -> Result<(), SetChunkError> {
+        self.cache_config = config;
+        let chunks_per_series = config.chunks_per_series.max(1);
+        let series_keys: Vec<i64> = self.series.keys().copied().collect();
+        for series_key in series_keys {
+            while self.cached(series_key).count() > chunks_per_series {
+                self.evict_lru(Some(series_key))?;
+            }
+        }
+        if let Some(max_chunks) = config.max_chunks {
+            while self.cached_count() > max_chunks.max(1) {
+                self.evict_lru(None)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_durability_mode(&mut self, mode: DurabilityMode) {
+        self.durability_mode = mode;
+    }
+
+    pub fn set_ingest_mode(&mut self, mode: IngestMode) {
+        self.ingest_mode = mode;
+    }
+
+    /// Compacts a series whenever it grows beyond `max_chunks` stored chunks, merging
+    /// its oldest runs into chunks within the compaction target.
+    /// Series whose chunks are already full may stay above the threshold.
+    pub fn set_auto_compact(&mut self, max_chunks: Option<usize>) {
+        self.auto_compact = max_chunks;
+    }
+
+    /// Sets the pco compression level chunks are written with, from 0 to
+    /// `MAX_COMPRESSION_LEVEL`. Defaults to `DEFAULT_COMPRESSION_LEVEL`. Chunks already
+    /// stored keep their level until rewritten, see `recompress_series`; chunks of any
+    /// level can be read.
+    pub fn set_compression_level(&mut self, level: u32) -> Result<(), CompressionLevelError> {
+        if level > MAX_COMPRESSION_LEVEL {
+            return Err(CompressionLevelError::AboveMax(level));
+        }
+        self.compression.level = level;
+        Ok(())
+    }
+
+    /// Compresses the columns of chunks written from now on again with zstd at the given
+    /// level, see `Chunk::compress_series_zstd`. Off by default.
+    ///
+    /// Like the compression level, this is a setting of the instance rather than of
+    /// `ScheduleConfig`: every chunk records how it was compressed, so it can change at
+    /// any time, while the schedule decides where chunks are and must stay fixed.
+    #[cfg(feature = "zstd")]
+    pub fn set_secondary_compression(&mut self, secondary_compression: Option<ZstdLevel>) {
+        self.compression.zstd = secondary_compression;
+    }
+
+    // compresses a chunk with the configured settings
+    fn compress(&self, data: &RawSeries) -> Chunk {
+        self.compression.compress(data)
+    }
+
+    /// Sets the size of the chunks merged by `compact_series` and automatic compaction.
+    /// Defaults to `DEFAULT_COMPACT_MAX_POINTS` points.
+    pub fn set_compaction_target(&mut self, target: CompactionTarget) {
+        self.compaction_target = target;
+    }
+
+    fn dedup_batch(
+        points: Vec<DataPoint>,
+        policy: BatchDedup,
+    ) -> Result<Vec<DataPoint>, BatchError> {
+        let mut deduped: Vec<DataPoint> = Vec::with_capacity(points.len());
+        for point in points {
+            match deduped.last_mut() {
+                Some(last) if last.time == point.time => match policy {
+                    BatchDedup::KeepFirst => {}
+                    BatchDedup::KeepLast => *last = point,
+                    BatchDedup::Error => return Err(BatchError::DuplicateTime(point.time)),
+                },
+                _ => deduped.push(point),
+            }
+        }
+        Ok(deduped)
+    }
+
+    /// Inserts a batch of points into one series.
+    ///
+    /// Points are sorted by time first, so every affected chunk is loaded and saved
+    /// only once. Points sharing a timestamp are resolved by the batch dedup policy
+    /// before anything is written.
+    ///
+    /// Points `insert` would reject (NaN values, negative times and `i64::MAX`) are
+    /// dropped without an error, as by `try_insert_lossy`.
+    pub fn insert_many(&mut self, series_key: i64, points: &[DataPoint]) -> Result<(), BatchError> {
+        let mut points: Vec<DataPoint> = points.iter().copied().filter(Self::is_storable).collect();
+        // stable, so colliding points keep their batch order
+        points.sort_by_key(|point| point.time);
+        let points = Self::dedup_batch(points, self.batch_dedup)?;
+        self.series_written(series_key);
+        for point in points {
+            self.ensure_series_for::<BatchError>(series_key, point.time)?;
+            assert!(self.latest_chunk(series_key).try_insert(point));
+            self.write_through(series_key)?;
+            self.publish(series_key, point);
+        }
+        Ok(())
+    }
+
+    /// Inserts the `time,value` rows written by `export_csv` with `insert_many`, and
+    /// returns how many rows were read. Blank lines and a `time,value` header are
+    /// skipped. Nothing is inserted if any row is malformed or holds a point `insert`
+    /// would reject, like a NaN value or a negative time.
+    pub fn import_csv<R: BufRead>(&mut self, series_key: i64, r: R) -> Result<usize, CsvError> {
+        let points = csv::read_points(r)?;
+        self.insert_many(series_key, &points)?;
+        Ok(points.len())
+    }
+
+    /// Inserts points across many series with all-or-nothing semantics.
+    ///
+    /// Every affected chunk is merged with its cached or stored contents and written
+    /// in a single store transaction. If any write fails, neither the store nor the
+    /// cache is modified.
+    ///
+    /// Points `insert` would reject (NaN values, negative times and `i64::MAX`) are
+    /// dropped without an error and only counted in `InsertReport::rejected`.
+    pub fn insert_batch(
+        &mut self,
+        points: &[(i64, DataPoint)],
+    ) -> Result<InsertReport, SetChunkError> {
+        let mut report = InsertReport::default();
+        let mut staged: BTreeMap<(i64, i64), (Schedule, RawSeries)> = BTreeMap::new();
+        for &(series_key, point) in points {
+            if !Self::is_storable(&point) {
+                report.rejected += 1;
+                continue;
+            }
+            let schedule = self
+                .series_config(series_key)?
+                .init_schedule_from_time(point.time);
+            staged
+                .entry((series_key, schedule.chunk_start))
+                .or_insert_with(|| (schedule, RawSeries::new()))
+                .1
+                .insert(point);
+            report.accepted += 1;
+        }
+
+        // schedules may share a compacted chunk, so merge by the chunk actually stored
+        let mut merged: BTreeMap<(i64, i64, i64), RawSeries> = BTreeMap::new();
+        for ((series_key, _), (schedule, mut new_points)) in staged {
+            let (meta, existing) = match self.query_exact_chunk(
+                series_key,
+                schedule.chunk_start,
+                schedule.chunk_end,
+            )? {
+                Some(existing) => existing,
+                None => {
+                    let meta = ChunkMeta {
+                        series_key,
+                        start: schedule.chunk_start,
+                        stop: schedule.chunk_end,
+                    };
+                    (meta, RawSeries::new())
+                }
+            };
+            let data = merged
+                .entry((series_key, meta.start, meta.stop))
+                .or_insert(existing);
+            data.data.append(&mut new_points.data);
+        }
+
+        let chunks: Vec<(ChunkMeta, Chunk)> = merged
+            .iter()
+            .map(|(&(series_key, start, stop), data)| {
+                let meta = ChunkMeta {
+                    series_key,
+                    start,
+                    stop,
+                };
+                (meta, self.compress(data))
+            })
+            .collect();
+        self.chunk_store.set_chunks(&chunks)?;
+        report.chunks_written = chunks.len();
+
+        // keep cached chunks in sync with what was just written
+        for ((series_key, start, stop), data) in merged {
+            self.series_written(series_key);
+            let cached = self.series.get_mut(&series_key).into_iter().flatten();
+            for series in cached {
+                if series.schedule.chunk_start == start && series.schedule.chunk_end == stop {
+                    series.data = data;
+                    break;
+                }
+            }
+        }
+        for &(series_key, point) in points {
+            if Self::is_storable(&point) {
+                self.publish(series_key, point);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Ingests a stream of points, writing each chunk to the store once the stream
+    /// moves past it, without going through the chunk cache.
+    ///
+    /// Pending chunks are merged with what is already stored for their range, so any
+    /// order is correct, but streams sorted by time write every chunk only once.
+    pub fn stream_ingest<I: IntoIterator<Item = (i64, DataPoint)>>(
+        &mut self,
+        points: I,
+    ) -> Result<InsertReport, SetChunkError> {
+        // the store must be up to date before chunks are merged into it
+        self.save_all()?;
+        self.all_series_written();
+        let mut report = InsertReport::default();
+        let mut pending: HashMap<i64, Series> = HashMap::new();
+        for (series_key, point) in points {
+            if !Self::is_storable(&point) {
+                report.rejected += 1;
+                continue;
+            }
+            report.accepted += 1;
+            if let Some(series) = pending.get_mut(&series_key) {
+                if series.try_insert(point) {
+                    continue;
+                }
+            }
+            let schedule = self
+                .series_config(series_key)?
+                .init_schedule_from_time(point.time);
+            let mut series = Series::new(schedule);
+            assert!(series.try_insert(point));
+            if let Some(full) = pending.insert(series_key, series) {
+                self.merge_into_store(series_key, full)?;
+                report.chunks_written += 1;
+            }
+        }
+        for (series_key, series) in pending {
+            self.merge_into_store(series_key, series)?;
+            report.chunks_written += 1;
+        }
+        Ok(report)
+    }
+
+    // writes a series' points into the stored chunk covering its schedule
+    fn merge_into_store(
+        &mut self,
+        series_key: i64,
+        mut series: Series,
+    ) -> Result<(), SetChunkError> {
+        let Schedule {
+            chunk_start,
+            chunk_end,
+        } = series.schedule;
+        let stored = self
+            .chunk_store
+            .get_chunk(series_key, chunk_start, chunk_end)?;
+        let (start, stop, mut data) = match stored {
+            Some((meta, chunk)) => {
+                let data = chunk.decompress().map_err(SetChunkError::Corrupt)?;
+                (meta.start, meta.stop, data)
+            }
+            None => (chunk_start, chunk_end, RawSeries::new()),
+        };
+        data.data.append(&mut series.data.data);
+        let chunk = self.compress(&data);
+        self.chunk_store.set_chunk(series_key, start, stop, &chunk)
+    }
+
+    /// Flushes every cached series, checkpoints and closes the store.
+    ///
+    /// Dropping a `Kelpie` flushes as well but has to ignore errors, so call this where
+    /// durability matters. Every series is attempted even if one fails, and the first
+    /// error is returned.
+    pub fn close(mut self) -> Result<(), SetChunkError> {
+        let res = self.flush_all();
+        // nothing left for drop to retry
+        self.series.clear();
+        res.and(self.chunk_store.checkpoint())
+    }
+
+    /// Writes the cached chunks of a series that changed since they were last written
+    /// to the store. The chunks stay cached, so later inserts into them don't reload
+    /// them.
+    pub fn flush(&mut self, series_key: i64) -> Result<(), SetChunkError> {
+        let Some(cached) = self.series.get(&series_key) else {
+            return Ok(());
+        };
+        let chunks = cached
+            .iter()
+            .filter(|series| series.unsaved > 0)
+            .map(|series| {
+                let meta = ChunkMeta {
+                    series_key,
+                    start: series.schedule.chunk_start,
+                    stop: series.schedule.chunk_end,
+                };
+                Ok((
+                    meta,
+                    self.compress(&*self.complete_data(series_key, series)?),
+                ))
+            })
+            .collect::<Result<Vec<(ChunkMeta, Chunk)>, GetChunkError>>()?;
+        match chunks.as_slice() {
+            [] => return Ok(()),
+            [(meta, chunk)] => self
+                .chunk_store
+                .set_chunk(series_key, meta.start, meta.stop, chunk)?,
+            chunks => self.chunk_store.set_chunks(chunks)?,
+        }
+        for series in self.series.get_mut(&series_key).into_iter().flatten() {
+            series.unsaved = 0;
+        }
+        Ok(())
+    }
+
+    /// Writes the cached chunks of every series to the store, see `flush`.
+    /// Every series is attempted even if one fails, and the first error is returned.
+    pub fn flush_all(&mut self) -> Result<(), SetChunkError> {
+        let mut res = Ok(());
+        let series_keys = self.series.keys().copied().collect::<Vec<_>>();
+        for key in series_keys {
+            res = res.and(self.flush(key));
+        }
+        res
+    }
+
+    // writes and evicts every cached chunk, leaving the store as the only copy. Like
+    // `flush_all`, every series is attempted and the first error is returned.
+    fn save_all(&mut self) -> Result<(), SetChunkError> {
+        let mut res = Ok(());
+        let series_keys = self.series.keys().copied().collect::<Vec<_>>();
+        for key in series_keys {
+            res = res.and(self.try_save_series(key));
+        }
+        res
+    }
+}
+
+impl<S: KelpieChunkStore + Send + Sync + 'static> Kelpie<S> {
+    /// Moves the `Kelpie` to a handle shared with a thread that calls `flush_all` every
+    /// `interval`, bounding how much a crash can lose without explicit flushes.
+    pub fn spawn_autoflush(self, interval: Duration) -> AutoflushHandle<S> {
+        AutoflushHandle::spawn(self, interval)
+    }
+}
+
+impl<S: KelpieChunkStore> Drop for Kelpie<S> {
+    /// Best-effort flush of every cached chunk; errors are ignored, see `close`.
+    fn drop(&mut self) {
+        let _ = self.flush_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::quickcheck;
+    use quickcheck::Arbitrary;
+    use std::mem::discriminant;
+
+    #[test]
+    fn should_create() -> Result<(), Box<dyn std::error::Error>> {
+        let _kelpie = Kelpie::new_memory()?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_insert() -> Result<(), Box<dyn std::error::Error>> {
+        use rand::prelude::*;
+        use rand::rngs::SmallRng;
+
+        let mut kelpie = Kelpie::new_memory()?;
+        let mut rng = SmallRng::seed_from_u64(0xdeadbeef);
+        // float series
+        const SERIES: usize = 64;
+        let mut times: [i64; SERIES] = [1722180250000; SERIES];
+        let mut vals: [f64; SERIES] = [0.0; SERIES];
+        let val_variance = 20.0;
+        for _ in 0..100_000 {
+            let series_key = rng.gen_range(0..SERIES);
+            let last_time = &mut times[series_key];
+            let last_val = &mut vals[series_key];
+
+            let time_inc = rng.gen_range(400..1100);
+            *last_time += time_inc;
+            let time = *last_time;
+
+            let val_inc = rng.gen_range(-val_variance..val_variance);
+            *last_val += val_inc;
+
+            // truncate precision of floats to simulate less random measurements
+            let value = f64::from_bits(last_val.to_bits() & (!0 << 36));
+            assert!((*last_val - value).abs() < 1.0);
+            let point = DataPoint { time, value };
+
+            kelpie.insert(series_key as i64, point)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_non_positive_chunk_size() {
+        for chunk_size in [0, -1, i64::MIN] {
+            assert_eq!(
+                ScheduleConfig::new(chunk_size),
+                Err(ScheduleConfigError::NonPositiveChunkSize(chunk_size))
+            );
+        }
+        assert!(ScheduleConfig::new(1).is_ok());
+    }
+
+    #[test]
+    fn should_offset_chunk_alignment() -> Result<(), Box<dyn std::error::Error>> {
+        let config = ScheduleConfig::new(1000)?.with_align_offset(300)?;
+        for (point, start) in [
+            (300, 300),
+            (299, -700),
+            (1299, 300),
+            (1300, 1300),
+            (-1, -700),
+        ] {
+            let schedule = config.init_schedule_from_time(point);
+            assert_eq!(
+                (schedule.chunk_start, schedule.chunk_end),
+                (start, start + 1000),
+                "{point}"
+            );
+        }
+        let schedule = config.init_schedule_from_time(i64::MIN);
+        assert!(schedule.contains(i64::MIN));
+
+        for offset in [-1, 1000] {
+            assert_eq!(
+                ScheduleConfig::new(1000)?.with_align_offset(offset),
+                Err(ScheduleConfigError::AlignOffsetOutOfRange { offset, max: 1000 })
+            );
+        }
+        let day = 24 * 3_600_000;
+        let local_days =
+            ScheduleConfig::calendar(CalendarUnit::Day).with_align_offset(6 * 3_600_000)?;
+        let schedule = local_days.init_schedule_from_time(day + 1);
+        assert_eq!(
+            (schedule.chunk_start, schedule.chunk_end),
+            (6 * 3_600_000, day + 6 * 3_600_000)
+        );
+        if ScheduleConfig::calendar(CalendarUnit::Month)
+            .with_align_offset(day)
+            .is_ok()
+        {
+            Err("calendar offset of a day should be rejected")?;
+        }
+
+        let temp = TempPath::new();
+        let mut fake = KelpieFake::new();
+        let mut kelpie = Kelpie::new_path_with_config(temp.path(), config)?;
+        for time in (0..20).map(|i| i * 150) {
+            kelpie.insert(0, DataPoint { time, value: 1.0 })?;
+            fake.insert(0, DataPoint { time, value: 1.0 })?;
+        }
+        kelpie.flush_all()?;
+        let starts: Vec<i64> = (kelpie.chunk_store.list_chunks(0)?)
+            .iter()
+            .map(|meta| meta.start)
+            .collect();
+        assert_eq!(starts, vec![-700, 300, 1300, 2300]);
+        for (start, stop) in [(0, 3000), (250, 350), (299, 1301), (150, 151)] {
+            if kelpie.query(0, start, stop)? != fake.query(0, start, stop)? {
+                Err(format!("not matching in {start}..{stop}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_align_chunks_to_calendar_months() -> Result<(), Box<dyn std::error::Error>> {
+        let day = 24 * 3_600_000;
+        // 2024-01-01T00:00:00Z, a leap year
+        let jan = 1_704_067_200_000;
+        let (feb, mar, apr) = (jan + 31 * day, jan + 60 * day, jan + 91 * day);
+        let temp = TempPath::new();
+        let config = ScheduleConfig::calendar(CalendarUnit::Month);
+        let mut fake = KelpieFake::new();
+        {
+            let mut kelpie = Kelpie::new_path_with_config(temp.path(), config)?;
+            // every 12 hours, plus the last millisecond of each month
+            let times = (0..182).map(|i| jan + i * day / 2);
+            for time in times.chain([feb - 1, mar - 1, apr - 1]) {
+                let point = DataPoint {
+                    time,
+                    value: time as f64,
+                };
+                kelpie.insert(0, point)?;
+                fake.insert(0, point)?;
+            }
+            kelpie.flush_all()?;
+            let chunks: Vec<(i64, i64)> = (kelpie.chunk_store.list_chunks(0)?)
+                .iter()
+                .map(|meta| (meta.start, meta.stop))
+                .collect();
+            assert_eq!(chunks, vec![(jan, feb), (feb, mar), (mar, apr)]);
+        }
+
+        let kelpie = Kelpie::new_path_with_config(temp.path(), config)?;
+        for (start, stop) in [
+            (jan, apr),
+            (feb - 1, mar + 1),
+            (mar - day, mar),
+            (0, i64::MAX),
+        ] {
+            if kelpie.query(0, start, stop)? != fake.query(0, start, stop)? {
+                Err(format!("not matching in {start}..{stop}"))?;
+            }
+        }
+        assert_eq!(kelpie.time_bounds(0)?, Some((jan, apr - 1)));
+        assert!(kelpie.has_data_in(0, mar - 1, mar)?);
+        assert!(!kelpie.has_data_in(0, mar - day / 2 + 1, mar - 1)?);
+        assert_eq!(
+            config.alignment(),
+            ChunkAlignment::Calendar(CalendarUnit::Month)
+        );
+        assert_eq!(config.chunk_size(), 31 * day);
+        Ok(())
+    }
+
+    #[test]
+    fn should_chunk_series_by_their_own_config() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let mut fake = KelpieFake::new();
+        let (fast, slow) = (ScheduleConfig::new(100)?, ScheduleConfig::new(10_000)?);
+        {
+            let mut kelpie = Kelpie::new_path(temp.path())?;
+            kelpie.set_series_config(0, fast)?;
+            kelpie.set_series_config(1, slow)?;
+            for time in (0..100).map(|i| i * 250) {
+                for series_key in 0..3 {
+                    kelpie.insert(series_key, DataPoint { time, value: 1.0 })?;
+                    fake.insert(series_key, DataPoint { time, value: 1.0 })?;
+                }
+            }
+            kelpie.flush_all()?;
+            let widths = |series_key| -> Result<Vec<i64>, GetChunkError> {
+                let chunks = kelpie.chunk_store.list_chunks(series_key)?;
+                Ok(chunks.iter().map(|meta| meta.stop - meta.start).collect())
+            };
+            assert_eq!(widths(0)?, vec![100; 100]);
+            assert_eq!(widths(1)?, vec![10_000; 3]);
+            assert_eq!(widths(2)?, vec![3_600_000]);
+            if !matches!(
+                kelpie.set_series_config(2, fast),
+                Err(SetChunkError::SeriesNotEmpty(2))
+            ) {
+                Err("config of a series with points should not change")?;
+            }
+        }
+
+        // the overrides are read back from the store
+        let kelpie = Kelpie::new_path(temp.path())?;
+        assert_eq!(kelpie.series_config(0)?, fast);
+        assert_eq!(kelpie.series_config(2)?, ScheduleConfig::default());
+        assert!(kelpie.misaligned_chunks()?.is_empty());
+        let ranges = [(0, 25_000), (120, 130), (9_900, 10_300)];
+        for series_key in 0..3 {
+            let res = kelpie.query_ranges(series_key, &ranges)?;
+            for (&(start, stop), res) in ranges.iter().zip(res) {
+                if res != fake.query(series_key, start, stop)? {
+                    Err(format!(
+                        "series {series_key} not matching in {start}..{stop}"
+                    ))?;
+                }
+            }
+            assert_eq!(kelpie.time_bounds(series_key)?, Some((0, 24_750)));
+            assert!(kelpie.has_data_in(series_key, 9_999, 10_001)?);
+            assert!(!kelpie.has_data_in(series_key, 10_001, 10_250)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_remember_only_schedule_overrides() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let fast = ScheduleConfig::new(100)?;
+        kelpie.set_series_config(0, fast)?;
+        for series_key in 0..1000 {
+            kelpie.series_config(series_key)?;
+        }
+        assert_eq!(unpoison(kelpie.series_configs.lock()).len(), 1);
+        assert_eq!(unpoison(kelpie.default_schedules.lock()).len(), 999);
+
+        // a series without an override can still get one
+        kelpie.set_series_config(1, fast)?;
+        assert_eq!(kelpie.series_config(1)?, fast);
+        assert!(!unpoison(kelpie.default_schedules.lock()).contains(&1));
+
+        kelpie.truncate()?;
+        assert!(unpoison(kelpie.series_configs.lock()).is_empty());
+        assert!(unpoison(kelpie.default_schedules.lock()).is_empty());
+        assert_eq!(kelpie.series_config(0)?, ScheduleConfig::default());
+        Ok(())
+    }
+
+    #[test]
+    fn should_honor_configured_chunk_size() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let config = ScheduleConfig::new(1000)?;
+        let mut fake = KelpieFake::new();
+        {
+            let mut kelpie = Kelpie::new_path_with_config(temp.path(), config)?;
+            for time in (0..20).map(|i| i * 150) {
+                kelpie.insert(0, DataPoint { time, value: 1.0 })?;
+                fake.insert(0, DataPoint { time, value: 1.0 })?;
+            }
+            kelpie.flush_all()?;
+            let starts: Vec<i64> = kelpie
+                .chunk_store
+                .list_chunks(0)?
+                .iter()
+                .map(|meta| meta.start)
+                .collect();
+            assert_eq!(starts, vec![0, 1000, 2000]);
+        }
+        let kelpie = Kelpie::new_path_with_config(temp.path(), config)?;
+        for (start, stop) in [(0, 3000), (900, 2100), (150, 151)] {
+            if kelpie.query(0, start, stop)? != fake.query(0, start, stop)? {
+                Err("not matching")?;
+            }
+        }
+        let kelpie = Kelpie::new_memory_with_config(ScheduleConfig::new(24 * 3_600_000)?)?;
+        assert_eq!(kelpie.schedule_config.chunk_size(), 24 * 3_600_000);
+        Ok(())
+    }
+
+    #[test]
+    fn should_round_trip_series_metadata() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let meta = r#"{"unit": "ms", "source": "sensor-1"}"#;
+        kelpie.set_series_metadata(0, meta)?;
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        )?;
+        if kelpie.get_series_metadata(0)?.as_deref() != Some(meta) {
+            Err("metadata doesn't match")?;
+        }
+        kelpie.delete_series(0)?;
+        if kelpie.get_series_metadata(0)?.is_some() {
+            Err("metadata should be deleted with the series")?;
+        }
+        if !kelpie.query(0, 0, 100)?.data.is_empty() {
+            Err("series should be deleted")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_match_fake_after_delete() -> Result<(), Box<dyn std::error::Error>> {
+        let query = Cmd::Query {
+            series_key: 0,
+            start: 0,
+            stop: 4 * 3_600_000,
+        };
+        let mut cmds = hourly_cmds(0, 3, 5);
+        cmds.push(Cmd::Reopen);
+        cmds.extend(hourly_cmds(0, 4, 2));
+        cmds.push(Cmd::Delete { series_key: 0 });
+        cmds.push(query.clone());
+        // the series is usable again after deleting it
+        cmds.extend(hourly_cmds(0, 2, 3));
+        cmds.push(query.clone());
+        cmds.push(Cmd::Reopen);
+        cmds.push(query);
+        kelpie_eq_fake_with(Backing::Memory, &cmds)?;
+        kelpie_eq_fake_with(Backing::Path, &cmds)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_delete_time_range() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let query = Cmd::Query {
+            series_key: 0,
+            start: 0,
+            stop: 6 * hour,
+        };
+        let delete = |start, stop| Cmd::DeleteRange {
+            series_key: 0,
+            start,
+            stop,
+        };
+        let mut cmds = hourly_cmds(0, 6, 4);
+        cmds.push(delete(hour, hour));
+        cmds.push(query.clone());
+        // rewrites the first chunk, empties the last one and covers the ones between
+        cmds.push(delete(2000, 3 * hour + hour / 2));
+        cmds.push(query.clone());
+        cmds.push(Cmd::Reopen);
+        cmds.push(query.clone());
+        // the unflushed chunk
+        cmds.extend(hourly_cmds(0, 6, 4).into_iter().skip(20));
+        cmds.push(delete(5 * hour, 5 * hour + 1));
+        cmds.push(query.clone());
+        cmds.push(Cmd::Reopen);
+        cmds.push(query);
+        kelpie_eq_fake_with(Backing::Memory, &cmds)?;
+        kelpie_eq_fake_with(Backing::Path, &cmds)?;
+
+        let mut kelpie = Kelpie::new_memory()?;
+        for cmd in hourly_cmds(0, 6, 4) {
+            if let Cmd::Insert { series_key, point } = cmd {
+                kelpie.insert(series_key, point)?;
+            }
+        }
+        kelpie.save_all()?;
+        kelpie.delete_range(0, 2000, 3 * hour + hour / 2)?;
+        let starts: Vec<i64> = kelpie
+            .chunk_store
+            .list_chunks(0)?
+            .iter()
+            .map(|meta| meta.start)
+            .collect();
+        assert_eq!(starts, vec![0, 4 * hour, 5 * hour]);
+        assert_eq!(kelpie.query(0, 0, hour)?.data.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn should_report_misaligned_chunks() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        for cmd in hourly_cmds(0, 3, 10) {
+            if let Cmd::Insert { series_key, point } = cmd {
+                kelpie.insert(series_key, point)?;
+            }
+        }
+        kelpie.insert(
+            1,
+            DataPoint {
+                time: i64::MAX - 1,
+                value: 1.0,
+            },
+        )?;
+        kelpie.flush_all()?;
+        kelpie.compact(0, CompactionTarget::MaxPoints(15))?;
+        if kelpie.assert_aligned().is_err() {
+            Err("store should be aligned")?;
+        }
+
+        let chunk = Chunk::compress_series(&RawSeries::new());
+        kelpie.chunk_store.set_chunk(2, 10, 3_600_010, &chunk)?;
+        kelpie.chunk_store.set_chunk(2, 0, 10, &chunk)?;
+        let Err(AlignmentError::Misaligned(misaligned)) = kelpie.assert_aligned() else {
+            return Err("store should be misaligned".into());
+        };
+        let misaligned: Vec<(i64, i64)> = misaligned
+            .iter()
+            .map(|meta| (meta.start, meta.stop))
+            .collect();
+        assert_eq!(misaligned, vec![(0, 10), (10, 3_600_010)]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_flush_without_evicting() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let mut kelpie = Kelpie::with_store(
+            CountingStore::new(SqliteChunkStore::new_path(temp.path())?),
+            ScheduleConfig::default(),
+        );
+        for series_key in [0, 1] {
+            kelpie.insert(
+                series_key,
+                DataPoint {
+                    time: 10,
+                    value: 1.0,
+                },
+            )?;
+        }
+        kelpie.flush(0)?;
+        let other = Kelpie::new_path(temp.path())?;
+        assert_eq!(other.query(0, 0, 100)?.data.len(), 1);
+        assert!(other.query(1, 0, 100)?.data.is_empty());
+
+        kelpie.chunk_store.chunks_read.set(0);
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 20,
+                value: 1.0,
+            },
+        )?;
+        assert!(!kelpie.would_create_chunk(0, 20));
+        assert_eq!(kelpie.chunk_store.chunks_read.get(), 0);
+
+        kelpie.flush_all()?;
+        assert_eq!(other.query(0, 0, 100)?.data.len(), 2);
+        assert_eq!(other.query(1, 0, 100)?.data.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn should_flush_on_drop() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let mut fake = KelpieFake::new();
+        {
+            let mut kelpie = Kelpie::new_path(temp.path())?;
+            for time in [10, 3_600_000 + 10, 3_600_000 + 20] {
+                kelpie.insert(0, DataPoint { time, value: 1.0 })?;
+                fake.insert(0, DataPoint { time, value: 1.0 })?;
+            }
+        }
+        let kelpie = Kelpie::new_path(temp.path())?;
+        if kelpie.query(0, 0, 7_200_000)? != fake.query(0, 0, 7_200_000)? {
+            Err("unflushed points lost on drop")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_persist_and_report_errors_on_close() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let mut fake = KelpieFake::new();
+        let mut kelpie = Kelpie::new_path(temp.path())?;
+        for series_key in [0, 99] {
+            for time in [10, 3_600_000 + 10] {
+                kelpie.insert(series_key, DataPoint { time, value: 1.0 })?;
+                fake.insert(series_key, DataPoint { time, value: 1.0 })?;
+            }
+        }
+        kelpie.close()?;
+
+        let mut kelpie = Kelpie::new_path(temp.path())?;
+        for series_key in [0, 99] {
+            if kelpie.query(series_key, 0, 7_200_000)? != fake.query(series_key, 0, 7_200_000)? {
+                Err("not persisted by close")?;
+            }
+        }
+
+        kelpie.chunk_store.connection().execute(
+            "CREATE TRIGGER fail BEFORE INSERT ON chunks WHEN NEW.series = 99 BEGIN SELECT RAISE(ABORT, 'fail'); END",
+        )?;
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 20,
+                value: 2.0,
+            },
+        )?;
+        kelpie.insert(
+            99,
+            DataPoint {
+                time: 20,
+                value: 2.0,
+            },
+        )?;
+        if kelpie.close().is_ok() {
+            Err("failed write should be reported")?;
+        }
+        let kelpie = Kelpie::new_path(temp.path())?;
+        if kelpie.query(0, 0, 100)?.data.len() != 2 {
+            Err("other series should still be written")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_keep_chunks_cached_when_saving_fails() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let point = DataPoint {
+            time: 10,
+            value: 1.0,
+        };
+        kelpie.insert(0, point)?;
+        kelpie.chunk_store.connection().execute(
+            "CREATE TRIGGER fail BEFORE INSERT ON chunks BEGIN SELECT RAISE(ABORT, 'fail'); END",
+        )?;
+        if kelpie.compact_all(CompactionTarget::MaxPoints(10)).is_ok() {
+            Err("failed save should be reported by compaction")?;
+        }
+        if kelpie.stream_ingest([(1, point)]).is_ok() {
+            Err("failed save should be reported by stream_ingest")?;
+        }
+        assert_eq!(kelpie.query(0, 0, 100)?.points(), [point]);
+        assert!(!kelpie.would_create_chunk(0, 10));
+        Ok(())
+    }
+
+    #[test]
+    fn should_copy_and_rename_series() -> Result<(), Box<dyn std::error::Error>> {
+        let (start, stop) = (0, 10 * 3_600_000);
+        let mut kelpie = Kelpie::new_memory()?;
+        let mut fake = KelpieFake::new();
+        for time in [10, 3_600_000 + 10, 5 * 3_600_000] {
+            kelpie.insert(0, DataPoint { time, value: 1.0 })?;
+            fake.insert(0, DataPoint { time, value: 1.0 })?;
+        }
+        kelpie.set_series_metadata(0, "{}")?;
+        kelpie.insert(
+            2,
+            DataPoint {
+                time: 0,
+                value: 2.0,
+            },
+        )?;
+
+        kelpie.copy_series(0, 1)?;
+        if kelpie.query(1, start, stop)? != fake.query(0, start, stop)? {
+            Err("copy not matching")?;
+        }
+        if kelpie.query(0, start, stop)? != fake.query(0, start, stop)? {
+            Err("source changed by copy")?;
+        }
+        if kelpie.get_series_metadata(1)?.as_deref() != Some("{}") {
+            Err("metadata not copied")?;
+        }
+        if !matches!(
+            kelpie.copy_series(0, 2),
+            Err(SetChunkError::SeriesNotEmpty(2))
+        ) {
+            Err("copying onto existing data should fail")?;
+        }
+
+        kelpie.rename_series(1, 3)?;
+        if kelpie.query(3, start, stop)? != fake.query(0, start, stop)? {
+            Err("rename not matching")?;
+        }
+        if !kelpie.query(1, start, stop)?.data.is_empty() {
+            Err("renamed series should be gone")?;
+        }
+
+        // the source is flushed first, which may fail
+        kelpie.insert(
+            3,
+            DataPoint {
+                time: 20,
+                value: 3.0,
+            },
+        )?;
+        kelpie.chunk_store.connection().execute(
+            "CREATE TRIGGER fail BEFORE INSERT ON chunks BEGIN SELECT RAISE(ABORT, 'fail'); END",
+        )?;
+        if kelpie.copy_series(3, 4).is_ok() {
+            Err("failed flush should be reported")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_truncate_all_series() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let mut kelpie = Kelpie::new_path(temp.path())?;
+        for series_key in 0..3 {
+            kelpie.insert(
+                series_key,
+                DataPoint {
+                    time: 10,
+                    value: 1.0,
+                },
+            )?;
+        }
+        kelpie.flush_all()?;
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 20,
+                value: 1.0,
+            },
+        )?;
+        kelpie.set_series_metadata(1, "{}")?;
+        kelpie.truncate()?;
+        for series_key in 0..3 {
+            if !kelpie.query(series_key, 0, 100)?.data.is_empty() {
+                Err("series should be empty")?;
+            }
+            if kelpie.chunk_store.time_bounds(series_key)?.is_some() {
+                Err("store should be empty")?;
+            }
+        }
+        if kelpie.get_series_metadata(1)?.is_some() {
+            Err("metadata should be empty")?;
+        }
+
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 30,
+                value: 2.0,
+            },
+        )?;
+        kelpie.flush_all()?;
+        drop(kelpie);
+        let kelpie = Kelpie::new_path(temp.path())?;
+        if kelpie.query(0, 0, 100)?.serial().len() != 1 {
+            Err("store should be usable after truncate")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_query_last_n_points() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let chunk_size = 3_600_000;
+        for chunk in 0..4 {
+            for i in 0..10 {
+                let time = chunk * chunk_size + i * 1000;
+                kelpie.insert(0, DataPoint { time, value: 1.0 })?;
+            }
+        }
+        kelpie.flush_all()?;
+        // the oldest chunk is unreadable, so touching it would panic
+        kelpie
+            .chunk_store
+            .set_chunk(0, 0, chunk_size, &corrupt_chunk())?;
+
+        let res = kelpie.query_last_n(0, 15)?;
+        let times: Vec<i64> = res.data.keys().copied().collect();
+        let expected: Vec<i64> = (5..10)
+            .map(|i| 2 * chunk_size + i * 1000)
+            .chain((0..10).map(|i| 3 * chunk_size + i * 1000))
+            .collect();
+        assert_eq!(times, expected);
+        assert!(kelpie.query_last_n(0, 0)?.data.is_empty());
+        assert!(kelpie.query_last_n(1, 10)?.data.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_rank_series_efficiency() -> Result<(), Box<dyn std::error::Error>> {
+        use rand::prelude::*;
+        use rand::rngs::SmallRng;
+
+        let mut kelpie = Kelpie::new_memory()?;
+        let mut rng = SmallRng::seed_from_u64(0xdeadbeef);
+        if !kelpie.series_efficiency(0)?.is_nan() {
+            Err("empty series should have no efficiency")?;
+        }
+        for i in 0..10_000 {
+            let time = i * 1000;
+            kelpie.insert(
+                0,
+                DataPoint {
+                    time,
+                    value: i as f64,
+                },
+            )?;
+            kelpie.insert(
+                1,
+                DataPoint {
+                    time,
+                    value: f64::from_bits(rng.gen::<u64>() >> 2),
+                },
+            )?;
+        }
+        let monotonic = kelpie.series_efficiency(0)?;
+        let random = kelpie.series_efficiency(1)?;
+        if monotonic * 4.0 > random {
+            Err(format!("monotonic {monotonic} should beat random {random}"))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_skip_chunks_by_value_range() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let mut kelpie = counting_kelpie(ScheduleConfig::default())?;
+        // chunk `i` holds the values `10 * i` to `10 * i + 9`
+        for i in 0..3 {
+            for j in 0..10 {
+                let point = DataPoint {
+                    time: i * hour + j,
+                    value: (10 * i + j) as f64,
+                };
+                kelpie.insert(0, point)?;
+            }
+        }
+        kelpie.flush_all()?;
+        for predicate in [
+            ValuePredicate::Gt(25.0),
+            ValuePredicate::Ge(10.0),
+            ValuePredicate::Lt(0.0),
+            ValuePredicate::Le(9.0),
+            ValuePredicate::Between(5.0, 15.0),
+        ] {
+            let expected: RawSeries = (kelpie.query(0, 0, 3 * hour)?)
+                .iter()
+                .filter(|point| predicate.matches(point.value))
+                .collect::<Vec<_>>()
+                .into();
+            let res = kelpie.query_value_filtered(0, 0, 3 * hour, predicate)?;
+            assert_eq!(res, expected, "{predicate:?}");
+        }
+
+        kelpie.chunk_store.chunks_read.set(0);
+        let res = kelpie.query_value_filtered(0, 0, 3 * hour, ValuePredicate::Gt(25.0))?;
+        assert_eq!(res.len(), 4);
+        assert_eq!(kelpie.chunk_store.chunks_read.get(), 1);
+        kelpie.query_value_filtered(0, 0, 3 * hour, ValuePredicate::Gt(100.0))?;
+        assert_eq!(kelpie.chunk_store.chunks_read.get(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn should_filter_overlapping_chunks_like_query() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let chunk = |value: f64| {
+            let series: RawSeries = vec![DataPoint { time: 50, value }].into();
+            Chunk::compress_series(&series)
+        };
+        kelpie.chunk_store.set_chunk(0, 40, 60, &chunk(3.0))?;
+        kelpie.chunk_store.set_chunk(0, 0, 100, &chunk(2.0))?;
+        kelpie.chunk_store.set_chunk(0, 0, 60, &chunk(1.0))?;
+        // the point of the skipped chunk is merged last and doesn't match
+        assert!(kelpie
+            .query_value_filtered(0, 0, 100, ValuePredicate::Lt(2.5))?
+            .is_empty());
+        let res = kelpie.query_value_filtered(0, 0, 100, ValuePredicate::Gt(2.5))?;
+        assert_eq!(res.get(50), Some(3.0));
+
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 50,
+                value: 0.0,
+            },
+        )?;
+        assert!(kelpie
+            .query_value_filtered(0, 0, 100, ValuePredicate::Gt(2.5))?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_stop_query_at_last_chunk() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = counting_kelpie(ScheduleConfig::default())?;
+        let mut fake = KelpieFake::new();
+        for time in [10 * 3_600_000 + 10, 11 * 3_600_000 + 10] {
+            kelpie.insert(0, DataPoint { time, value: 1.0 })?;
+            fake.insert(0, DataPoint { time, value: 1.0 })?;
+        }
+        kelpie.flush_all()?;
+        kelpie.chunk_store.chunks_read.set(0);
+        let (start, stop) = (0, 1_000_000 * 3_600_000);
+        if kelpie.query(0, start, stop)? != fake.query(0, start, stop)? {
+            Err("not matching")?;
+        }
+        assert_eq!(kelpie.chunk_store.chunks_read.get(), 2);
+        if !kelpie.query(1, start, i64::MAX)?.data.is_empty() {
+            Err("unknown series should be empty")?;
+        }
+        assert_eq!(kelpie.chunk_store.chunks_read.get(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn should_query_in_descending_order() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = counting_kelpie(ScheduleConfig::new(100)?)?;
+        // stored without going through the cache, in chunks of 100
+        for chunk_start in (0..900).step_by(100) {
+            let points: Vec<DataPoint> = (chunk_start..chunk_start + 100)
+                .filter(|time| time % 17 == 0)
+                .map(|time| DataPoint {
+                    time,
+                    value: time as f64,
+                })
+                .collect();
+            kelpie.store_chunk(0, chunk_start, chunk_start + 100, &points)?;
+        }
+        // a chunk overlapping two others, which replaces the point at 476 of the one it
+        // starts after
+        let overlap = RawSeries::from(
+            &[DataPoint {
+                time: 476,
+                value: -1.0,
+            }][..],
+        );
+        kelpie
+            .chunk_store
+            .set_chunk(0, 450, 550, &Chunk::compress_series(&overlap))?;
+
+        for (start, stop) in [(0, 1000), (100, 101), (90, 610), (470, 520)] {
+            let mut expected: Vec<DataPoint> = kelpie.query(0, start, stop)?.iter().collect();
+            expected.reverse();
+            assert_eq!(kelpie.query_desc(0, start, stop, None)?, expected);
+            for limit in [0, 1, 3, 10] {
+                let res = kelpie.query_desc(0, start, stop, Some(limit))?;
+                assert_eq!(res[..], expected[..limit.min(expected.len())]);
+            }
+        }
+        assert_eq!(
+            kelpie.query_desc(0, 470, 480, Some(1))?[0],
+            DataPoint {
+                time: 476,
+                value: -1.0
+            }
+        );
+
+        // the last two chunks hold the 8 latest points, earlier ones are never read
+        kelpie.chunk_store.chunks_read.set(0);
+        let res = kelpie.query_desc(0, 0, 1000, Some(8))?;
+        let times: Vec<i64> = res.iter().map(|point| point.time).collect();
+        assert_eq!(times, [884, 867, 850, 833, 816, 799, 782, 765]);
+        assert_eq!(kelpie.chunk_store.chunks_read.get(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn should_query_chunks_of_a_previous_chunk_size() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let mut fake = KelpieFake::new();
+        // written with chunks of 1000, then 300, then 5000
+        for (config, times) in [(1000, 0..20), (300, 20..40), (5000, 40..60)] {
+            let mut kelpie =
+                Kelpie::new_path_with_config(temp.path(), ScheduleConfig::new(config)?)?;
+            for time in times.map(|i| i * 170) {
+                let point = DataPoint {
+                    time,
+                    value: time as f64,
+                };
+                kelpie.insert(0, point)?;
+                fake.insert(0, point)?;
+            }
+            kelpie.flush_all()?;
+        }
+
+        let kelpie = Kelpie::new_path_with_config(temp.path(), ScheduleConfig::new(700)?)?;
+        for (start, stop) in [(0, 10_200), (999, 1001), (3000, 3700), (6700, 6900)] {
+            let expected = fake.query(0, start, stop)?;
+            if kelpie.query(0, start, stop)? != expected {
+                Err(format!("query not matching in {start}..{stop}"))?;
+            }
+            let points: Vec<DataPoint> = kelpie
+                .query_iter(0, start, stop)?
+                .collect::<Result<_, _>>()?;
+            if RawSeries::from(points) != expected {
+                Err(format!("scan not matching in {start}..{stop}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_find_points_of_manually_stored_chunk() -> Result<(), Box<dyn std::error::Error>> {
+        let minute = 60_000;
+        let mut kelpie = Kelpie::new_memory()?;
+        let point = DataPoint {
+            time: 45 * minute,
+            value: 1.0,
+        };
+        kelpie.store_chunk(0, 30 * minute, 90 * minute, &[point])?;
+
+        assert_eq!(kelpie.time_bounds(0)?, Some((45 * minute, 45 * minute)));
+        assert!(kelpie.has_data_in(0, 0, 200 * minute)?);
+        assert!(!kelpie.has_data_in(0, 46 * minute, 200 * minute)?);
+        let results = kelpie.query_ranges(0, &[(0, 200 * minute), (0, 45 * minute)])?;
+        let lens: Vec<usize> = results.iter().map(|result| result.data.len()).collect();
+        assert_eq!(lens, vec![1, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_query_manually_stored_chunks() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let mut kelpie = Kelpie::new_memory()?;
+        let mut fake = KelpieFake::new();
+        let sessions = [
+            (0, 10, vec![0, 3, 9]),
+            (10, 5 * hour + 7, vec![10, hour, 2 * hour + 5, 5 * hour + 6]),
+            (
+                9 * hour + 30,
+                9 * hour + 40,
+                vec![9 * hour + 30, 9 * hour + 39],
+            ),
+        ];
+        for (start, stop, times) in sessions {
+            let points: Vec<DataPoint> = times
+                .iter()
+                .map(|&time| DataPoint {
+                    time,
+                    value: time as f64,
+                })
+                .collect();
+            kelpie.store_chunk(0, start, stop, &points)?;
+            for point in points {
+                fake.insert(0, point)?;
+            }
+        }
+        let active = DataPoint {
+            time: 7 * hour + 1,
+            value: 1.0,
+        };
+        kelpie.insert(0, active)?;
+        fake.insert(0, active)?;
+
+        let widths: Vec<i64> = kelpie
+            .chunk_store
+            .list_chunks(0)?
+            .iter()
+            .map(|meta| meta.stop - meta.start)
+            .collect();
+        assert_eq!(widths, vec![10, 5 * hour - 3, 10]);
+        for (start, stop) in [
+            (0, 10 * hour),
+            (5, hour),
+            (hour, 8 * hour),
+            (5 * hour + 7, 9 * hour + 35),
+            (6 * hour, 7 * hour),
+        ] {
+            if kelpie.query(0, start, stop)? != fake.query(0, start, stop)? {
+                Err(format!("not matching in [{start}, {stop})"))?;
+            }
+        }
+
+        // the active chunk is written first, which may fail
+        kelpie.chunk_store.connection().execute(
+            "CREATE TRIGGER fail BEFORE INSERT ON chunks BEGIN SELECT RAISE(ABORT, 'fail'); END",
+        )?;
+        if kelpie.store_chunk(0, 0, 10, &[active]).is_ok() {
+            Err("failed write should be reported")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_query_read_only_database() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let mut fake = KelpieFake::new();
+        {
+            let mut kelpie = Kelpie::new_path(temp.path())?;
+            for time in [10, 3_600_000 + 10] {
+                kelpie.insert(0, DataPoint { time, value: 1.0 })?;
+                fake.insert(0, DataPoint { time, value: 1.0 })?;
+            }
+            kelpie.flush_all()?;
+        }
+        let kelpie = Kelpie::new_path_read_only(temp.path())?;
+        if kelpie.query(0, 0, 10_000_000)? != fake.query(0, 0, 10_000_000)? {
+            Err("not matching")?;
+        }
+        Ok(())
+    }
+
+    // a chunk whose times don't decompress, while its values can still be indexed
+    fn corrupt_chunk() -> Chunk {
+        let empty = Chunk::compress_series(&RawSeries::new());
+        let (_times, values) = empty.columns().expect("compressed chunks are framed");
+        Chunk::from_columns(&[1, 2, 3], values)
+    }
+
+    #[test]
+    fn should_return_error_for_corrupt_chunk() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let corrupt = corrupt_chunk();
+        kelpie.chunk_store.set_chunk(0, 0, 3_600_000, &corrupt)?;
+        kelpie.insert(
+            1,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        )?;
+
+        if !matches!(kelpie.query(0, 0, 100), Err(GetChunkError::Corrupt(_))) {
+            Err("corrupt chunk should fail the query")?;
+        }
+        if kelpie.has_data_in(0, 0, 100).is_ok() {
+            Err("corrupt chunk should fail the check")?;
+        }
+        assert_eq!(kelpie.query(1, 0, 100)?.data.len(), 1);
+
+        let point = DataPoint {
+            time: 20,
+            value: 1.0,
+        };
+        if !matches!(kelpie.insert_many(0, &[point]), Err(BatchError::Store(_))) {
+            Err("corrupt chunk should fail the insert")?;
+        }
+        // the corrupt chunk is still there and not replaced by an empty one
+        kelpie.flush_all()?;
+        let (_, chunk) = kelpie.chunk_store.get_chunk(0, 0, 3_600_000)?.unwrap();
+        assert_eq!(chunk.as_bytes(), corrupt.as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn should_merge_overlapping_chunks_in_order() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let chunk = |value: f64| {
+            let series: RawSeries = vec![DataPoint { time: 50, value }].into();
+            Chunk::compress_series(&series)
+        };
+        // stored out of merge order on purpose
+        kelpie.chunk_store.set_chunk(0, 40, 60, &chunk(3.0))?;
+        kelpie.chunk_store.set_chunk(0, 0, 100, &chunk(2.0))?;
+        kelpie.chunk_store.set_chunk(0, 0, 60, &chunk(1.0))?;
+        let expected: RawSeries = vec![DataPoint {
+            time: 50,
+            value: 3.0,
+        }]
+        .into();
+        assert_eq!(kelpie.query(0, 0, 100)?, expected);
+
+        // the unflushed chunk is merged after every stored one
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 50,
+                value: 4.0,
+            },
+        )?;
+        assert_eq!(kelpie.query(0, 0, 100)?.data[&50], 4.0);
+        Ok(())
+    }
+
+    #[test]
+    fn should_profile_query() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let mut kelpie = Kelpie::new_memory()?;
+        for time in [10, hour + 10, 2 * hour + 10] {
+            kelpie.insert(0, DataPoint { time, value: 1.0 })?;
+        }
+        let (series, profile) = kelpie.query_profiled(0, 0, 2 * hour)?;
+        assert_eq!(series.data.len(), 2);
+        assert_eq!(profile.chunks_read, 2);
+        assert_eq!(profile.cache_hits, 0);
+        if profile.bytes_read == 0 || profile.decompress_time > profile.total_time {
+            Err(format!("implausible profile {profile:?}"))?;
+        }
+        let (_, profile) = kelpie.query_profiled(0, 0, 3 * hour)?;
+        assert_eq!((profile.chunks_read, profile.cache_hits), (2, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn should_check_for_data_in_window() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let mut kelpie = counting_kelpie(ScheduleConfig::default())?;
+        for time in [hour + 10, 5 * hour + 10, 6 * hour + 10] {
+            kelpie.insert(0, DataPoint { time, value: 1.0 })?;
+        }
+        kelpie.flush_all()?;
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 9 * hour,
+                value: 1.0,
+            },
+        )?;
+
+        kelpie.chunk_store.chunks_read.set(0);
+        assert!(kelpie.has_data_in(0, 0, 10 * hour)?);
+        assert_eq!(kelpie.chunk_store.chunks_read.get(), 1);
+        assert!(!kelpie.has_data_in(0, hour + 11, 5 * hour + 10)?);
+        assert!(kelpie.has_data_in(0, 5 * hour, 5 * hour + 11)?);
+        assert!(kelpie.has_data_in(0, 8 * hour, 10 * hour)?);
+        assert!(!kelpie.has_data_in(0, 10, 5)?);
+        assert!(!kelpie.has_data_in(1, 0, 10 * hour)?);
+
+        // a chunk started empty in append-only mode still has its stored points
+        kelpie.save_all()?;
+        kelpie.set_ingest_mode(IngestMode::AppendOnly);
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: hour + 20,
+                value: 1.0,
+            },
+        )?;
+        assert!(kelpie.has_data_in(0, hour, hour + 11)?);
+        Ok(())
+    }
+
+    #[test]
+    fn should_share_repeated_query_results() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        )?;
+        kelpie.insert(
+            1,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        )?;
+        let first = kelpie.query_shared(0, 0, 100)?;
+        let second = kelpie.query_shared(0, 0, 100)?;
+        assert!(Arc::ptr_eq(&first, &second));
+        let other = kelpie.query_shared(1, 0, 100)?;
+
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 20,
+                value: 2.0,
+            },
+        )?;
+        let third = kelpie.query_shared(0, 0, 100)?;
+        assert!(!Arc::ptr_eq(&first, &third));
+        assert_eq!(third.serial().len(), 2);
+        assert!(Arc::ptr_eq(&other, &kelpie.query_shared(1, 0, 100)?));
+        Ok(())
+    }
+
+    #[test]
+    fn should_query_ranges_reading_shared_chunks_once() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = counting_kelpie(ScheduleConfig::default())?;
+        for cmd in hourly_cmds(0, 4, 100) {
+            if let Cmd::Insert { series_key, point } = cmd {
+                kelpie.insert(series_key, point)?;
+            }
+        }
+        kelpie.save_all()?;
+        let hour = 3_600_000;
+        let ranges = [
+            (10_000, 20_000),
+            (50_000, 60_000),
+            (hour - 5_000, hour + 5_000),
+            (3 * hour, 2 * hour),
+            (3 * hour, 10 * hour),
+        ];
+        kelpie.chunk_store.chunks_read.set(0);
+        let results = kelpie.query_ranges(0, &ranges)?;
+        assert_eq!(kelpie.chunk_store.chunks_read.get(), 3);
+        assert_eq!(results.len(), ranges.len());
+        for (&(start, stop), result) in ranges.iter().zip(&results) {
+            if *result != kelpie.query(0, start, stop)? {
+                Err("not matching")?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_query_last_window() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        if !kelpie.query_last(0, 1000)?.data.is_empty() {
+            Err("empty series should yield nothing")?;
+        }
+        for time in [1_000, 5_000, 3_600_000, 3_600_500, 3_601_000] {
+            kelpie.insert(0, DataPoint { time, value: 1.0 })?;
+        }
+        kelpie.flush_all()?;
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 3_601_500,
+                value: 2.0,
+            },
+        )?;
+
+        assert_eq!(kelpie.time_bounds(0)?, Some((1_000, 3_601_500)));
+        let res = kelpie.query_last(0, 1000)?;
+        let times: Vec<i64> = res.data.keys().copied().collect();
+        assert_eq!(times, vec![3_600_500, 3_601_000, 3_601_500]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_query_last_wallclock_window() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        let day = 24 * 60 * 60 * 1000;
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: now - day,
+                value: 1.0,
+            },
+        )?;
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: now - 10,
+                value: 2.0,
+            },
+        )?;
+
+        let res = kelpie.query_last_wallclock(0, 60 * 1000)?;
+        let times: Vec<i64> = res.data.keys().copied().collect();
+        assert_eq!(times, vec![now - 10]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_write_each_chunk_once_in_insert_many() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        // alternates between two chunks, with one invalid point
+        let mut points: Vec<DataPoint> = (0..100)
+            .map(|i| DataPoint {
+                time: (i % 2) * hour + i,
+                value: i as f64,
+            })
+            .collect();
+        points.push(DataPoint {
+            time: -1,
+            value: 1.0,
+        });
+
+        let mut looped = counting_kelpie(ScheduleConfig::default())?;
+        for &point in &points {
+            looped.try_insert_lossy(0, point)?;
+        }
+        looped.save_all()?;
+
+        let mut batched = counting_kelpie(ScheduleConfig::default())?;
+        batched.insert_many(0, &points)?;
+        batched.save_all()?;
+
+        assert_eq!(looped.chunk_store.writes.get(), 100);
+        assert_eq!(batched.chunk_store.writes.get(), 2);
+        assert_eq!(batched.chunk_store.queries.get(), 2);
+        if batched.query(0, 0, 2 * hour)? != looped.query(0, 0, 2 * hour)? {
+            Err("not matching")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_apply_batch_dedup_policy() -> Result<(), Box<dyn std::error::Error>> {
+        let points = [
+            DataPoint {
+                time: 3_600_010,
+                value: 1.0,
+            },
+            DataPoint {
+                time: 10,
+                value: 2.0,
+            },
+            DataPoint {
+                time: 3_600_010,
+                value: 3.0,
+            },
+        ];
+        for (policy, expected) in [
+            (BatchDedup::KeepFirst, Some(1.0)),
+            (BatchDedup::KeepLast, Some(3.0)),
+            (BatchDedup::Error, None),
+        ] {
+            let mut kelpie = Kelpie::new_memory()?;
+            let mut fake = KelpieFake::new();
+            kelpie.set_batch_dedup(policy);
+            fake.set_batch_dedup(policy);
+            let kelpie_res = kelpie.insert_many(0, &points);
+            let fake_res = fake.insert_many(0, &points);
+            if kelpie_res.is_ok() != fake_res.is_ok() {
+                Err("not matching")?;
+            }
+            let res = kelpie.query(0, 0, 10_000_000)?;
+            if res != fake.query(0, 0, 10_000_000)? {
+                Err("not matching")?;
+            }
+            assert_eq!(res.data.get(&3_600_010).copied(), expected);
+        }
+        Ok(())
+    }
+
+    fn hourly_cmds(series_key: i64, hours: i64, points_per_hour: i64) -> Vec<Cmd> {
+        let mut cmds = vec![];
+        for hour in 0..hours {
+            for i in 0..points_per_hour {
+                let point = DataPoint {
+                    time: hour * 3_600_000 + i * 1000,
+                    value: (hour * i) as f64,
+                };
+                cmds.push(Cmd::Insert { series_key, point });
+            }
+        }
+        cmds
+    }
+
+    #[test]
+    fn should_compact_adjacent_chunks() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmds = hourly_cmds(0, 6, 10);
+        cmds.push(Cmd::Reopen);
+        cmds.push(Cmd::Compact {
+            series_key: 0,
+            target: CompactionTarget::MaxPoints(25),
+        });
+        cmds.push(Cmd::Query {
+            series_key: 0,
+            start: 0,
+            stop: 6 * 3_600_000,
+        });
+        cmds.push(Cmd::Compact {
+            series_key: 0,
+            target: CompactionTarget::MaxPoints(1000),
+        });
+        // write into the middle of the compacted chunk
+        cmds.push(Cmd::Insert {
+            series_key: 0,
+            point: DataPoint {
+                time: 2 * 3_600_000 + 500,
+                value: 42.0,
+            },
+        });
+        cmds.push(Cmd::Query {
+            series_key: 0,
+            start: 0,
+            stop: 6 * 3_600_000,
+        });
+        cmds.push(Cmd::Reopen);
+        cmds.push(Cmd::Query {
+            series_key: 0,
+            start: 3_600_000 + 10,
+            stop: 4 * 3_600_000,
+        });
+        kelpie_eq_fake_with(Backing::Memory, &cmds)?;
+        kelpie_eq_fake_with(Backing::Path, &cmds)?;
+
+        let mut kelpie = Kelpie::new_memory()?;
+        for cmd in hourly_cmds(0, 6, 10) {
+            if let Cmd::Insert { series_key, point } = cmd {
+                kelpie.insert(series_key, point)?;
+            }
+        }
+        kelpie.save_all()?;
+        assert_eq!(kelpie.compact(0, CompactionTarget::MaxPoints(25))?, 3);
+        assert_eq!(kelpie.chunk_store.list_chunks(0)?.len(), 3);
+        assert_eq!(kelpie.compact(0, CompactionTarget::MaxPoints(1000))?, 2);
+        assert_eq!(kelpie.chunk_store.list_chunks(0)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn should_compact_within_byte_budget() -> Result<(), Box<dyn std::error::Error>> {
+        use rand::prelude::*;
+        use rand::rngs::SmallRng;
+
+        let mut rng = SmallRng::seed_from_u64(11);
+        let mut kelpie = Kelpie::new_memory()?;
+        let mut fake = KelpieFake::new();
+        for i in 0..20 * 200 {
+            let point = DataPoint {
+                time: i * 18_000,
+                value: rng.gen_range(-100.0..100.0),
+            };
+            kelpie.insert(0, point)?;
+            fake.insert(0, point)?;
+        }
+        kelpie.flush_all()?;
+        let chunk_size = |kelpie: &Kelpie, meta: &ChunkMeta| -> Result<usize, GetChunkError> {
+            let (_, chunk) = kelpie
+                .chunk_store
+                .get_chunk(0, meta.start, meta.stop)?
+                .unwrap();
+            Ok(chunk.as_bytes().len())
+        };
+        let before = kelpie.chunk_store.list_chunks(0)?;
+        let largest = before
+            .iter()
+            .map(|meta| chunk_size(&kelpie, meta))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .max()
+            .unwrap();
+        let max_bytes = largest * 7 / 2;
+
+        let removed = kelpie.compact(0, CompactionTarget::MaxBytes(max_bytes))?;
+        let after = kelpie.chunk_store.list_chunks(0)?;
+        assert_eq!(before.len() - removed, after.len());
+        if after.len() * 2 > before.len() {
+            Err("compaction should merge chunks")?;
+        }
+        for meta in &after {
+            if chunk_size(&kelpie, meta)? > max_bytes {
+                Err("chunk over byte budget")?;
+            }
+        }
+        if kelpie.query(0, 0, 20 * 3_600_000)? != fake.query(0, 0, 20 * 3_600_000)? {
+            Err("not matching")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_split_runs_merged_over_byte_budget() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory_with_config(ScheduleConfig::new(100)?)?;
+        let mut run = vec![];
+        let mut data = RawSeries::new();
+        for chunk_start in (0..400).step_by(100) {
+            let points: Vec<DataPoint> = (chunk_start..chunk_start + 100)
+                .map(|time| DataPoint {
+                    time,
+                    value: ((time * 7919) % 1000) as f64 / 7.0,
+                })
+                .collect();
+            kelpie.store_chunk(0, chunk_start, chunk_start + 100, &points)?;
+            run.extend(kelpie.chunk_store.list_chunks(0)?.last().copied());
+            data.data
+                .extend(points.iter().map(|point| (point.time, point.value)));
+        }
+        let pair = |start: i64| {
+            let mut pair = data.clone();
+            pair.data
+                .retain(|&time, _| (start..start + 200).contains(&time));
+            kelpie.compress(&pair).as_bytes().len()
+        };
+        let max_bytes = pair(0).max(pair(200));
+        if kelpie.compress(&data).as_bytes().len() <= max_bytes {
+            Err("the whole run should be over the budget")?;
+        }
+
+        // expected to fit, but found not to once merged
+        let target = CompactionTarget::MaxBytes(max_bytes);
+        assert_eq!(kelpie.write_merged(0, target, &run, data.clone())?, 2);
+        let bounds: Vec<(i64, i64)> = (kelpie.chunk_store.list_chunks(0)?)
+            .iter()
+            .map(|meta| (meta.start, meta.stop))
+            .collect();
+        assert_eq!(bounds, [(0, 200), (200, 400)]);
+        assert_eq!(kelpie.query(0, 0, 400)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn should_compact_all_series() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let mut fake = KelpieFake::new();
+        for series_key in 0..4 {
+            for cmd in hourly_cmds(series_key, 8, 5) {
+                if let Cmd::Insert { series_key, point } = cmd {
+                    kelpie.insert(series_key, point)?;
+                    fake.insert(series_key, point)?;
+                }
+            }
+        }
+        let report = kelpie.compact_all(CompactionTarget::MaxPoints(20))?;
+        assert_eq!(report.series_compacted, 4);
+        assert_eq!(report.chunks_before, 32);
+        assert_eq!(report.chunks_after, 8);
+        assert_eq!(kelpie.chunk_store.list_all_chunks()?.len(), 8);
+        if report.bytes_reclaimed == 0 {
+            Err("merging chunks should save space")?;
+        }
+        for series_key in 0..4 {
+            if kelpie.query(series_key, 0, 8 * 3_600_000)?
+                != fake.query(series_key, 0, 8 * 3_600_000)?
+            {
+                Err("not matching")?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_auto_compact_past_threshold() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let mut fake = KelpieFake::new();
+        kelpie.set_auto_compact(Some(3));
+        for cmd in hourly_cmds(0, 10, 10) {
+            if let Cmd::Insert { series_key, point } = cmd {
+                kelpie.insert(series_key, point)?;
+                fake.insert(series_key, point)?;
+            }
+        }
+        // the active chunk is never compacted
+        let active = kelpie.cached(0).last().ok_or("no active chunk")?.schedule;
+        assert_eq!(active.chunk_start, 9 * 3_600_000);
+        assert_eq!(active.chunk_end, 10 * 3_600_000);
+        kelpie.flush_all()?;
+        let chunks = kelpie.chunk_store.list_chunks(0)?.len();
+        if chunks > 4 {
+            Err(format!("expected at most 4 chunks, got {chunks}"))?;
+        }
+        if kelpie.query(0, 0, 10 * 3_600_000)? != fake.query(0, 0, 10 * 3_600_000)? {
+            Err("not matching")?;
+        }
+
+        // a compaction that can't replace its run fails the insert that triggered it
+        kelpie.chunk_store.connection().execute(
+            "CREATE TRIGGER fail BEFORE DELETE ON chunks BEGIN SELECT RAISE(ABORT, 'fail'); END",
+        )?;
+        kelpie.set_auto_compact(Some(1));
+        let point = DataPoint {
+            time: 10 * 3_600_000,
+            value: 1.0,
+        };
+        if !matches!(kelpie.insert(0, point), Err(InsertError::Write(_))) {
+            Err("failed compaction should be reported")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_compact_series_idempotently() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory_with_config(ScheduleConfig::new(4)?)?;
+        let mut fake = KelpieFake::new();
+        kelpie.set_compaction_target(CompactionTarget::MaxPoints(10));
+        for time in (0..100).step_by(3) {
+            let point = DataPoint {
+                time,
+                value: time as f64,
+            };
+            kelpie.insert(0, point)?;
+            fake.insert(0, point)?;
+        }
+        kelpie.save_all()?;
+        let before = kelpie.chunk_store.list_chunks(0)?.len();
+        kelpie.compact_series(0)?;
+        let compacted = kelpie.chunk_store.list_chunks(0)?;
+        assert!(compacted.len() < before);
+        assert!(compacted
+            .windows(2)
+            .all(|pair| pair[0].stop <= pair[1].start));
+        assert_eq!(kelpie.query(0, 0, 100)?, fake.query(0, 0, 100)?);
+
+        kelpie.compact_series(0)?;
+        assert_eq!(kelpie.chunk_store.list_chunks(0)?, compacted);
+        assert_eq!(kelpie.query(0, 0, 100)?, fake.query(0, 0, 100)?);
+        Ok(())
+    }
+
+    #[test]
+    fn should_recompress_series() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory_with_config(ScheduleConfig::new(1000)?)?;
+        for time in 0..5000 {
+            kelpie.insert(
+                0,
+                DataPoint {
+                    time,
+                    value: (time % 100) as f64 / 7.0,
+                },
+            )?;
+        }
+        kelpie.flush_all()?;
+        let expected = kelpie.query(0, 0, 5000)?;
+        let (chunks, bytes) = kelpie.stored_size(0)?;
+
+        let stats = kelpie.recompress_series(0, 0)?;
+        assert_eq!((stats.chunks, stats.bytes_before), (chunks, bytes));
+        assert_eq!(kelpie.stored_size(0)?, (chunks, stats.bytes_after));
+        assert_eq!(kelpie.query(0, 0, 5000)?, expected);
+
+        let stats = kelpie.recompress_series(0, MAX_COMPRESSION_LEVEL)?;
+        assert!(stats.ratio().ok_or("nothing recompressed")? < 1.0);
+        assert_eq!(kelpie.query(0, 0, 5000)?, expected);
+        assert_eq!(kelpie.recompress_series(1, 0)?.ratio(), None);
+
+        // compaction writes with the instance's level, not the one recompressed with
+        kelpie.set_compression_level(0)?;
+        kelpie.compact_series(0)?;
+        // the latest chunk is left for inserts
+        let (_, merged) = kelpie
+            .chunk_store
+            .get_chunk(0, 0, 4000)?
+            .ok_or("chunks not merged")?;
+        let expected = Chunk::compress_series_with_level(&kelpie.query(0, 0, 4000)?, 0);
+        assert_eq!(merged.as_bytes(), expected.as_bytes());
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn should_write_chunks_with_secondary_compression() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        kelpie.set_secondary_compression(Some(ZstdLevel(19)));
+        for time in 0..5000 {
+            kelpie.insert(
+                0,
+                DataPoint {
+                    time: time * 7,
+                    value: (time % 100) as f64 / 7.0,
+                },
+            )?;
+        }
+        kelpie.flush_all()?;
+        let (_meta, chunk) = kelpie
+            .chunk_store
+            .get_chunk(0, 0, 3_600_000)?
+            .ok_or("chunk was not written")?;
+        assert_eq!(chunk.as_bytes()[4], ZSTD_CHUNK_FORMAT_VERSION);
+        assert_eq!(chunk.decompress()?.len(), 5000);
+
+        kelpie.set_secondary_compression(None);
+        let stats = kelpie.recompress_series(0, DEFAULT_COMPRESSION_LEVEL)?;
+        assert_eq!(stats.chunks, 1);
+        let (_meta, chunk) = kelpie
+            .chunk_store
+            .get_chunk(0, 0, 3_600_000)?
+            .ok_or("chunk was not rewritten")?;
+        assert_eq!(chunk.as_bytes()[4], CHUNK_FORMAT_VERSION);
+        assert_eq!(kelpie.query(0, 0, 3_600_000)?.len(), 5000);
+        Ok(())
+    }
+
+    #[test]
+    fn should_estimate_stored_chunk_size() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        for time in 0..2000 {
+            kelpie.insert(
+                0,
+                DataPoint {
+                    time: time * 13,
+                    value: (time % 37) as f64 * 0.25,
+                },
+            )?;
+        }
+        kelpie.flush_all()?;
+        let (_meta, chunk) = kelpie
+            .chunk_store
+            .get_chunk(0, 0, 3_600_000)?
+            .ok_or("chunk was not written")?;
+        let series = kelpie.query(0, 0, 3_600_000)?;
+        assert_eq!(series.estimated_compressed_size(), chunk.as_bytes().len());
+        assert!(series.estimated_compressed_size() < series.serial_size_hint());
+        Ok(())
+    }
+
+    #[test]
+    fn should_write_chunks_at_configured_level() -> Result<(), Box<dyn std::error::Error>> {
+        let mut sizes = vec![];
+        for level in [1, 12] {
+            let mut kelpie = Kelpie::new_memory()?;
+            kelpie.set_compression_level(level)?;
+            for time in 0..5000 {
+                kelpie.insert(
+                    0,
+                    DataPoint {
+                        time: time * 7,
+                        value: (time % 100) as f64 / 7.0,
+                    },
+                )?;
+            }
+            kelpie.flush_all()?;
+            let (_meta, chunk) = kelpie
+                .chunk_store
+                .get_chunk(0, 0, 3_600_000)?
+                .ok_or("chunk was not written")?;
+            assert_eq!(chunk.decompress()?, kelpie.query(0, 0, 3_600_000)?);
+            assert_eq!(kelpie.query(0, 0, 3_600_000)?.len(), 5000);
+            sizes.push(chunk.as_bytes().len());
+        }
+        assert!(sizes[1] <= sizes[0]);
+        let mut kelpie = Kelpie::new_memory()?;
+        assert_eq!(
+            kelpie.set_compression_level(MAX_COMPRESSION_LEVEL + 1),
+            Err(CompressionLevelError::AboveMax(13))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_round_trip_csv() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory_with_config(ScheduleConfig::new(100)?)?;
+        for time in 0..500 {
+            kelpie.insert(
+                0,
+                DataPoint {
+                    time: time * 3,
+                    value: time as f64 / 7.0 - 20.0,
+                },
+            )?;
+        }
+        let mut csv = vec![];
+        kelpie.export_csv(0, 50, 1000, &mut csv)?;
+        assert!(csv.starts_with(b"time,value\n51,"));
+
+        let mut imported = Kelpie::new_memory()?;
+        assert_eq!(imported.import_csv(7, csv.as_slice())?, 317);
+        assert_eq!(imported.query(7, 0, 2000)?, kelpie.query(0, 50, 1000)?);
+        Ok(())
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn should_query_arrow_batch() -> Result<(), Box<dyn std::error::Error>> {
+        use arrow_array::cast::AsArray;
+        use arrow_array::types::{Float64Type, TimestampMillisecondType};
+        use arrow_schema::{DataType, TimeUnit};
+
+        let mut kelpie = Kelpie::new_memory_with_config(ScheduleConfig::new(100)?)?;
+        for time in 0..300 {
+            kelpie.insert(
+                0,
+                DataPoint {
+                    time,
+                    value: time as f64 / 2.0,
+                },
+            )?;
+        }
+        let batch = kelpie.query_arrow(0, 50, 250)?;
+        let schema = batch.schema();
+        assert_eq!(
+            schema.field(0).data_type(),
+            &DataType::Timestamp(TimeUnit::Millisecond, None)
+        );
+        assert_eq!(schema.field(1).data_type(), &DataType::Float64);
+        assert_eq!(batch.num_rows(), 200);
+        let times = batch.column(0).as_primitive::<TimestampMillisecondType>();
+        assert_eq!((times.value(0), times.value(199)), (50, 249));
+        let values = batch.column(1).as_primitive::<Float64Type>();
+        assert_eq!(values.value(199), 124.5);
+        assert_eq!(kelpie.query_arrow(0, 300, 400)?.num_rows(), 0);
+        Ok(())
+    }
+
+    fn unflushed_chunk_cmds(active_hour: i64) -> Vec<Cmd> {
+        let hour = 3_600_000;
+        let mut cmds = hourly_cmds(0, 5, 10);
+        cmds.push(Cmd::Reopen);
+        for (time, value) in [(active_hour * hour + 500, -1.0), (active_hour * hour, -2.0)] {
+            cmds.push(Cmd::Insert {
+                series_key: 0,
+                point: DataPoint { time, value },
+            });
+        }
+        cmds.push(Cmd::Query {
+            series_key: 0,
+            start: 0,
+            stop: 5 * hour,
+        });
+        cmds.push(Cmd::Query {
+            series_key: 0,
+            start: hour / 2,
+            stop: 5 * hour - hour / 2,
+        });
+        cmds
+    }
+
+    #[test]
+    fn should_query_unflushed_chunk_at_start() -> Result<(), Box<dyn std::error::Error>> {
+        kelpie_eq_fake_with(Backing::Memory, &unflushed_chunk_cmds(0))?;
+        kelpie_eq_fake_with(Backing::Path, &unflushed_chunk_cmds(0))?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_query_unflushed_chunk_in_middle() -> Result<(), Box<dyn std::error::Error>> {
+        kelpie_eq_fake_with(Backing::Memory, &unflushed_chunk_cmds(2))?;
+        kelpie_eq_fake_with(Backing::Path, &unflushed_chunk_cmds(2))?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_query_unflushed_chunk_at_end() -> Result<(), Box<dyn std::error::Error>> {
+        kelpie_eq_fake_with(Backing::Memory, &unflushed_chunk_cmds(4))?;
+        kelpie_eq_fake_with(Backing::Path, &unflushed_chunk_cmds(4))?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_stream_ingest() -> Result<(), Box<dyn std::error::Error>> {
+        use rand::prelude::*;
+        use rand::rngs::SmallRng;
+
+        let mut rng = SmallRng::seed_from_u64(0xdeadbeef);
+        const SERIES: i64 = 4;
+        let mut time = 0;
+        let mut points = vec![];
+        for _ in 0..20_000 {
+            time += rng.gen_range(400..1100);
+            let series_key = rng.gen_range(0..SERIES);
+            let value = rng.gen_range(-100.0..100.0);
+            points.push((series_key, DataPoint { time, value }));
+        }
+        // an unsorted straggler into an already written chunk, and an invalid point
+        points.push((
+            0,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        ));
+        points.push((
+            0,
+            DataPoint {
+                time: -1,
+                value: 1.0,
+            },
+        ));
+
+        // every chunk of the sorted stream is written once, the straggler's chunk twice
+        let chunks: HashSet<(i64, i64)> = points
+            .iter()
+            .map(|(series_key, point)| (*series_key, point.time.div_euclid(3_600_000)))
+            .filter(|&(_, hour)| hour >= 0)
+            .collect();
+
+        let mut fake = KelpieFake::new();
+        for &(series_key, point) in &points {
+            fake.try_insert_lossy(series_key, point)?;
+        }
+        let mut streamed = Kelpie::new_memory()?;
+        streamed.insert(
+            1,
+            DataPoint {
+                time: 20,
+                value: 2.0,
+            },
+        )?;
+        fake.insert(
+            1,
+            DataPoint {
+                time: 20,
+                value: 2.0,
+            },
+        )?;
+        let report = streamed.stream_ingest(points.iter().copied())?;
+
+        assert_eq!(report.accepted, points.len() - 1);
+        assert_eq!(report.rejected, 1);
+        assert_eq!(report.chunks_written, chunks.len() + 1);
+        for series_key in 0..SERIES {
+            if streamed.query(series_key, 0, time + 1)? != fake.query(series_key, 0, time + 1)? {
+                Err("not matching")?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_scan_only_chunks_of_prefetched_run() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory_with_config(ScheduleConfig::new(10)?)?;
+        let at = |time, value| DataPoint { time, value };
+        kelpie.store_chunk(0, 0, 20, &[at(15, 1.0)])?;
+        kelpie.chunk_store.set_chunk(
+            0,
+            0,
+            10,
+            &Chunk::compress_series(&vec![at(5, 1.0)].into()),
+        )?;
+        // loads the chunk spanning 0 to 20, whose run is cut short by the chunk before
+        kelpie.insert(0, at(16, 2.0))?;
+        kelpie.set_prefetch_chunks(8);
+        let chunks: Vec<Vec<i64>> = kelpie
+            .iter_chunks(0, 0, 20)?
+            .map(|chunk| Ok(chunk?.data.into_keys().collect()))
+            .collect::<Result<_, GetChunkError>>()?;
+        assert_eq!(chunks, vec![vec![5], vec![15, 16]]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_scan_chunks_with_prefetch() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = counting_kelpie(ScheduleConfig::default())?;
+        let hours = 50;
+        let mut points = vec![];
+        for i in 0..hours * 100 {
+            let time = i * 36_000 + 7;
+            points.push((
+                0,
+                DataPoint {
+                    time,
+                    value: i as f64,
+                },
+            ));
+        }
+        kelpie.insert_batch(&points)?;
+        // an unflushed chunk in the middle of the series
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 25 * 3_600_000 + 1,
+                value: -1.0,
+            },
+        )?;
+        let (start, stop) = (5 * 3_600_000 + 100, hours * 3_600_000);
+        let expected = kelpie.query(0, start, stop)?;
+
+        // the stored chunks 5 to 24 and 26 to 49 are read in runs of up to `prefetch`
+        for (prefetch, queries) in [(1, 44), (8, 6), (100, 2)] {
+            kelpie.set_prefetch_chunks(prefetch);
+            kelpie.chunk_store.chunks_read.set(0);
+            kelpie.chunk_store.queries.set(0);
+            let mut exported = RawSeries::new();
+            let mut last = None;
+            for chunk in kelpie.iter_chunks(0, start, stop)? {
+                let mut chunk = chunk?;
+                if chunk.first_time() <= last {
+                    Err("chunks out of order")?;
+                }
+                last = chunk.last_time().or(last);
+                exported.data.append(&mut chunk.data);
+            }
+            if exported != expected {
+                Err(format!("export with prefetch {prefetch} not matching"))?;
+            }
+            assert_eq!(kelpie.chunk_store.queries.get(), queries, "{prefetch}");
+            assert_eq!(kelpie.chunk_store.chunks_read.get(), 44, "{prefetch}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_read_store_in_append_only_mode() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = counting_kelpie(ScheduleConfig::default())?;
+        let mut fake = KelpieFake::new();
+        kelpie.set_ingest_mode(IngestMode::AppendOnly);
+        for i in 0..1000 {
+            let point = DataPoint {
+                time: i * 60_000,
+                value: i as f64,
+            };
+            kelpie.insert(i % 3, point)?;
+            fake.insert(i % 3, point)?;
+        }
+        kelpie.flush_all()?;
+        assert_eq!(kelpie.chunk_store.chunks_read.get(), 0);
+        for series_key in 0..3 {
+            if kelpie.query(series_key, 0, 1000 * 60_000)?
+                != fake.query(series_key, 0, 1000 * 60_000)?
+            {
+                Err("not matching")?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_tell_whether_insert_creates_chunk() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let mut kelpie = Kelpie::new_memory()?;
+        assert!(kelpie.would_create_chunk(0, 10));
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        )?;
+        assert!(!kelpie.would_create_chunk(0, 0));
+        assert!(!kelpie.would_create_chunk(0, hour - 1));
+        assert!(kelpie.would_create_chunk(0, hour));
+        assert!(kelpie.would_create_chunk(1, 10));
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_unstorable_points() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let point = DataPoint {
+            time: 10,
+            value: 1.0,
+        };
+        assert_eq!(kelpie.insert(0, point)?, InsertStatus::Cached);
+        match kelpie.insert(
+            0,
+            DataPoint {
+                value: f64::NAN,
+                ..point
+            },
+        ) {
+            Err(InsertError::NanValue) => {}
+            _ => Err("NaN value should be rejected")?,
+        }
+        match kelpie.insert(0, DataPoint { time: -5, ..point }) {
+            Err(InsertError::NegativeTime(-5)) => {}
+            _ => Err("negative time should be rejected")?,
+        }
+        match kelpie.insert(
+            0,
+            DataPoint {
+                time: i64::MAX,
+                ..point
+            },
+        ) {
+            Err(InsertError::TimestampOutOfRange(i64::MAX)) => {}
+            _ => Err("max time should be rejected")?,
+        }
+        kelpie.try_insert_lossy(0, DataPoint { time: -5, ..point })?;
+        kelpie.try_insert_lossy(0, DataPoint { time: 20, ..point })?;
+        let times: Vec<i64> = kelpie.query(0, 0, 100)?.data.keys().copied().collect();
+        assert_eq!(times, vec![10, 20]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_apply_duplicate_policy() -> Result<(), Box<dyn std::error::Error>> {
+        let point = |value| DataPoint { time: 10, value };
+        for (policy, expected) in [
+            (DuplicatePolicy::LastWins, Some(2.0)),
+            (DuplicatePolicy::FirstWins, Some(1.0)),
+            (DuplicatePolicy::Error, Some(1.0)),
+            (DuplicatePolicy::Sum, Some(3.0)),
+        ] {
+            let mut kelpie = Kelpie::new_memory()?;
+            kelpie.set_duplicate_policy(policy);
+            kelpie.insert(0, point(1.0))?;
+            // the stored point counts as well, not just the cached one
+            kelpie.flush_all()?;
+            kelpie.set_cache_config(CacheConfig::default())?;
+            kelpie.insert(
+                0,
+                DataPoint {
+                    time: 3_600_000,
+                    value: 0.0,
+                },
+            )?;
+            match (policy, kelpie.insert(0, point(2.0))) {
+                (DuplicatePolicy::Error, Err(InsertError::DuplicateTime(10))) => {}
+                (DuplicatePolicy::Error, res) => Err(format!("duplicate accepted: {res:?}"))?,
+                (_, res) => {
+                    res?;
+                }
+            }
+            assert_eq!(kelpie.query(0, 0, 100)?.get(10), expected, "{policy:?}");
+        }
+
+        let mut kelpie = Kelpie::new_memory()?;
+        kelpie.set_duplicate_policy(DuplicatePolicy::Sum);
+        kelpie.insert(0, point(f64::INFINITY))?;
+        if !matches!(
+            kelpie.insert(0, point(f64::NEG_INFINITY)),
+            Err(InsertError::NanValue)
+        ) {
+            Err("NaN sum should be rejected")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_out_of_order_inserts() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let at = |time| DataPoint { time, value: 1.0 };
+        let temp = TempPath::new();
+        let mut kelpie = Kelpie::new_path(temp.path())?;
+        kelpie.set_reject_out_of_order(true);
+        // in order, across a chunk boundary and in a second series
+        for time in [10, 20, hour - 1, hour, 2 * hour + 5] {
+            kelpie.insert(0, at(time))?;
+        }
+        kelpie.insert(1, at(5))?;
+
+        for time in [2 * hour + 5, hour + 1, 10] {
+            match kelpie.insert(0, at(time)) {
+                Err(InsertError::OutOfOrder { last, .. }) => assert_eq!(last, 2 * hour + 5),
+                res => Err(format!("{time} should be rejected, got {res:?}"))?,
+            }
+        }
+        assert_eq!(kelpie.query(0, 0, 3 * hour)?.len(), 5);
+
+        // the latest time is looked up again from the stored chunks after reopening
+        kelpie.flush_all()?;
+        drop(kelpie);
+        let mut kelpie = Kelpie::new_path(temp.path())?;
+        kelpie.set_reject_out_of_order(true);
+        if kelpie.insert(0, at(hour + 1)).is_ok() {
+            Err("insert before a stored point should be rejected")?;
+        }
+        kelpie.insert(0, at(2 * hour + 6))?;
+
+        // off by default
+        let mut kelpie = Kelpie::new_memory()?;
+        kelpie.insert(0, at(20))?;
+        kelpie.insert(0, at(10))?;
+        assert_eq!(kelpie.query(0, 0, 100)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn should_report_chunk_rolls_on_insert() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let mut kelpie = Kelpie::new_memory()?;
+        let at = |time| DataPoint { time, value: 1.0 };
+        assert_eq!(kelpie.insert(0, at(10))?, InsertStatus::Cached);
+        assert_eq!(kelpie.insert(0, at(20))?, InsertStatus::Cached);
+        assert_eq!(kelpie.insert(1, at(hour))?, InsertStatus::Cached);
+        assert_eq!(
+            kelpie.insert(0, at(hour + 10))?,
+            InsertStatus::CompactmentPending(Schedule {
+                chunk_start: 0,
+                chunk_end: hour,
+            })
+        );
+        assert_eq!(kelpie.chunk_store.list_chunks(0)?.len(), 1);
+        assert_eq!(kelpie.insert(0, at(hour + 20))?, InsertStatus::Cached);
+        // going back reloads the stored chunk, which also rolls the cached one
+        assert_eq!(
+            kelpie.insert(0, at(30))?,
+            InsertStatus::CompactmentPending(Schedule {
+                chunk_start: hour,
+                chunk_end: 2 * hour,
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_find_last_point_in_earlier_chunks() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let mut kelpie = Kelpie::new_memory()?;
+        assert_eq!(kelpie.count(0, 0, i64::MAX)?, 0);
+        assert_eq!(kelpie.first(0, 0)?, None);
+        assert_eq!(kelpie.last(0, i64::MAX)?, None);
+
+        let early = DataPoint {
+            time: 10,
+            value: 1.0,
+        };
+        let late = DataPoint {
+            time: 5 * hour + 10,
+            value: 2.0,
+        };
+        kelpie.insert(0, early)?;
+        kelpie.insert(0, late)?;
+        assert_eq!(kelpie.count(0, 0, i64::MAX)?, 2);
+        assert_eq!(kelpie.count(0, 11, 5 * hour + 10)?, 0);
+        // the chunks in between are empty, so both walk past them
+        assert_eq!(kelpie.last(0, 4 * hour)?, Some(early));
+        assert_eq!(kelpie.last(0, 5 * hour + 10)?, Some(late));
+        assert_eq!(kelpie.first(0, 11)?, Some(late));
+        assert_eq!(kelpie.last(0, 9)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn should_iterate_query_across_chunks() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let mut kelpie = Kelpie::new_memory()?;
+        for i in 0..50 {
+            kelpie.insert(
+                0,
+                DataPoint {
+                    time: i * hour / 7,
+                    value: i as f64,
+                },
+            )?;
+        }
+        // the last chunk stays cached
+        for (start, stop) in [
+            (0, 8 * hour),
+            (hour / 2, 5 * hour + 1),
+            (3 * hour, 3 * hour),
+        ] {
+            let points = kelpie
+                .query_iter(0, start, stop)?
+                .collect::<Result<Vec<_>, _>>()?;
+            let expected: Vec<DataPoint> = kelpie
+                .query(0, start, stop)?
+                .data
+                .into_iter()
+                .map(|(time, value)| DataPoint { time, value })
+                .collect();
+            assert_eq!(points, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_aggregate_buckets_across_chunks() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory_with_config(ScheduleConfig::new(4)?)?;
+        for time in [1, 2, 3, 5, 6, 14] {
+            kelpie.insert(
+                0,
+                DataPoint {
+                    time,
+                    value: time as f64,
+                },
+            )?;
+        }
+        // buckets [0, 5) and [5, 10) each span two chunks, [10, 15) holds one point
+        let aggregate = |agg| kelpie.query_aggregate(0, 2, 20, 5, agg);
+        assert_eq!(
+            aggregate(Aggregator::Count)?.points(),
+            [(0, 2.0), (5, 2.0), (10, 1.0)].map(|(time, value)| DataPoint { time, value })
+        );
+        assert_eq!(aggregate(Aggregator::Mean)?.get(0), Some(2.5));
+        assert_eq!(aggregate(Aggregator::Sum)?.get(5), Some(11.0));
+        assert_eq!(aggregate(Aggregator::Min)?.get(5), Some(5.0));
+        assert_eq!(aggregate(Aggregator::Max)?.get(0), Some(3.0));
+        assert_eq!(aggregate(Aggregator::First)?.get(10), Some(14.0));
+        assert_eq!(aggregate(Aggregator::Last)?.get(0), Some(3.0));
+        assert!(kelpie
+            .query_aggregate(0, 7, 14, 5, Aggregator::Count)?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_compute_counter_rate_across_resets() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory_with_config(ScheduleConfig::new(4)?)?;
+        let mut fake = KelpieFake::new();
+        // resets to 0 after 30 and to 5 after 20, [20, 30) holds a single point
+        let points = [
+            (0, 10.0),
+            (2, 30.0),
+            (4, 0.0),
+            (8, 10.0),
+            (10, 20.0),
+            (12, 5.0),
+            (20, 1.0),
+        ];
+        for (time, value) in points {
+            kelpie.insert(0, DataPoint { time, value })?;
+            fake.insert(0, DataPoint { time, value })?;
+        }
+        let rates = kelpie.query_rate(0, 0, 30, 10)?;
+        assert_eq!(rates.len(), 2);
+        // 20 up to the reset, 10 after it, over 8
+        assert_eq!(rates.get(0), Some(30.0 / 8.0));
+        // the reset counts 5, over 2
+        assert_eq!(rates.get(10), Some(2.5));
+        assert_eq!(rates, fake.query_rate(0, 0, 30, 10)?);
+        Ok(())
+    }
+
+    #[test]
+    fn should_fill_gaps_on_a_grid() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory_with_config(ScheduleConfig::new(8)?)?;
+        for (time, value) in [(10, 1.0), (15, 7.0), (20, 3.0), (35, 6.0)] {
+            kelpie.insert(0, DataPoint { time, value })?;
+        }
+        let values = |fill| -> Result<Vec<Option<f64>>, GetChunkError> {
+            let grid = kelpie.query_filled(0, 1, 32, 10, fill)?;
+            assert_eq!(
+                grid.iter().map(|&(time, _)| time).collect::<Vec<_>>(),
+                [10, 20, 30]
+            );
+            Ok(grid.into_iter().map(|(_, value)| value).collect())
+        };
+        assert_eq!(values(FillPolicy::None)?, [Some(1.0), Some(3.0), None]);
+        assert_eq!(
+            values(FillPolicy::Previous)?,
+            [Some(1.0), Some(3.0), Some(3.0)]
+        );
+        // interpolated towards the point at 35, past the range
+        assert_eq!(
+            values(FillPolicy::Linear)?,
+            [Some(1.0), Some(3.0), Some(5.0)]
+        );
+        assert_eq!(
+            values(FillPolicy::Constant(0.0))?,
+            [Some(1.0), Some(3.0), Some(0.0)]
+        );
+        // a leading gap has nothing to fill from
+        let leading = kelpie.query_filled(0, 0, 10, 5, FillPolicy::Previous)?;
+        assert_eq!(leading, [(0, None), (5, None)]);
+        let trailing = kelpie.query_filled(0, 40, 50, 5, FillPolicy::Linear)?;
+        assert_eq!(trailing, [(40, None), (45, None)]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_list_unflushed_series() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let point = DataPoint {
+            time: 10,
+            value: 1.0,
+        };
+        kelpie.insert(5, point)?;
+        kelpie.flush_all()?;
+        kelpie.insert(2, point)?;
+        kelpie.insert(5, point)?;
+        assert_eq!(kelpie.chunk_store.list_series()?, vec![5]);
+        assert_eq!(kelpie.list_series()?, vec![2, 5]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_query_many_series() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory_with_config(ScheduleConfig::new(10)?)?;
+        // series 0 and 1 overlap in [20, 30), series 2 lies past the queried range
+        for (series_key, times) in [(0, 0..30), (1, 20..50), (2, 100..110)] {
+            for time in times {
+                kelpie.insert(series_key, DataPoint { time, value: 1.0 })?;
+            }
+        }
+        let res = kelpie.query_many(&[0, 1, 2, 3, 1], 15, 35)?;
+        assert_eq!(res.len(), 4);
+        for series_key in 0..4 {
+            assert_eq!(res[&series_key], kelpie.query(series_key, 15, 35)?);
+        }
+        assert_eq!(res[&0].len(), 15);
+        assert_eq!(res[&1].len(), 15);
+        assert!(res[&2].is_empty() && res[&3].is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_enforce_retention() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory_with_config(ScheduleConfig::new(10)?)?;
+        for series_key in [0, 1] {
+            for time in 0..50 {
+                kelpie.insert(series_key, DataPoint { time, value: 1.0 })?;
+            }
+        }
+        kelpie.flush_all()?;
+        // [20, 30) straddles 25 and is kept whole, [40, 50) is still cached
+        assert_eq!(kelpie.enforce_retention(0, 25)?, 2);
+        assert!(kelpie.query(0, 0, 20)?.is_empty());
+        assert_eq!(kelpie.query(0, 20, 50)?.len(), 30);
+        assert_eq!(kelpie.query(1, 0, 50)?.len(), 50);
+
+        assert_eq!(kelpie.enforce_retention_all(45)?, 2 + 4);
+        kelpie.flush_all()?;
+        for series_key in [0, 1] {
+            assert!(kelpie.query(series_key, 0, 40)?.is_empty());
+            assert_eq!(kelpie.query(series_key, 40, 50)?.len(), 10);
+        }
+        // the unflushed chunk [50, 60) is dropped without being written
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 55,
+                value: 1.0,
+            },
+        )?;
+        assert_eq!(kelpie.enforce_retention(0, 60)?, 1);
+        kelpie.flush_all()?;
+        assert!(kelpie.query(0, 0, 60)?.is_empty());
+        Ok(())
+    }
+
+    /// A chunk store counting the chunks it hands out and the chunks written to it.
+    struct CountingStore<S> {
+        inner: S,
+        chunks_read: std::cell::Cell<usize>,
+        // number of calls reading chunks, whether they found any or not
+        queries: std::cell::Cell<usize>,
+        // number of set_chunk calls
+        writes: std::cell::Cell<usize>,
+    }
+
+    impl<S> CountingStore<S> {
+        fn new(inner: S) -> Self {
+            Self {
+                inner,
+                chunks_read: Default::default(),
+                queries: Default::default(),
+                writes: Default::default(),
+            }
+        }
+    }
+
+    // an in-memory SQLite `Kelpie` whose store counts its reads and writes
+    fn counting_kelpie(
+        config: ScheduleConfig,
+    ) -> Result<Kelpie<CountingStore<SqliteChunkStore>>, sqlite::Error> {
+        let store = CountingStore::new(SqliteChunkStore::new_memory()?);
+        Ok(Kelpie::with_store(store, config))
+    }
+
+    impl<S: KelpieChunkStore> KelpieChunkStore for CountingStore<S> {
+        fn get_chunk(
+            &self,
+            series_key: i64,
+            start: i64,
+            stop: i64,
+        ) -> Result<Option<(ChunkMeta, Chunk)>, GetChunkError> {
+            self.queries.set(self.queries.get() + 1);
+            let res = self.inner.get_chunk(series_key, start, stop)?;
+            self.chunks_read
+                .set(self.chunks_read.get() + res.iter().count());
+            Ok(res)
+        }
+        fn get_chunks_in_range(
+            &self,
+            series_key: i64,
+            start: i64,
+            stop: i64,
+        ) -> Result<Vec<(ChunkMeta, Chunk)>, GetChunkError> {
+            self.queries.set(self.queries.get() + 1);
+            let res = self.inner.get_chunks_in_range(series_key, start, stop)?;
+            self.chunks_read.set(self.chunks_read.get() + res.len());
+            Ok(res)
+        }
+        fn get_chunks_in_value_range(
+            &self,
+            series_key: i64,
+            start: i64,
+            stop: i64,
+            min: f64,
+            max: f64,
+        ) -> Result<Vec<(ChunkMeta, Chunk)>, GetChunkError> {
+            self.queries.set(self.queries.get() + 1);
+            let res = self
+                .inner
+                .get_chunks_in_value_range(series_key, start, stop, min, max)?;
+            self.chunks_read.set(self.chunks_read.get() + res.len());
+            Ok(res)
+        }
+        fn set_chunk(
+            &mut self,
+            series_key: i64,
+            start: i64,
+            stop: i64,
+            chunk: &Chunk,
+        ) -> Result<(), SetChunkError> {
+            self.writes.set(self.writes.get() + 1);
+            self.inner.set_chunk(series_key, start, stop, chunk)
+        }
+        fn set_chunks(&mut self, chunks: &[(ChunkMeta, Chunk)]) -> Result<(), SetChunkError> {
+            self.inner.set_chunks(chunks)
+        }
+        fn replace_chunks(
+            &mut self,
+            remove: &[ChunkMeta],
+            add: &[(ChunkMeta, Chunk)],
+        ) -> Result<(), SetChunkError> {
+            self.inner.replace_chunks(remove, add)
+        }
+        fn truncate(&mut self) -> Result<(), SetChunkError> {
+            self.inner.truncate()
+        }
+        fn delete_series(&mut self, series_key: i64) -> Result<(), SetChunkError> {
+            self.inner.delete_series(series_key)
+        }
+        fn delete_chunks_before(
+            &mut self,
+            series_key: i64,
+            time: i64,
+        ) -> Result<usize, SetChunkError> {
+            self.inner.delete_chunks_before(series_key, time)
+        }
+        fn set_series_metadata(
+            &mut self,
+            series_key: i64,
+            json: &str,
+        ) -> Result<(), SetChunkError> {
+            self.inner.set_series_metadata(series_key, json)
+        }
+        fn get_series_metadata(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+            self.inner.get_series_metadata(series_key)
+        }
+        fn set_series_name(&mut self, series_key: i64, name: &str) -> Result<(), SetChunkError> {
+            self.inner.set_series_name(series_key, name)
+        }
+        fn get_series_name(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+            self.inner.get_series_name(series_key)
+        }
+        fn set_series_schedule(
+            &mut self,
+            series_key: i64,
+            schedule: &str,
+        ) -> Result<(), SetChunkError> {
+            self.inner.set_series_schedule(series_key, schedule)
+        }
+        fn get_series_schedule(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+            self.inner.get_series_schedule(series_key)
+        }
+        fn list_all_chunks(&self) -> Result<Vec<ChunkMeta>, GetChunkError> {
+            self.inner.list_all_chunks()
+        }
+        fn list_chunks(&self, series_key: i64) -> Result<Vec<ChunkMeta>, GetChunkError> {
+            self.inner.list_chunks(series_key)
+        }
+        fn list_series(&self) -> Result<Vec<i64>, GetChunkError> {
+            self.inner.list_series()
+        }
+        fn time_bounds(&self, series_key: i64) -> Result<Option<(i64, i64)>, GetChunkError> {
+            self.inner.time_bounds(series_key)
+        }
+        fn checkpoint(&mut self) -> Result<(), SetChunkError> {
+            self.inner.checkpoint()
+        }
+    }
+
+    #[test]
+    fn should_compute_several_aggregates_in_one_pass() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::with_store(
+            CountingStore::new(MemoryChunkStore::new()),
+            ScheduleConfig::new(10)?,
+        );
+        for time in 0..100 {
+            kelpie.insert(
+                0,
+                DataPoint {
+                    time,
+                    value: (time % 7) as f64,
+                },
+            )?;
+        }
+        kelpie.flush_all()?;
+        kelpie.chunk_store.chunks_read.set(0);
+        let aggs = [Aggregator::Min, Aggregator::Max, Aggregator::Mean];
+        let multi = kelpie.query_multi_aggregate(0, 5, 95, 15, &aggs)?;
+        // 10 chunks overlap the range, the last one is still cached
+        assert_eq!(kelpie.chunk_store.chunks_read.get(), 9);
+        assert_eq!(multi.len(), 7);
+        for (i, &agg) in aggs.iter().enumerate() {
+            let single = kelpie.query_aggregate(0, 5, 95, 15, agg)?;
+            let column: Vec<DataPoint> = multi
+                .iter()
+                .map(|(&time, values)| DataPoint {
+                    time,
+                    value: values[i],
+                })
+                .collect();
+            assert_eq!(column, single.points());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_run_on_a_given_store() -> Result<(), Box<dyn std::error::Error>> {
+        fn fill<S: KelpieChunkStore>(kelpie: &mut Kelpie<S>) -> Result<(), InsertError> {
+            for time in 0..10 {
+                kelpie.insert(0, DataPoint { time, value: 1.0 })?;
+            }
+            Ok(())
+        }
+        let mut kelpie = Kelpie::with_store(MemoryChunkStore::new(), ScheduleConfig::new(4)?);
+        fill(&mut kelpie)?;
+        kelpie.flush_all()?;
+        let starts: Vec<i64> = kelpie
+            .chunk_store
+            .list_chunks(0)?
+            .iter()
+            .map(|meta| meta.start)
+            .collect();
+        assert_eq!(starts, vec![0, 4, 8]);
+        assert_eq!(kelpie.count(0, 0, 10)?, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn should_merge_stored_points_into_cached_chunk() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let mut kelpie = Kelpie::new_memory()?;
+        let at = |time, value| DataPoint { time, value };
+        kelpie.insert(0, at(10, 1.0))?;
+        kelpie.insert(0, at(20, 1.0))?;
+        kelpie.flush_all()?;
+        // reloaded without the stored points
+        kelpie.save_all()?;
+        kelpie.set_ingest_mode(IngestMode::AppendOnly);
+        kelpie.insert(0, at(20, 2.0))?;
+        kelpie.insert(0, at(30, 2.0))?;
+
+        let (meta, data) = kelpie
+            .query_exact_chunk(0, 0, hour)?
+            .ok_or("no chunk found")?;
+        assert_eq!((meta.start, meta.stop), (0, hour));
+        let points: Vec<(i64, f64)> = data.data.into_iter().collect();
+        assert_eq!(points, vec![(10, 1.0), (20, 2.0), (30, 2.0)]);
+        assert_eq!(kelpie.query(0, 0, hour)?.data.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn should_keep_stored_points_when_writing_partial_chunk(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let temp = TempPath::new();
+        let at = |time, value| DataPoint { time, value };
+        let mut kelpie = Kelpie::new_path(temp.path())?;
+        kelpie.insert(0, at(10, 1.0))?;
+        kelpie.insert(1, at(10, 1.0))?;
+        kelpie.insert(2, at(10, 1.0))?;
+        kelpie.close()?;
+
+        let mut kelpie = Kelpie::new_path(temp.path())?;
+        kelpie.set_ingest_mode(IngestMode::AppendOnly);
+        for series_key in 0..3 {
+            kelpie.insert(series_key, at(20, 2.0))?;
+            assert_eq!(kelpie.query(series_key, 0, hour)?.data.len(), 2);
+            assert_eq!(kelpie.count(series_key, 0, hour)?, 2);
+            assert_eq!(kelpie.query_iter(series_key, 0, hour)?.count(), 2);
+        }
+        // written by flushing, evicting and writing through
+        kelpie.flush(0)?;
+        kelpie.insert(1, at(hour, 2.0))?;
+        kelpie.set_durability_mode(DurabilityMode::WriteThroughEveryN(1));
+        kelpie.insert(2, at(30, 2.0))?;
+        kelpie.flush_all()?;
+        for series_key in 0..3 {
+            let points = kelpie.query(series_key, 0, hour)?;
+            assert_eq!(points.data.get(&10), Some(&1.0), "{series_key}");
+            assert_eq!(points.data.get(&20), Some(&2.0), "{series_key}");
+        }
+        kelpie.close()?;
+        let kelpie = Kelpie::new_path(temp.path())?;
+        for series_key in 0..3 {
+            assert_eq!(kelpie.count(series_key, 0, 30)?, 2, "{series_key}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_serve_loaded_chunk_from_cache() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let mut kelpie = counting_kelpie(ScheduleConfig::default())?;
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        )?;
+        kelpie.flush_all()?;
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 20,
+                value: 1.0,
+            },
+        )?;
+        let queries = kelpie.chunk_store.queries.get();
+        let (_, data) = kelpie
+            .query_exact_chunk(0, 0, hour)?
+            .ok_or("no chunk found")?;
+        assert_eq!(data.data.len(), 2);
+        // the chunk was loaded with the stored points, so the store isn't read again
+        assert_eq!(kelpie.chunk_store.queries.get(), queries);
+        Ok(())
+    }
+
+    #[test]
+    fn should_flush_only_changed_chunks() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let mut kelpie = counting_kelpie(ScheduleConfig::default())?;
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        )?;
+        kelpie.insert(
+            1,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        )?;
+        kelpie.flush_all()?;
+        assert_eq!(kelpie.chunk_store.writes.get(), 2);
+        kelpie.flush_all()?;
+        assert_eq!(kelpie.chunk_store.writes.get(), 2);
+
+        kelpie.insert(
+            1,
+            DataPoint {
+                time: 20,
+                value: 1.0,
+            },
+        )?;
+        kelpie.flush_all()?;
+        assert_eq!(kelpie.chunk_store.writes.get(), 3);
+        // a chunk reloaded from the store is unchanged as well
+        kelpie.save_all()?;
+        assert_eq!(kelpie.query(0, 0, hour)?.len(), 1);
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        )?;
+        let writes = kelpie.chunk_store.writes.get();
+        kelpie.flush_all()?;
+        assert_eq!(kelpie.chunk_store.writes.get(), writes + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn should_write_through_every_n_inserts() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        {
+            let mut kelpie = Kelpie::with_store(
+                CountingStore::new(SqliteChunkStore::new_path(temp.path())?),
+                ScheduleConfig::default(),
+            );
+            kelpie.set_durability_mode(DurabilityMode::WriteThroughEveryN(3));
+            for time in 0..7 {
+                kelpie.insert(0, DataPoint { time, value: 1.0 })?;
+            }
+            assert_eq!(kelpie.chunk_store.writes.get(), 2);
+            // a crash: nothing is flushed on the way out
+            std::mem::forget(kelpie);
+        }
+        let kelpie = Kelpie::new_path(temp.path())?;
+        let times: Vec<i64> = kelpie.query(0, 0, 100)?.data.keys().copied().collect();
+        assert_eq!(times, vec![0, 1, 2, 3, 4, 5]);
+        Ok(())
+    }
 
     #[test]
-    fn should_create() -> Result<(), Box<dyn std::error::Error>> {
-        let _kelpie = Kelpie::new_memory()?;
+    fn should_publish_inserted_points() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        let point = DataPoint {
+            time: 10,
+            value: 1.0,
+        };
+        kelpie.insert(0, point)?;
+        let first = kelpie.subscribe(0);
+        let second = kelpie.subscribe(0);
+        let other = kelpie.subscribe(1);
+
+        kelpie.insert(0, DataPoint { time: 20, ..point })?;
+        if kelpie.insert(0, DataPoint { time: -1, ..point }).is_ok() {
+            Err("negative time should be rejected")?;
+        }
+        kelpie.insert_many(0, &[DataPoint { time: 30, ..point }])?;
+        kelpie.insert_batch(&[(0, DataPoint { time: 40, ..point })])?;
+        for receiver in [&first, &second] {
+            let times: Vec<i64> = receiver.try_iter().map(|point| point.time).collect();
+            assert_eq!(times, vec![20, 30, 40]);
+        }
+        assert!(other.try_recv().is_err());
+
+        drop(first);
+        kelpie.insert(0, DataPoint { time: 50, ..point })?;
+        assert_eq!(second.try_recv()?.time, 50);
+        assert_eq!(kelpie.subscribers[&0].len(), 1);
         Ok(())
     }
 
     #[test]
-    fn should_insert() -> Result<(), Box<dyn std::error::Error>> {
-        use rand::prelude::*;
-        use rand::rngs::SmallRng;
-
+    fn should_insert_batch() -> Result<(), Box<dyn std::error::Error>> {
         let mut kelpie = Kelpie::new_memory()?;
-        let mut rng = SmallRng::seed_from_u64(0xdeadbeef);
-        // float series
-        const SERIES: usize = 64;
-        let mut times: [i64; SERIES] = [1722180250000; SERIES];
-        let mut vals: [f64; SERIES] = [0.0; SERIES];
-        let val_variance = 20.0;
-        for _ in 0..100_000 {
-            let series_key = rng.gen_range(0..SERIES);
-            let last_time = &mut times[series_key];
-            let last_val = &mut vals[series_key];
+        let mut fake = KelpieFake::new();
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 5,
+                value: 0.5,
+            },
+        )?;
+        fake.insert(
+            0,
+            DataPoint {
+                time: 5,
+                value: 0.5,
+            },
+        )?;
+        let points = [
+            (
+                0,
+                DataPoint {
+                    time: 10,
+                    value: 1.0,
+                },
+            ),
+            (
+                0,
+                DataPoint {
+                    time: 3_600_010,
+                    value: 2.0,
+                },
+            ),
+            (
+                1,
+                DataPoint {
+                    time: 10,
+                    value: 3.0,
+                },
+            ),
+            (
+                1,
+                DataPoint {
+                    time: -1,
+                    value: 4.0,
+                },
+            ),
+            (
+                2,
+                DataPoint {
+                    time: 10,
+                    value: f64::NAN,
+                },
+            ),
+        ];
+        let report = kelpie.insert_batch(&points)?;
+        for &(series_key, point) in &points {
+            fake.try_insert_lossy(series_key, point)?;
+        }
+        assert_eq!(
+            report,
+            InsertReport {
+                accepted: 3,
+                rejected: 2,
+                chunks_written: 3,
+            }
+        );
+        for series_key in 0..3 {
+            if kelpie.query(series_key, 0, 10_000_000)? != fake.query(series_key, 0, 10_000_000)? {
+                Err("not matching")?;
+            }
+        }
+        Ok(())
+    }
 
-            let time_inc = rng.gen_range(400..1100);
-            *last_time += time_inc;
-            let time = *last_time;
+    #[test]
+    fn should_leave_store_unchanged_on_failed_batch() -> Result<(), Box<dyn std::error::Error>> {
+        let mut kelpie = Kelpie::new_memory()?;
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        )?;
+        kelpie.insert(
+            1,
+            DataPoint {
+                time: 10,
+                value: 1.0,
+            },
+        )?;
+        kelpie.flush_all()?;
+        kelpie.insert(
+            0,
+            DataPoint {
+                time: 20,
+                value: 1.0,
+            },
+        )?;
+        kelpie.chunk_store.connection().execute(
+            "CREATE TRIGGER fail BEFORE INSERT ON chunks WHEN NEW.series = 99 BEGIN SELECT RAISE(ABORT, 'fail'); END",
+        )?;
 
-            let val_inc = rng.gen_range(-val_variance..val_variance);
-            *last_val += val_inc;
+        let stored_before = |kelpie: &Kelpie, series_key| -> Result<Vec<u8>, GetChunkError> {
+            let (_, chunk) = kelpie
+                .chunk_store
+                .get_chunk(series_key, 0, 3_600_000)?
+                .unwrap();
+            Ok(chunk.compressed_data)
+        };
+        let before = [stored_before(&kelpie, 0)?, stored_before(&kelpie, 1)?];
+        let query_before = [kelpie.query(0, 0, 100)?, kelpie.query(1, 0, 100)?];
 
-            // truncate precision of floats to simulate less random measurements
-            let value = f64::from_bits(last_val.to_bits() & (!0 << 36));
-            assert!((*last_val - value).abs() < 1.0);
-            let point = DataPoint { time, value };
+        let res = kelpie.insert_batch(&[
+            (
+                0,
+                DataPoint {
+                    time: 30,
+                    value: 2.0,
+                },
+            ),
+            (
+                1,
+                DataPoint {
+                    time: 30,
+                    value: 2.0,
+                },
+            ),
+            (
+                99,
+                DataPoint {
+                    time: 30,
+                    value: 2.0,
+                },
+            ),
+        ]);
+        if res.is_ok() {
+            Err("batch should fail")?;
+        }
 
-            kelpie.insert(series_key as i64, point);
+        let after = [stored_before(&kelpie, 0)?, stored_before(&kelpie, 1)?];
+        let query_after = [kelpie.query(0, 0, 100)?, kelpie.query(1, 0, 100)?];
+        if before != after || query_before != query_after {
+            Err("failed batch modified the store")?;
+        }
+        if kelpie.chunk_store.get_chunk(99, 0, 3_600_000)?.is_some() {
+            Err("failed batch left a chunk behind")?;
         }
         Ok(())
     }
@@ -343,6 +5806,46 @@ mod tests {
             start: i64,
             stop: i64,
         },
+        // write and evict every cached chunk and, for path-backed stores, reopen the file
+        Reopen,
+        Compact {
+            series_key: i64,
+            target: CompactionTarget,
+        },
+        Delete {
+            series_key: i64,
+        },
+        DeleteRange {
+            series_key: i64,
+            start: i64,
+            stop: i64,
+        },
+        // count points in [start, stop), and the first and last points around them
+        Stats {
+            series_key: i64,
+            start: i64,
+            stop: i64,
+        },
+        Aggregate {
+            series_key: i64,
+            start: i64,
+            stop: i64,
+            bucket: i64,
+            agg: Aggregator,
+        },
+        Rate {
+            series_key: i64,
+            start: i64,
+            stop: i64,
+            bucket: i64,
+        },
+        Filled {
+            series_key: i64,
+            start: i64,
+            stop: i64,
+            step: i64,
+            fill: FillPolicy,
+        },
     }
 
     impl quickcheck::Arbitrary for Cmd {
@@ -359,6 +5862,82 @@ mod tests {
                         stop: start.saturating_add(size as i64),
                     }
                 }
+                1 => Cmd::Reopen,
+                2 => {
+                    let limit = u8::arbitrary(g) as usize;
+                    let target = if bool::arbitrary(g) {
+                        CompactionTarget::MaxPoints(limit)
+                    } else {
+                        CompactionTarget::MaxBytes(limit * 16)
+                    };
+                    Cmd::Compact { series_key, target }
+                }
+                3 => Cmd::Delete { series_key },
+                4 => {
+                    let start = Arbitrary::arbitrary(g);
+                    let size = u16::arbitrary(g);
+                    Cmd::DeleteRange {
+                        series_key,
+                        start,
+                        stop: start.saturating_add(size as i64),
+                    }
+                }
+                5 => {
+                    let start = Arbitrary::arbitrary(g);
+                    let size = u16::arbitrary(g);
+                    Cmd::Stats {
+                        series_key,
+                        start,
+                        stop: start.saturating_add(size as i64),
+                    }
+                }
+                6 => {
+                    let start = Arbitrary::arbitrary(g);
+                    let size = u16::arbitrary(g);
+                    let aggs = [
+                        Aggregator::Mean,
+                        Aggregator::Min,
+                        Aggregator::Max,
+                        Aggregator::Sum,
+                        Aggregator::Count,
+                        Aggregator::Last,
+                        Aggregator::First,
+                    ];
+                    Cmd::Aggregate {
+                        series_key,
+                        start,
+                        stop: start.saturating_add(size as i64),
+                        bucket: u8::arbitrary(g) as i64,
+                        agg: *g.choose(&aggs).unwrap(),
+                    }
+                }
+                7 => {
+                    let start = Arbitrary::arbitrary(g);
+                    let size = u16::arbitrary(g);
+                    Cmd::Rate {
+                        series_key,
+                        start,
+                        stop: start.saturating_add(size as i64),
+                        bucket: u8::arbitrary(g) as i64,
+                    }
+                }
+                8 => {
+                    let start = Arbitrary::arbitrary(g);
+                    let size = u16::arbitrary(g);
+                    let fills = [
+                        FillPolicy::None,
+                        FillPolicy::Previous,
+                        FillPolicy::Linear,
+                        FillPolicy::Constant(-1.0),
+                    ];
+                    Cmd::Filled {
+                        series_key,
+                        start,
+                        stop: start.saturating_add(size as i64),
+                        step: u8::arbitrary(g) as i64,
+                        fill: *g.choose(&fills).unwrap(),
+                    }
+                }
                 _ => {
                     let points = Arbitrary::arbitrary(g);
                     Cmd::Insert {
@@ -397,14 +5976,92 @@ mod tests {
     }
     */
 
+    /// A database file in the system temp dir that is removed on drop.
+    pub(crate) struct TempPath(std::path::PathBuf);
+
+    impl TempPath {
+        pub(crate) fn new() -> Self {
+            use std::sync::atomic::{AtomicUsize, Ordering};
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let name = format!("kelpie-test-{}-{}.sqlite", std::process::id(), n);
+            let path = std::env::temp_dir().join(name);
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+
+        pub(crate) fn path(&self) -> &std::path::Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+            // left behind by WAL mode if a connection wasn't closed cleanly
+            for suffix in ["-wal", "-shm"] {
+                let mut path = self.0.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    enum Backing {
+        Memory,
+        Path,
+        // in memory, with the given cache limits
+        Cached(CacheConfig),
+        // in memory, with chunks laid out by the given schedule
+        Schedule(ScheduleConfig),
+    }
+
     fn kelpie_eq_fake(cmds: &[Cmd]) -> Result<(), Box<dyn std::error::Error>> {
-        let mut kelpie = Kelpie::new_memory()?;
+        kelpie_eq_fake_with(Backing::Memory, cmds)
+    }
+
+    fn kelpie_eq_fake_with(
+        backing: Backing,
+        cmds: &[Cmd],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        kelpie_eq_fake_with_policy(backing, DuplicatePolicy::LastWins, cmds)
+    }
+
+    fn kelpie_eq_fake_with_policy(
+        backing: Backing,
+        policy: DuplicatePolicy,
+        cmds: &[Cmd],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let open = || match backing {
+            Backing::Memory => Kelpie::new_memory(),
+            Backing::Path => Kelpie::new_path(temp.path()),
+            Backing::Cached(cache) => Kelpie::new_memory().map(|mut kelpie| {
+                kelpie
+                    .set_cache_config(cache)
+                    .expect("nothing is cached to evict");
+                kelpie
+            }),
+            Backing::Schedule(config) => Kelpie::new_memory_with_config(config),
+        };
+        let mut kelpie = open()?;
+        kelpie.set_duplicate_policy(policy);
         let mut fake = KelpieFake::new();
+        fake.set_duplicate_policy(policy);
         for cmd in cmds {
             match *cmd {
                 Cmd::Insert { series_key, point } => {
-                    kelpie.insert(series_key, point);
-                    fake.insert(series_key, point);
+                    match (
+                        kelpie.insert(series_key, point),
+                        fake.insert(series_key, point),
+                    ) {
+                        (Err(InsertError::Store(e)), _) => Err(e)?,
+                        (Err(InsertError::Write(e)), _) => Err(e)?,
+                        (Ok(_), Ok(_)) => {}
+                        (Err(a), Err(b)) if discriminant(&a) == discriminant(&b) => {}
+                        _ => Err("insert results not matching")?,
+                    }
                 }
                 Cmd::Query {
                     series_key,
@@ -417,6 +6074,81 @@ mod tests {
                         Err("not matching")?;
                     }
                 }
+                Cmd::Reopen => {
+                    kelpie.save_all()?;
+                    if let Backing::Path = backing {
+                        drop(kelpie);
+                        kelpie = open()?;
+                        kelpie.set_duplicate_policy(policy);
+                    }
+                }
+                Cmd::Compact { series_key, target } => {
+                    kelpie.compact(series_key, target)?;
+                }
+                Cmd::Delete { series_key } => {
+                    kelpie.delete_series(series_key)?;
+                    fake.delete_series(series_key);
+                }
+                Cmd::DeleteRange {
+                    series_key,
+                    start,
+                    stop,
+                } => {
+                    kelpie.delete_range(series_key, start, stop)?;
+                    fake.delete_range(series_key, start, stop);
+                }
+                Cmd::Stats {
+                    series_key,
+                    start,
+                    stop,
+                } => {
+                    if kelpie.count(series_key, start, stop)?
+                        != fake.count(series_key, start, stop)?
+                        || kelpie.first(series_key, start)? != fake.first(series_key, start)?
+                        || kelpie.last(series_key, stop)? != fake.last(series_key, stop)?
+                    {
+                        Err("stats not matching")?;
+                    }
+                }
+                Cmd::Aggregate {
+                    series_key,
+                    start,
+                    stop,
+                    bucket,
+                    agg,
+                } => {
+                    let kelpie_res =
+                        kelpie.query_aggregate(series_key, start, stop, bucket, agg)?;
+                    let fake_res = fake.query_aggregate(series_key, start, stop, bucket, agg)?;
+                    if kelpie_res != fake_res {
+                        Err("aggregates not matching")?;
+                    }
+                }
+                Cmd::Rate {
+                    series_key,
+                    start,
+                    stop,
+                    bucket,
+                } => {
+                    if kelpie.query_rate(series_key, start, stop, bucket)?
+                        != fake.query_rate(series_key, start, stop, bucket)?
+                    {
+                        Err("rates not matching")?;
+                    }
+                }
+                Cmd::Filled {
+                    series_key,
+                    start,
+                    stop,
+                    step,
+                    fill,
+                } => {
+                    if kelpie.query_filled(series_key, start, stop, step, fill)?
+                        != fake.query_filled(series_key, start, stop, step, fill)?
+                    {
+                        Err("filled grids not matching")?;
+                    }
+                }
             }
         }
         Ok(())
@@ -651,11 +6383,256 @@ mod tests {
         Ok(())
     }
 
+    fn schedule_is_consistent(point: i64, chunk_size: i64) -> bool {
+        let config = ScheduleConfig::new(chunk_size).unwrap();
+        let schedule = config.init_schedule_from_time(point);
+        // the end is exclusive, so no chunk can hold i64::MAX
+        let contains = point == i64::MAX || schedule.contains(point);
+        let aligned =
+            schedule.chunk_start == i64::MIN || schedule.chunk_start.rem_euclid(chunk_size) == 0;
+        let sized = schedule.chunk_end - schedule.chunk_start <= chunk_size;
+        contains && aligned && sized
+    }
+
+    #[quickcheck]
+    fn schedule_contains_its_point(point: i64, chunk_size: u32) -> bool {
+        schedule_is_consistent(point, chunk_size.max(1) as i64)
+    }
+
+    #[test]
+    fn should_schedule_extreme_times() {
+        for point in [i64::MIN, i64::MIN + 1, -1, 0, i64::MAX - 1, i64::MAX] {
+            for chunk_size in [1, 7, 3_600_000, i64::MAX] {
+                assert!(
+                    schedule_is_consistent(point, chunk_size),
+                    "{point} {chunk_size}"
+                );
+            }
+        }
+        let config = ScheduleConfig::new(10).unwrap();
+        let schedule = config.init_schedule_from_time(-1);
+        assert_eq!((schedule.chunk_start, schedule.chunk_end), (-10, 0));
+    }
+
     #[quickcheck]
     fn matches_fake(cmds: Vec<Cmd>) -> bool {
         kelpie_eq_fake(&cmds).is_ok()
     }
 
+    #[quickcheck]
+    fn matches_fake_on_disk(cmds: Vec<Cmd>) -> bool {
+        kelpie_eq_fake_with(Backing::Path, &cmds).is_ok()
+    }
+
+    #[quickcheck]
+    fn matches_fake_with_cached_chunks(cmds: Vec<Cmd>) -> bool {
+        kelpie_eq_fake_with(Backing::Cached(three_per_series()), &cmds).is_ok()
+    }
+
+    #[quickcheck]
+    fn matches_fake_with_shared_cache(cmds: Vec<Cmd>) -> bool {
+        // few series and close times, so that series compete for the cache
+        let cmds: Vec<Cmd> = cmds
+            .into_iter()
+            .map(|cmd| match cmd {
+                Cmd::Insert { series_key, point } => Cmd::Insert {
+                    series_key: series_key % 4,
+                    point: DataPoint {
+                        time: point.time % (8 * 3_600_000),
+                        ..point
+                    },
+                },
+                cmd => cmd,
+            })
+            .collect();
+        kelpie_eq_fake_with(Backing::Cached(CacheConfig::lru(3)), &cmds).is_ok()
+    }
+
+    fn three_per_series() -> CacheConfig {
+        CacheConfig {
+            chunks_per_series: 3,
+            ..CacheConfig::default()
+        }
+    }
+
+    #[quickcheck]
+    fn matches_fake_with_duplicate_policy(cmds: Vec<Cmd>, policy: u8) -> bool {
+        let policy = [
+            DuplicatePolicy::LastWins,
+            DuplicatePolicy::FirstWins,
+            DuplicatePolicy::Error,
+            DuplicatePolicy::Sum,
+        ][policy as usize % 4];
+        // few series and times, so that inserts hit existing points
+        let cmds: Vec<Cmd> = cmds
+            .into_iter()
+            .map(|cmd| match cmd {
+                Cmd::Insert { series_key, point } => Cmd::Insert {
+                    series_key: series_key % 4,
+                    point: DataPoint {
+                        time: point.time % 64 * 100_000,
+                        ..point
+                    },
+                },
+                cmd => cmd,
+            })
+            .collect();
+        kelpie_eq_fake_with_policy(Backing::Cached(three_per_series()), policy, &cmds).is_ok()
+    }
+
+    #[quickcheck]
+    fn matches_fake_with_align_offset(cmds: Vec<Cmd>, offset: u32) -> bool {
+        let hour = 3_600_000;
+        let config = ScheduleConfig::default()
+            .with_align_offset(i64::from(offset) % hour)
+            .expect("offset is within an hour");
+        kelpie_eq_fake_with(Backing::Schedule(config), &cmds).is_ok()
+    }
+
+    #[test]
+    fn should_cache_chunks_across_boundaries() -> Result<(), Box<dyn std::error::Error>> {
+        use rand::prelude::*;
+        use rand::rngs::SmallRng;
+
+        let hour = 3_600_000;
+        let mut rng = SmallRng::seed_from_u64(7);
+        // out of order points straddling each hour boundary
+        let mut cmds = vec![];
+        for boundary in 1..20 {
+            for _ in 0..50 {
+                let time = boundary * hour + rng.gen_range(-1000..1000);
+                let point = DataPoint { time, value: 1.0 };
+                cmds.push(Cmd::Insert {
+                    series_key: rng.gen_range(0..2),
+                    point,
+                });
+            }
+            cmds.push(Cmd::Query {
+                series_key: 0,
+                start: (boundary - 1) * hour,
+                stop: (boundary + 1) * hour,
+            });
+        }
+        let two_per_series = CacheConfig {
+            chunks_per_series: 2,
+            ..CacheConfig::default()
+        };
+        let configs = [CacheConfig::default(), two_per_series, CacheConfig::lru(4)];
+        let mut writes = vec![];
+        for cache in configs {
+            kelpie_eq_fake_with(Backing::Cached(cache), &cmds)?;
+            let mut kelpie = counting_kelpie(ScheduleConfig::default())?;
+            kelpie.set_cache_config(cache)?;
+            for cmd in &cmds {
+                if let Cmd::Insert { series_key, point } = *cmd {
+                    kelpie.insert(series_key, point)?;
+                }
+            }
+            kelpie.flush_all()?;
+            writes.push(kelpie.chunk_store.writes.get());
+        }
+        if writes[1] * 5 > writes[0] || writes[2] * 5 > writes[0] {
+            Err(format!("caching two chunks should save writes: {writes:?}"))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_evict_least_recently_used_chunk_of_any_series(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let mut kelpie = counting_kelpie(ScheduleConfig::default())?;
+        kelpie.set_cache_config(CacheConfig::lru(2))?;
+        let point = |time| DataPoint { time, value: 1.0 };
+        kelpie.insert(0, point(0))?;
+        kelpie.insert(0, point(hour))?;
+        assert_eq!(kelpie.chunk_store.writes.get(), 0);
+        // the chunk of series 0 at hour 0 is the least recently used
+        assert_eq!(kelpie.insert(1, point(0))?, InsertStatus::Cached);
+        assert_eq!(kelpie.chunk_store.writes.get(), 1);
+        assert!(!kelpie.is_cached(0, 0));
+        assert!(kelpie.is_cached(0, hour));
+        // series 0 is now the least recently used, so its own chunk makes room
+        assert_eq!(
+            kelpie.insert(0, point(2 * hour))?,
+            InsertStatus::CompactmentPending(Schedule {
+                chunk_start: hour,
+                chunk_end: 2 * hour,
+            })
+        );
+        assert!(kelpie.is_cached(1, 0));
+
+        // shrinking the cache evicts right away
+        kelpie.set_cache_config(CacheConfig::lru(1))?;
+        assert_eq!(kelpie.cached_count(), 1);
+        assert!(kelpie.is_cached(0, 2 * hour));
+        assert_eq!(kelpie.count(0, 0, i64::MAX)?, 3);
+        assert_eq!(kelpie.count(1, 0, i64::MAX)?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn should_keep_chunk_cached_when_eviction_fails() -> Result<(), Box<dyn std::error::Error>> {
+        let hour = 3_600_000;
+        let mut kelpie = Kelpie::new_memory()?;
+        let point = |time| DataPoint { time, value: 1.0 };
+        kelpie.insert(0, point(10))?;
+        kelpie.chunk_store.connection().execute(
+            "CREATE TRIGGER fail BEFORE INSERT ON chunks BEGIN SELECT RAISE(ABORT, 'fail'); END",
+        )?;
+        match kelpie.insert(0, point(hour)) {
+            Err(InsertError::Write(_)) => {}
+            res => Err(format!("eviction should fail: {res:?}"))?,
+        }
+        match kelpie.insert_many(0, &[point(hour)]) {
+            Err(BatchError::Write(_)) => {}
+            res => Err(format!("eviction should fail: {res:?}"))?,
+        }
+        assert!(kelpie.is_cached(0, 0));
+        assert_eq!(kelpie.query(0, 0, i64::MAX)?.len(), 1);
+
+        kelpie
+            .chunk_store
+            .connection()
+            .execute("DROP TRIGGER fail")?;
+        kelpie.insert(0, point(hour))?;
+        kelpie.flush_all()?;
+        assert_eq!(kelpie.count(0, 0, i64::MAX)?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn should_match_fake_across_reopen() -> Result<(), Box<dyn std::error::Error>> {
+        use Cmd::*;
+        let series_key = 0;
+        let cmds = vec![
+            Insert {
+                series_key,
+                point: DataPoint {
+                    time: 10,
+                    value: 1.0,
+                },
+            },
+            Reopen,
+            Insert {
+                series_key,
+                point: DataPoint {
+                    time: 20,
+                    value: 2.0,
+                },
+            },
+            Reopen,
+            Query {
+                series_key,
+                start: 0,
+                stop: 100,
+            },
+        ];
+        kelpie_eq_fake_with(Backing::Memory, &cmds)?;
+        kelpie_eq_fake_with(Backing::Path, &cmds)?;
+        Ok(())
+    }
+
     /*
 
     */
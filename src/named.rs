@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use crate::{
+    DataPoint, GetChunkError, InsertError, InsertStatus, Kelpie, KelpieChunkStore, RawSeries,
+    SetChunkError, SqliteChunkStore,
+};
+
+/// Derives the series key of a name.
+pub type KeyFn = fn(&str) -> i64;
+
+/// The default `KeyFn`: 64-bit FNV-1a of the name's UTF-8 bytes.
+///
+/// Unlike `DefaultHasher` it is not seeded, so keys are the same in every process and
+/// stay the same across versions.
+pub fn fnv1a_key(name: &str) -> i64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash as i64
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum NameError {
+    #[error("Series {name:?} maps to key {key}, which is taken by {existing:?}")]
+    KeyCollision {
+        name: String,
+        existing: String,
+        key: i64,
+    },
+    #[error(transparent)]
+    Insert(#[from] InsertError),
+    #[error(transparent)]
+    Get(#[from] GetChunkError),
+    #[error(transparent)]
+    Set(#[from] SetChunkError),
+}
+
+/// Addresses series by name instead of by key.
+///
+/// The name each key was derived from is recorded in the store, so a second name
+/// hashing to the same key is rejected instead of silently sharing its series.
+pub struct NamedKelpie<S: KelpieChunkStore = SqliteChunkStore> {
+    kelpie: Kelpie<S>,
+    key_fn: KeyFn,
+    // names already checked against the store
+    known: HashMap<i64, String>,
+}
+
+impl<S: KelpieChunkStore> NamedKelpie<S> {
+    pub fn new(kelpie: Kelpie<S>) -> Self {
+        Self::with_key_fn(kelpie, fnv1a_key)
+    }
+
+    /// Uses `key_fn` instead of `fnv1a_key`. A store must always be opened with the
+    /// same function, or names resolve to different series.
+    pub fn with_key_fn(kelpie: Kelpie<S>, key_fn: KeyFn) -> Self {
+        Self {
+            kelpie,
+            key_fn,
+            known: HashMap::new(),
+        }
+    }
+
+    pub fn kelpie(&self) -> &Kelpie<S> {
+        &self.kelpie
+    }
+
+    pub fn into_inner(self) -> Kelpie<S> {
+        self.kelpie
+    }
+
+    /// Returns the key of `name`, registering the name if the key is still free.
+    pub fn key(&mut self, name: &str) -> Result<i64, NameError> {
+        let key = (self.key_fn)(name);
+        if !self.is_registered(name, key)? {
+            self.kelpie.chunk_store.set_series_name(key, name)?;
+            self.known.insert(key, name.to_string());
+        }
+        Ok(key)
+    }
+
+    pub fn insert(&mut self, name: &str, data_point: DataPoint) -> Result<InsertStatus, NameError> {
+        let key = self.key(name)?;
+        Ok(self.kelpie.insert(key, data_point)?)
+    }
+
+    pub fn query(&mut self, name: &str, start: i64, stop: i64) -> Result<RawSeries, NameError> {
+        let key = (self.key_fn)(name);
+        if !self.is_registered(name, key)? {
+            return Ok(RawSeries::new());
+        }
+        Ok(self.kelpie.query(key, start, stop)?)
+    }
+
+    // whether `name` is registered under `key`, or an error if another name is
+    fn is_registered(&mut self, name: &str, key: i64) -> Result<bool, NameError> {
+        let existing = match self.known.get(&key) {
+            Some(existing) => Some(existing.clone()),
+            None => self.kelpie.chunk_store.get_series_name(key)?,
+        };
+        match existing {
+            Some(existing) if existing == name => {
+                self.known.insert(key, existing);
+                Ok(true)
+            }
+            Some(existing) => Err(NameError::KeyCollision {
+                name: name.to_string(),
+                existing,
+                key,
+            }),
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fnv1a_key, NameError, NamedKelpie};
+    use crate::tests::TempPath;
+    use crate::{DataPoint, Kelpie};
+
+    #[test]
+    fn should_derive_stable_keys() {
+        // must never change, stores depend on it
+        assert_eq!(fnv1a_key("cpu,host=a"), -40133807838434617);
+        assert_ne!(fnv1a_key("cpu,host=a"), fnv1a_key("cpu,host=b"));
+    }
+
+    #[test]
+    fn should_reject_key_collisions() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let point = DataPoint {
+            time: 10,
+            value: 1.0,
+        };
+        {
+            let mut named = NamedKelpie::with_key_fn(Kelpie::new_path(temp.path())?, |_| 7);
+            named.insert("cpu", point)?;
+            named.into_inner().flush_all()?;
+        }
+        let mut named = NamedKelpie::with_key_fn(Kelpie::new_path(temp.path())?, |_| 7);
+        assert_eq!(named.query("cpu", 0, 100)?.data.len(), 1);
+        match named.insert("mem", point) {
+            Err(NameError::KeyCollision { existing, key, .. }) => {
+                assert_eq!((existing.as_str(), key), ("cpu", 7));
+            }
+            _ => Err("colliding name should be rejected")?,
+        }
+        if named.query("mem", 0, 100).is_ok() {
+            Err("colliding name should not read the other series")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+
+use crate::{
+    Chunk, ChunkMeta, GetChunkError, JournalMode, KelpieChunkStore, SetChunkError,
+    SqliteChunkStore, StoreOptions,
+};
+
+/// SQLite chunk store with one writer connection and a pool of read-only connections.
+///
+/// The database is always put in WAL mode, which is what lets the readers run next to
+/// each other and next to the writer: without it a write would lock every reader out.
+/// As WAL only works on files, there is no in-memory variant.
+///
+/// Reads through a shared reference run in parallel on up to `readers` connections;
+/// further reads wait for a connection to be returned. Writes take `&mut self` and stay
+/// serialized on the writer. A `Kelpie` built on this store runs queries in parallel
+/// when shared with `SharedKelpie`.
+///
+/// A reader sees every write committed before its read started.
+pub struct PooledSqliteChunkStore {
+    writer: SqliteChunkStore,
+    readers: Mutex<Vec<SqliteChunkStore>>,
+    returned: Condvar,
+}
+
+impl PooledSqliteChunkStore {
+    /// Opens or creates a store at `path` with `readers` read connections, at least one.
+    pub fn new_path<T: AsRef<Path>>(path: T, readers: usize) -> Result<Self, sqlite::Error> {
+        Self::new_path_with(path, StoreOptions::default(), readers)
+    }
+
+    /// Like `new_path`, with `options` applied to every connection. The journal mode
+    /// is always WAL, whatever `options` says.
+    pub fn new_path_with<T: AsRef<Path>>(
+        path: T,
+        options: StoreOptions,
+        readers: usize,
+    ) -> Result<Self, sqlite::Error> {
+        let options = StoreOptions {
+            journal_mode: JournalMode::Wal,
+            ..options
+        };
+        let writer = SqliteChunkStore::new_path_with(path.as_ref(), options)?;
+        let readers = (0..readers.max(1))
+            .map(|_| SqliteChunkStore::open_read_only(path.as_ref(), &options))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            writer,
+            readers: Mutex::new(readers),
+            returned: Condvar::new(),
+        })
+    }
+
+    // waits until a reader is free and takes it out of the pool until the guard drops
+    fn reader(&self) -> Reader<'_> {
+        let mut readers = self
+            .readers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        loop {
+            if let Some(store) = readers.pop() {
+                return Reader {
+                    pool: self,
+                    store: Some(store),
+                };
+            }
+            readers = self
+                .returned
+                .wait(readers)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+}
+
+struct Reader<'a> {
+    pool: &'a PooledSqliteChunkStore,
+    store: Option<SqliteChunkStore>,
+}
+
+impl Deref for Reader<'_> {
+    type Target = SqliteChunkStore;
+
+    fn deref(&self) -> &SqliteChunkStore {
+        self.store.as_ref().expect("reader is only taken on drop")
+    }
+}
+
+impl Drop for Reader<'_> {
+    fn drop(&mut self) {
+        if let Some(store) = self.store.take() {
+            self.pool
+                .readers
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .push(store);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+impl KelpieChunkStore for PooledSqliteChunkStore {
+    fn get_chunk(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<Option<(ChunkMeta, Chunk)>, GetChunkError> {
+        self.reader().get_chunk(series_key, start, stop)
+    }
+
+    fn get_chunks_in_range(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(ChunkMeta, Chunk)>, GetChunkError> {
+        self.reader().get_chunks_in_range(series_key, start, stop)
+    }
+
+    fn get_chunks_in_value_range(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<(ChunkMeta, Chunk)>, GetChunkError> {
+        self.reader()
+            .get_chunks_in_value_range(series_key, start, stop, min, max)
+    }
+
+    fn set_chunk(
+        &mut self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        chunk: &Chunk,
+    ) -> Result<(), SetChunkError> {
+        self.writer.set_chunk(series_key, start, stop, chunk)
+    }
+
+    fn set_chunks(&mut self, chunks: &[(ChunkMeta, Chunk)]) -> Result<(), SetChunkError> {
+        self.writer.set_chunks(chunks)
+    }
+
+    fn replace_chunks(
+        &mut self,
+        remove: &[ChunkMeta],
+        add: &[(ChunkMeta, Chunk)],
+    ) -> Result<(), SetChunkError> {
+        self.writer.replace_chunks(remove, add)
+    }
+
+    fn truncate(&mut self) -> Result<(), SetChunkError> {
+        self.writer.truncate()
+    }
+
+    fn delete_series(&mut self, series_key: i64) -> Result<(), SetChunkError> {
+        self.writer.delete_series(series_key)
+    }
+
+    fn delete_chunks_before(&mut self, series_key: i64, time: i64) -> Result<usize, SetChunkError> {
+        self.writer.delete_chunks_before(series_key, time)
+    }
+
+    fn set_series_metadata(&mut self, series_key: i64, json: &str) -> Result<(), SetChunkError> {
+        self.writer.set_series_metadata(series_key, json)
+    }
+
+    fn get_series_metadata(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+        self.reader().get_series_metadata(series_key)
+    }
+
+    fn set_series_name(&mut self, series_key: i64, name: &str) -> Result<(), SetChunkError> {
+        self.writer.set_series_name(series_key, name)
+    }
+
+    fn get_series_name(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+        self.reader().get_series_name(series_key)
+    }
+
+    fn set_series_schedule(
+        &mut self,
+        series_key: i64,
+        schedule: &str,
+    ) -> Result<(), SetChunkError> {
+        self.writer.set_series_schedule(series_key, schedule)
+    }
+
+    fn get_series_schedule(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+        self.reader().get_series_schedule(series_key)
+    }
+
+    fn list_all_chunks(&self) -> Result<Vec<ChunkMeta>, GetChunkError> {
+        self.reader().list_all_chunks()
+    }
+
+    fn list_chunks(&self, series_key: i64) -> Result<Vec<ChunkMeta>, GetChunkError> {
+        self.reader().list_chunks(series_key)
+    }
+
+    fn list_series(&self) -> Result<Vec<i64>, GetChunkError> {
+        self.reader().list_series()
+    }
+
+    fn time_bounds(&self, series_key: i64) -> Result<Option<(i64, i64)>, GetChunkError> {
+        self.reader().time_bounds(series_key)
+    }
+
+    fn checkpoint(&mut self) -> Result<(), SetChunkError> {
+        self.writer.checkpoint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::PooledSqliteChunkStore;
+    use crate::tests::TempPath;
+    use crate::{Chunk, DataPoint, Kelpie, KelpieChunkStore, RawSeries, ScheduleConfig};
+
+    #[test]
+    fn should_read_in_parallel() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let temp = TempPath::new();
+        let mut store = PooledSqliteChunkStore::new_path(temp.path(), 4)?;
+        for series_key in 0..8 {
+            let series = RawSeries::from(
+                &[DataPoint {
+                    time: 10,
+                    value: series_key as f64,
+                }][..],
+            );
+            store.set_chunk(series_key, 0, 100, &Chunk::compress_series(&series))?;
+        }
+
+        let store = &store;
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..16)
+                .map(|thread| {
+                    scope.spawn(move || -> Result<(), Box<dyn Error + Send + Sync>> {
+                        for i in 0..200 {
+                            let series_key = (thread + i) % 8;
+                            let (_, chunk) = store
+                                .get_chunk(series_key, 10, 11)?
+                                .ok_or("chunk should exist")?;
+                            let series = chunk.decompress()?;
+                            if series.get(10) != Some(series_key as f64) {
+                                Err(format!("wrong chunk for series {series_key}"))?;
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .try_for_each(|thread| thread.join().map_err(|_| "thread panicked")?)
+        })?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_read_own_writes() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempPath::new();
+        let store = PooledSqliteChunkStore::new_path(temp.path(), 0)?;
+        let mut kelpie = Kelpie::with_store(store, ScheduleConfig::default());
+        for time in 0..100 {
+            kelpie.insert(
+                3,
+                DataPoint {
+                    time,
+                    value: time as f64,
+                },
+            )?;
+        }
+        kelpie.flush_all()?;
+
+        assert_eq!(kelpie.query(3, 0, 100)?.len(), 100);
+        // written by the writer, read back through a pooled reader
+        let stored: usize = (kelpie.chunk_store.get_chunks_in_range(3, 0, 100)?)
+            .iter()
+            .map(|(_, chunk)| chunk.decompress().map(|series| series.len()))
+            .sum::<Result<_, _>>()?;
+        assert_eq!(stored, 100);
+        assert_eq!(kelpie.chunk_store.list_series()?, vec![3]);
+        Ok(())
+    }
+}
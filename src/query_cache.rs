@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::RawSeries;
+
+// bounded so a scan over many distinct windows can't grow the cache forever
+const QUERY_CACHE_CAPACITY: usize = 64;
+
+/// Shared results of recent queries, keyed by `(series_key, start, stop)`.
+///
+/// Entries of a series must be invalidated whenever the series is written.
+#[derive(Default)]
+pub(crate) struct QueryCache {
+    entries: HashMap<(i64, i64, i64), Arc<RawSeries>>,
+}
+
+impl QueryCache {
+    pub(crate) fn get(&self, series_key: i64, start: i64, stop: i64) -> Option<Arc<RawSeries>> {
+        self.entries.get(&(series_key, start, stop)).cloned()
+    }
+
+    pub(crate) fn insert(&mut self, series_key: i64, start: i64, stop: i64, res: Arc<RawSeries>) {
+        if self.entries.len() >= QUERY_CACHE_CAPACITY {
+            self.entries.clear();
+        }
+        self.entries.insert((series_key, start, stop), res);
+    }
+
+    pub(crate) fn invalidate(&mut self, series_key: i64) {
+        self.entries.retain(|&(key, _, _), _| key != series_key);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
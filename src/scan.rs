@@ -0,0 +1,188 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+use crate::{
+    Chunk, ChunkMeta, GetChunkError, Kelpie, KelpieChunkStore, RawSeries, Series, SqliteChunkStore,
+};
+
+/// Iterator over the chunks of a series overlapping a time range, in time order.
+///
+/// Chunk blobs are read from the store `prefetch` at a time and decompressed one by one
+/// as the iterator advances. Each item holds the points of one chunk within the range.
+pub struct ChunkScan<'a, S: KelpieChunkStore = SqliteChunkStore> {
+    kelpie: &'a Kelpie<S>,
+    series_key: i64,
+    start: i64,
+    stop: i64,
+    prefetch: usize,
+    // chunks not read from the store yet
+    pending: VecDeque<ChunkMeta>,
+    // chunks read from the store but not decompressed yet
+    fetched: VecDeque<Chunk>,
+}
+
+impl<'a, S: KelpieChunkStore> ChunkScan<'a, S> {
+    pub(crate) fn new(
+        kelpie: &'a Kelpie<S>,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<Self, GetChunkError> {
+        let mut pending: Vec<ChunkMeta> = kelpie
+            .chunk_store
+            .list_chunks(series_key)?
+            .into_iter()
+            .filter(|meta| meta.start < stop && start < meta.stop)
+            .collect();
+        // cached chunks take the place of the stored chunks they were loaded from
+        for series in kelpie.cached(series_key) {
+            let meta = ChunkMeta {
+                series_key,
+                start: series.schedule.chunk_start,
+                stop: series.schedule.chunk_end,
+            };
+            if meta.start < stop && start < meta.stop {
+                pending.retain(|m| (m.start, m.stop) != (meta.start, meta.stop));
+                pending.push(meta);
+            }
+        }
+        pending.sort_by_key(|m| (m.start, m.stop));
+        Ok(Self {
+            kelpie,
+            series_key,
+            start,
+            stop,
+            prefetch: kelpie.prefetch_chunks,
+            pending: pending.into(),
+            fetched: VecDeque::new(),
+        })
+    }
+
+    fn cached_chunk(&self, meta: &ChunkMeta) -> Option<&'a Series> {
+        let kelpie: &'a Kelpie<S> = self.kelpie;
+        kelpie.cached(self.series_key).find(|series| {
+            (series.schedule.chunk_start, series.schedule.chunk_end) == (meta.start, meta.stop)
+        })
+    }
+
+    fn is_cached(&self, meta: &ChunkMeta) -> bool {
+        self.cached_chunk(meta).is_some()
+    }
+
+    // reads the next run of stored chunks, up to `prefetch` of them, in a single query
+    fn fetch(&mut self) -> Result<(), GetChunkError> {
+        let mut run = 0;
+        while run < self.pending.len() && run < self.prefetch && !self.is_cached(&self.pending[run])
+        {
+            run += 1;
+        }
+        let batch: Vec<ChunkMeta> = self.pending.drain(..run).collect();
+        let (Some(first), Some(last)) = (batch.first(), batch.last()) else {
+            return Ok(());
+        };
+        let chunks =
+            self.kelpie
+                .chunk_store
+                .get_chunks_in_range(self.series_key, first.start, last.stop)?;
+        // the range may also hold chunks outside the run, such as stored copies of
+        // cached chunks
+        self.fetched.extend(
+            chunks
+                .into_iter()
+                .filter(|(meta, _)| {
+                    batch
+                        .iter()
+                        .any(|m| (m.start, m.stop) == (meta.start, meta.stop))
+                })
+                .map(|(_meta, chunk)| chunk),
+        );
+        Ok(())
+    }
+
+    fn clip(&self, mut series: RawSeries) -> RawSeries {
+        series
+            .data
+            .retain(|&time, _| self.start <= time && time < self.stop);
+        series
+    }
+
+    // the next chunk in time order, still compressed if it comes from the store
+    fn next_chunk(&mut self) -> Option<Result<Next<'a>, GetChunkError>> {
+        loop {
+            if let Some(chunk) = self.fetched.pop_front() {
+                return Some(Ok(Next::Stored(chunk)));
+            }
+            let meta = *self.pending.front()?;
+            if let Some(cached) = self.cached_chunk(&meta) {
+                self.pending.pop_front();
+                let data = self.kelpie.complete_data(self.series_key, cached);
+                return Some(data.map(Next::Cached));
+            }
+            if let Err(e) = self.fetch() {
+                self.pending.clear();
+                return Some(Err(e));
+            }
+        }
+    }
+
+    /// Like `next`, but yields only the times within the range. Stored chunks only
+    /// have their times decompressed.
+    pub(crate) fn next_times(&mut self) -> Option<Result<Vec<i64>, GetChunkError>> {
+        let range = self.start..self.stop;
+        Some(self.next_chunk()?.and_then(|next| match next {
+            Next::Stored(chunk) => {
+                let mut times = chunk.decompress_times().map_err(GetChunkError::Corrupt)?;
+                times.retain(|time| range.contains(time));
+                Ok(times)
+            }
+            Next::Cached(data) => Ok(data.data.range(range).map(|(&time, _)| time).collect()),
+        }))
+    }
+
+    /// Like `next`, but skips chunks without a point in the range. Stored chunks only
+    /// have their values decompressed if they have a time in the range.
+    pub(crate) fn next_non_empty(&mut self) -> Option<Result<RawSeries, GetChunkError>> {
+        loop {
+            let series = match self.next_chunk()? {
+                Ok(Next::Stored(chunk)) => {
+                    let times = match chunk.decompress_times() {
+                        Ok(times) => times,
+                        Err(e) => return Some(Err(GetChunkError::Corrupt(e))),
+                    };
+                    if !times
+                        .iter()
+                        .any(|time| (self.start..self.stop).contains(time))
+                    {
+                        continue;
+                    }
+                    chunk.decompress().map_err(GetChunkError::Corrupt)
+                }
+                Ok(Next::Cached(data)) => Ok(data.into_owned()),
+                Err(e) => Err(e),
+            };
+            let series = series.map(|series| self.clip(series));
+            if series.as_ref().is_ok_and(|series| series.is_empty()) {
+                continue;
+            }
+            return Some(series);
+        }
+    }
+}
+
+enum Next<'a> {
+    Stored(Chunk),
+    // merged with the stored points if the cached chunk lacks them
+    Cached(Cow<'a, RawSeries>),
+}
+
+impl<S: KelpieChunkStore> Iterator for ChunkScan<'_, S> {
+    type Item = Result<RawSeries, GetChunkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let series = self.next_chunk()?.and_then(|next| match next {
+            Next::Stored(chunk) => chunk.decompress().map_err(GetChunkError::Corrupt),
+            Next::Cached(data) => Ok(data.into_owned()),
+        });
+        Some(series.map(|series| self.clip(series)))
+    }
+}
@@ -0,0 +1,170 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::{
+    DataPoint, GetChunkError, InsertError, InsertStatus, Kelpie, KelpieChunkStore, RawSeries,
+    SetChunkError, SqliteChunkStore,
+};
+
+/// A `Kelpie` that can be cloned and used from several threads.
+///
+/// Queries share a read lock and run in parallel with each other, as far as the chunk
+/// store allows: `PooledSqliteChunkStore` reads on several connections at once, while
+/// `SqliteChunkStore` takes turns on its single connection. Inserts, flushes and `with`
+/// take the write lock, so they wait for running queries and the other way around.
+pub struct SharedKelpie<S: KelpieChunkStore + Send + Sync = SqliteChunkStore> {
+    kelpie: Arc<RwLock<Kelpie<S>>>,
+}
+
+impl<S: KelpieChunkStore + Send + Sync> Clone for SharedKelpie<S> {
+    fn clone(&self) -> Self {
+        Self {
+            kelpie: self.kelpie.clone(),
+        }
+    }
+}
+
+impl<S: KelpieChunkStore + Send + Sync> SharedKelpie<S> {
+    pub fn new(kelpie: Kelpie<S>) -> Self {
+        Self {
+            kelpie: Arc::new(RwLock::new(kelpie)),
+        }
+    }
+
+    pub fn insert(
+        &self,
+        series_key: i64,
+        data_point: DataPoint,
+    ) -> Result<InsertStatus, InsertError> {
+        write(&self.kelpie).insert(series_key, data_point)
+    }
+
+    pub fn query(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<RawSeries, GetChunkError> {
+        read(&self.kelpie).query(series_key, start, stop)
+    }
+
+    pub fn flush(&self, series_key: i64) -> Result<(), SetChunkError> {
+        write(&self.kelpie).flush(series_key)
+    }
+
+    pub fn flush_all(&self) -> Result<(), SetChunkError> {
+        write(&self.kelpie).flush_all()
+    }
+
+    /// Runs `f` with shared access to the `Kelpie`, for queries not covered by `query`.
+    /// Runs in parallel with other reads.
+    pub fn read<R>(&self, f: impl FnOnce(&Kelpie<S>) -> R) -> R {
+        f(&read(&self.kelpie))
+    }
+
+    /// Runs `f` with exclusive access to the `Kelpie`, for anything not covered by the
+    /// other methods. Every other clone waits until `f` returns.
+    pub fn with<R>(&self, f: impl FnOnce(&mut Kelpie<S>) -> R) -> R {
+        f(&mut write(&self.kelpie))
+    }
+
+    /// Returns the `Kelpie` if this is the last clone, or gives the handle back.
+    pub fn try_into_inner(self) -> Result<Kelpie<S>, Self> {
+        match Arc::try_unwrap(self.kelpie) {
+            Ok(kelpie) => Ok(kelpie
+                .into_inner()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())),
+            Err(kelpie) => Err(Self { kelpie }),
+        }
+    }
+}
+
+// a panic while holding the lock leaves the cache as consistent as any unflushed state
+fn read<S: KelpieChunkStore>(kelpie: &RwLock<Kelpie<S>>) -> RwLockReadGuard<'_, Kelpie<S>> {
+    kelpie
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn write<S: KelpieChunkStore>(kelpie: &RwLock<Kelpie<S>>) -> RwLockWriteGuard<'_, Kelpie<S>> {
+    kelpie
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::sync::Barrier;
+
+    use super::SharedKelpie;
+    use crate::tests::TempPath;
+    use crate::{DataPoint, Kelpie, PooledSqliteChunkStore, ScheduleConfig};
+
+    #[test]
+    fn should_insert_from_several_threads() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let temp = TempPath::new();
+        let kelpie = Kelpie::new_path_with_config(temp.path(), ScheduleConfig::new(100)?)?;
+        let shared = SharedKelpie::new(kelpie);
+        let threads: Vec<_> = (0..8)
+            .map(|series_key| {
+                let shared = shared.clone();
+                std::thread::spawn(move || -> Result<(), Box<dyn Error + Send + Sync>> {
+                    for time in 0..1000 {
+                        let point = DataPoint {
+                            time,
+                            value: series_key as f64,
+                        };
+                        shared.insert(series_key, point)?;
+                        if time % 250 == 0 {
+                            shared.query(series_key, 0, time + 1)?;
+                        }
+                    }
+                    Ok(shared.flush(series_key)?)
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().map_err(|_| "thread panicked")??;
+        }
+
+        let kelpie = shared.try_into_inner().map_err(|_| "handle still shared")?;
+        for series_key in 0..8 {
+            let res = kelpie.query(series_key, 0, 1000)?;
+            assert_eq!(res.len(), 1000);
+            assert!(res.iter().all(|point| point.value == series_key as f64));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_query_in_parallel() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let temp = TempPath::new();
+        let store = PooledSqliteChunkStore::new_path(temp.path(), 4)?;
+        let shared = SharedKelpie::new(Kelpie::with_store(store, ScheduleConfig::new(100)?));
+        for time in 0..1000 {
+            shared.insert(0, DataPoint { time, value: 1.0 })?;
+        }
+        shared.flush_all()?;
+
+        // every thread holds the read lock until all of them do, which would never
+        // happen if reads took turns
+        let barrier = Barrier::new(4);
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        shared.read(|kelpie| {
+                            barrier.wait();
+                            kelpie.query(0, 0, 1000).map(|res| res.len())
+                        })
+                    })
+                })
+                .collect();
+            for thread in threads {
+                let len = thread.join().map_err(|_| "thread panicked")??;
+                assert_eq!(len, 1000);
+            }
+            Ok(())
+        })
+    }
+}
@@ -1,15 +1,33 @@
-use crate::Chunk;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use crate::{Chunk, DecompressError};
 
 #[derive(thiserror::Error, Debug)]
 pub enum SetChunkError {
     #[error("Driver error")]
-    Driver(#[from] Box<dyn std::error::Error>),
+    Driver(#[from] Box<dyn std::error::Error + Send + Sync>),
+    #[error("Series {0} already has data")]
+    SeriesNotEmpty(i64),
+    #[error("Corrupt chunk")]
+    Corrupt(#[source] DecompressError),
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum GetChunkError {
     #[error("Driver error")]
-    Driver(#[from] Box<dyn std::error::Error>),
+    Driver(#[from] Box<dyn std::error::Error + Send + Sync>),
+    #[error("Corrupt chunk")]
+    Corrupt(#[source] DecompressError),
+}
+
+impl From<GetChunkError> for SetChunkError {
+    fn from(e: GetChunkError) -> Self {
+        match e {
+            GetChunkError::Driver(e) => SetChunkError::Driver(e),
+            GetChunkError::Corrupt(e) => SetChunkError::Corrupt(e),
+        }
+    }
 }
 
 pub trait KelpieChunkStore {
@@ -19,6 +37,26 @@ pub trait KelpieChunkStore {
         start: i64,
         stop: i64,
     ) -> Result<Option<(ChunkMeta, Chunk)>, GetChunkError>;
+    /// Returns every chunk of a series overlapping `[start, stop)`, ordered by start.
+    fn get_chunks_in_range(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(ChunkMeta, Chunk)>, GetChunkError>;
+    /// Like `get_chunks_in_range`, but may leave out chunks known to hold no value in
+    /// `[min, max]`. Stores without an index of chunk values return every chunk.
+    fn get_chunks_in_value_range(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<(ChunkMeta, Chunk)>, GetChunkError> {
+        let _ = (min, max);
+        self.get_chunks_in_range(series_key, start, stop)
+    }
     fn set_chunk(
         &mut self,
         series_key: i64,
@@ -26,32 +64,395 @@ pub trait KelpieChunkStore {
         stop: i64,
         chunk: &Chunk,
     ) -> Result<(), SetChunkError>;
+    /// Writes all chunks atomically: either every chunk is stored or none is.
+    fn set_chunks(&mut self, chunks: &[(ChunkMeta, Chunk)]) -> Result<(), SetChunkError>;
+    /// Atomically deletes the exact chunks in `remove` and writes the chunks in `add`.
+    fn replace_chunks(
+        &mut self,
+        remove: &[ChunkMeta],
+        add: &[(ChunkMeta, Chunk)],
+    ) -> Result<(), SetChunkError>;
+    /// Removes all chunks and metadata of every series, keeping the store usable.
+    fn truncate(&mut self) -> Result<(), SetChunkError>;
+    /// Removes all chunks and metadata of a series.
+    fn delete_series(&mut self, series_key: i64) -> Result<(), SetChunkError>;
+    /// Removes every chunk of a series with `stop <= time`, returning how many there were.
+    fn delete_chunks_before(&mut self, series_key: i64, time: i64) -> Result<usize, SetChunkError>;
+    /// Stores an opaque metadata string for a series, replacing any previous one.
+    fn set_series_metadata(&mut self, series_key: i64, json: &str) -> Result<(), SetChunkError>;
+    fn get_series_metadata(&self, series_key: i64) -> Result<Option<String>, GetChunkError>;
+    /// Records the name a series key was derived from, replacing any previous one.
+    fn set_series_name(&mut self, series_key: i64, name: &str) -> Result<(), SetChunkError>;
+    fn get_series_name(&self, series_key: i64) -> Result<Option<String>, GetChunkError>;
+    /// Stores the encoded chunk schedule of a series, replacing any previous one. Removed
+    /// with the series like its metadata.
+    fn set_series_schedule(&mut self, series_key: i64, schedule: &str)
+        -> Result<(), SetChunkError>;
+    fn get_series_schedule(&self, series_key: i64) -> Result<Option<String>, GetChunkError>;
+    /// Lists the chunks of every series, ordered by series and start.
+    fn list_all_chunks(&self) -> Result<Vec<ChunkMeta>, GetChunkError>;
+    /// Lists the chunks stored for a series, ordered by start.
+    fn list_chunks(&self, series_key: i64) -> Result<Vec<ChunkMeta>, GetChunkError>;
+    /// Lists the keys of every series with at least one chunk, in ascending order.
+    fn list_series(&self) -> Result<Vec<i64>, GetChunkError>;
+    /// Returns the smallest chunk start and the largest chunk stop stored for a series.
+    fn time_bounds(&self, series_key: i64) -> Result<Option<(i64, i64)>, GetChunkError>;
+    /// Makes every completed write durable in the main storage. Stores that write in
+    /// place have nothing to do.
+    fn checkpoint(&mut self) -> Result<(), SetChunkError> {
+        Ok(())
+    }
+}
+
+/// How SQLite keeps a file consistent while it is written, see `PRAGMA journal_mode`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum JournalMode {
+    /// Rollback journal, deleted after every transaction.
+    #[default]
+    Delete,
+    /// Write-ahead log. Writers don't block readers and commits are cheaper, at the
+    /// cost of `-wal` and `-shm` files next to the database.
+    Wal,
+}
+
+/// How often SQLite waits for writes to reach the disk, see `PRAGMA synchronous`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Synchronous {
+    /// Syncs less often. In WAL mode, a power loss may roll back the last commits but
+    /// never corrupts the database.
+    Normal,
+    /// Syncs on every commit, so committed chunks survive a power loss.
+    #[default]
+    Full,
+}
+
+/// Connection settings for stores backed by a file.
+///
+/// The defaults favour durability. `bulk_load` trades some of it for faster ingest.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StoreOptions {
+    /// How long to wait for a lock held by another connection before failing with
+    /// `SQLITE_BUSY`.
+    pub busy_timeout: Duration,
+    /// Only applied to stores opened for writing.
+    pub journal_mode: JournalMode,
+    pub synchronous: Synchronous,
+    /// Page cache size, in pages if positive or in KiB if negative. SQLite's default
+    /// if `None`.
+    pub cache_size: Option<i64>,
+    /// Bytes of the file to access through memory mapping. SQLite's default if `None`.
+    pub mmap_size: Option<u64>,
+}
+
+impl Default for StoreOptions {
+    fn default() -> Self {
+        Self {
+            busy_timeout: Duration::from_secs(5),
+            journal_mode: JournalMode::default(),
+            synchronous: Synchronous::default(),
+            cache_size: None,
+            mmap_size: None,
+        }
+    }
+}
+
+impl StoreOptions {
+    /// WAL with `synchronous = NORMAL`, for backfills where throughput matters more
+    /// than keeping the last commits through a power loss.
+    pub fn bulk_load() -> Self {
+        Self {
+            journal_mode: JournalMode::Wal,
+            synchronous: Synchronous::Normal,
+            ..Self::default()
+        }
+    }
 }
 
+// Both lookups are served by the index SQLite creates for the `UNIQUE (series, start,
+// stop)` constraint of the chunks table, so no separate index is needed.
+// The compressed times and values of a chunk are stored in their own blob each, see
+// `Chunk::columns`. `values` is a keyword, so it's always quoted.
+// `min_val` and `max_val` hold the smallest and largest value of a chunk, both NULL
+// for an empty chunk, so range lookups can skip chunks without reading their blobs.
+const CHUNKS_COLUMNS: &str = "(series INTEGER, start INTEGER, stop INTEGER, times BLOB, \"values\" BLOB, min_val REAL, max_val REAL, UNIQUE (series, start, stop))";
+const GET_CHUNK: &str = "SELECT start, stop, times, \"values\" FROM chunks WHERE series == ? AND start <= ? AND stop >= ? ORDER BY start DESC, stop ASC LIMIT 1";
+const GET_CHUNKS_IN_RANGE: &str = "SELECT start, stop, times, \"values\" FROM chunks WHERE series == ? AND start < ? AND stop > ? ORDER BY start, stop";
+const GET_CHUNKS_IN_VALUE_RANGE: &str = "SELECT start, stop, times, \"values\" FROM chunks WHERE series == ? AND start < ? AND stop > ? AND max_val >= ? AND min_val <= ? ORDER BY start, stop";
+
+fn corrupt(message: String) -> sqlite::Error {
+    sqlite::Error {
+        code: Some(sqlite3_sys::SQLITE_CORRUPT as isize),
+        message: Some(message),
+    }
+}
+
+/// Chunk store backed by a single SQLite connection.
+///
+/// The connection is opened in serialized mode, so the store is `Sync` and reads
+/// through a shared reference may come from several threads. SQLite runs them one at a
+/// time; `PooledSqliteChunkStore` runs them in parallel.
 pub struct SqliteChunkStore {
-    db: sqlite::Connection,
+    db: sqlite::ConnectionThreadSafe,
+    // false for stores opened read-only that were written before series could have
+    // their own schedule, which then all use the default one
+    has_schedules: bool,
 }
 
 impl SqliteChunkStore {
     fn migrate(db: &mut sqlite::Connection) -> Result<(), sqlite::Error> {
-        db.execute("CREATE TABLE IF NOT EXISTS chunks (series INTEGER, start INTEGER, stop INTEGER, chunk BLOB, UNIQUE (series, start, stop))")?;
-        Ok(())
+        db.execute(format!(
+            "CREATE TABLE IF NOT EXISTS chunks {CHUNKS_COLUMNS}"
+        ))?;
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS series_meta (series INTEGER PRIMARY KEY, json TEXT)",
+        )?;
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS series_names (series INTEGER PRIMARY KEY, name TEXT NOT NULL)",
+        )?;
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS series_schedules (series INTEGER PRIMARY KEY, schedule TEXT NOT NULL)",
+        )?;
+        Self::split_chunk_blobs(db)?;
+        Self::index_value_ranges(db)
+    }
+
+    fn has_table(db: &sqlite::Connection, name: &str) -> Result<bool, sqlite::Error> {
+        let mut tables =
+            db.prepare("SELECT name FROM sqlite_master WHERE type == 'table' AND name == ?")?;
+        tables.bind((1, name))?;
+        Ok(matches!(tables.next()?, sqlite::State::Row))
+    }
+
+    fn has_chunks_column(db: &sqlite::Connection, name: &str) -> Result<bool, sqlite::Error> {
+        let mut found = false;
+        let mut columns = db.prepare("SELECT name FROM pragma_table_info('chunks')")?;
+        while let sqlite::State::Row = columns.next()? {
+            found |= columns.read::<String, _>("name")? == name;
+        }
+        Ok(found)
+    }
+
+    // runs a migration step in a transaction, so a failing step leaves the store as it was
+    fn migrate_step(
+        db: &sqlite::Connection,
+        step: impl FnOnce(&sqlite::Connection) -> Result<(), sqlite::Error>,
+    ) -> Result<(), sqlite::Error> {
+        db.execute("BEGIN")?;
+        let res = step(db);
+        db.execute(if res.is_ok() { "COMMIT" } else { "ROLLBACK" })?;
+        res
+    }
+
+    /// Moves stores written before the columns were kept apart, with one framed `chunk`
+    /// blob per row, to the current schema. A blob with malformed framing aborts the
+    /// migration and leaves the store as it was.
+    fn split_chunk_blobs(db: &mut sqlite::Connection) -> Result<(), sqlite::Error> {
+        if !Self::has_chunks_column(db, "chunk")? {
+            return Ok(());
+        }
+        Self::migrate_step(db, Self::copy_split_chunks)
+    }
+
+    /// Adds `min_val` and `max_val` to stores written before chunk values were indexed,
+    /// decompressing the values of every chunk once to fill them in. A malformed chunk
+    /// aborts the migration and leaves the store as it was.
+    fn index_value_ranges(db: &mut sqlite::Connection) -> Result<(), sqlite::Error> {
+        if Self::has_chunks_column(db, "min_val")? {
+            return Ok(());
+        }
+        Self::migrate_step(db, |db| {
+            db.execute("ALTER TABLE chunks ADD COLUMN min_val REAL")?;
+            db.execute("ALTER TABLE chunks ADD COLUMN max_val REAL")?;
+            // collected first, rather than updating rows the select is still visiting
+            let mut ranges = vec![];
+            let mut read = db.prepare("SELECT rowid, \"values\" FROM chunks")?;
+            while let sqlite::State::Row = read.next()? {
+                let rowid: i64 = read.read("rowid")?;
+                let values: Vec<u8> = read.read("values")?;
+                let range = Chunk::from_columns(&[], &values)
+                    .value_range()
+                    .map_err(|e| corrupt(format!("chunk values can't be indexed: {e}")))?;
+                ranges.extend(range.map(|range| (rowid, range)));
+            }
+            let mut write =
+                db.prepare("UPDATE chunks SET min_val = ?, max_val = ? WHERE rowid == ?")?;
+            for (rowid, (min, max)) in ranges {
+                write.bind((1, min))?;
+                write.bind((2, max))?;
+                write.bind((3, rowid))?;
+                while let sqlite::State::Row = write.next()? {}
+                write.reset()?;
+            }
+            Ok(())
+        })
+    }
+
+    fn copy_split_chunks(db: &sqlite::Connection) -> Result<(), sqlite::Error> {
+        db.execute(format!("CREATE TABLE chunks_split {CHUNKS_COLUMNS}"))?;
+        let mut read = db.prepare("SELECT series, start, stop, chunk FROM chunks")?;
+        let mut write = db.prepare("INSERT INTO chunks_split VALUES (?, ?, ?, ?, ?, ?, ?)")?;
+        while let sqlite::State::Row = read.next()? {
+            let series_key: i64 = read.read("series")?;
+            let start: i64 = read.read("start")?;
+            let stop: i64 = read.read("stop")?;
+            let chunk = Chunk::from_bytes(read.read("chunk")?);
+            let split = |e| {
+                corrupt(format!(
+                    "chunk {start}..{stop} of series {series_key} can't be split: {e}"
+                ))
+            };
+            let (times, values) = chunk.columns().map_err(split)?;
+            let range = chunk.value_range().map_err(split)?;
+            write.bind((1, series_key))?;
+            write.bind((2, start))?;
+            write.bind((3, stop))?;
+            write.bind((4, times))?;
+            write.bind((5, values))?;
+            write.bind((6, range.map(|(min, _max)| min)))?;
+            write.bind((7, range.map(|(_min, max)| max)))?;
+            while let sqlite::State::Row = write.next()? {}
+            write.reset()?;
+        }
+        drop((read, write));
+        db.execute("DROP TABLE chunks")?;
+        db.execute("ALTER TABLE chunks_split RENAME TO chunks")
     }
 
     pub fn new_memory() -> Result<Self, sqlite::Error> {
-        let mut db = sqlite::open(":memory:")?;
+        let mut db = sqlite::Connection::open_thread_safe(":memory:")?;
         Self::migrate(&mut db)?;
-        Ok(Self { db })
+        Ok(Self {
+            db,
+            has_schedules: true,
+        })
     }
 
+    /// Opens or creates a store at `path`. A file that can't be written, e.g. on a
+    /// read-only mount, is opened read-only as long as it already holds a store.
     pub fn new_path<T: AsRef<std::path::Path>>(path: T) -> Result<Self, sqlite::Error> {
-        let mut db = sqlite::open(path)?;
-        Self::migrate(&mut db)?;
-        Ok(Self { db })
+        Self::new_path_with(path, StoreOptions::default())
+    }
+
+    pub fn new_path_with<T: AsRef<std::path::Path>>(
+        path: T,
+        options: StoreOptions,
+    ) -> Result<Self, sqlite::Error> {
+        let mut db = sqlite::Connection::open_thread_safe(path.as_ref())?;
+        Self::configure(&db, &options)?;
+        let journal_mode = match options.journal_mode {
+            JournalMode::Delete => "DELETE",
+            JournalMode::Wal => "WAL",
+        };
+        let res = db
+            .execute(format!("PRAGMA journal_mode = {journal_mode}"))
+            .and_then(|()| Self::migrate(&mut db));
+        match res {
+            Ok(()) => Ok(Self {
+                db,
+                has_schedules: true,
+            }),
+            Err(e)
+                if e.code
+                    .is_some_and(|code| code & 0xff == sqlite3_sys::SQLITE_READONLY as isize) =>
+            {
+                Self::open_read_only(path, &options)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Opens an existing store without write access, skipping migrations.
+    /// Writes through the returned store fail. A store that still needs migrating fails
+    /// to open, until it has been opened for writing once.
+    pub fn new_path_read_only<T: AsRef<std::path::Path>>(path: T) -> Result<Self, sqlite::Error> {
+        Self::open_read_only(path, &StoreOptions::default())
+    }
+
+    pub(crate) fn open_read_only<T: AsRef<std::path::Path>>(
+        path: T,
+        options: &StoreOptions,
+    ) -> Result<Self, sqlite::Error> {
+        let flags = sqlite::OpenFlags::new().with_read_only();
+        let db = sqlite::Connection::open_thread_safe_with_flags(path, flags)?;
+        Self::configure(&db, options)?;
+        // fail on open rather than on the first query if there is no store yet
+        db.execute("SELECT times, \"values\", min_val, max_val FROM chunks LIMIT 0")?;
+        let has_schedules = Self::has_table(&db, "series_schedules")?;
+        Ok(Self { db, has_schedules })
+    }
+
+    fn configure(db: &sqlite::Connection, options: &StoreOptions) -> Result<(), sqlite::Error> {
+        db.execute(format!(
+            "PRAGMA busy_timeout = {}",
+            options.busy_timeout.as_millis()
+        ))?;
+        let synchronous = match options.synchronous {
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+        };
+        db.execute(format!("PRAGMA synchronous = {synchronous}"))?;
+        if let Some(cache_size) = options.cache_size {
+            db.execute(format!("PRAGMA cache_size = {cache_size}"))?;
+        }
+        if let Some(mmap_size) = options.mmap_size {
+            db.execute(format!("PRAGMA mmap_size = {mmap_size}"))?;
+        }
+        Ok(())
+    }
+
+    /// Runs `f` inside a transaction, rolling back if it returns an error.
+    fn transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, SetChunkError>,
+    ) -> Result<T, SetChunkError> {
+        fn driver(e: sqlite::Error) -> SetChunkError {
+            SetChunkError::Driver(e.into())
+        }
+        self.db.execute("BEGIN").map_err(driver)?;
+        match f(self) {
+            Ok(res) => {
+                self.db.execute("COMMIT").map_err(driver)?;
+                Ok(res)
+            }
+            Err(e) => {
+                self.db.execute("ROLLBACK").map_err(driver)?;
+                Err(e)
+            }
+        }
+    }
+
+    // reads the chunk rows a `GET_CHUNKS_IN_RANGE`-like statement selects
+    fn read_chunks(
+        &self,
+        series_key: i64,
+        mut statement: sqlite::Statement<'_>,
+    ) -> Result<Vec<(ChunkMeta, Chunk)>, GetChunkError> {
+        fn driver(e: sqlite::Error) -> GetChunkError {
+            GetChunkError::Driver(e.into())
+        }
+        let mut res = vec![];
+        while let sqlite::State::Row = statement.next().map_err(driver)? {
+            let meta = ChunkMeta {
+                series_key,
+                start: statement.read("start").map_err(driver)?,
+                stop: statement.read("stop").map_err(driver)?,
+            };
+            let times: Vec<u8> = statement.read("times").map_err(driver)?;
+            let values: Vec<u8> = statement.read("values").map_err(driver)?;
+            res.push((meta, Chunk::from_columns(&times, &values)));
+        }
+
+        statement.reset().map_err(driver)?;
+        Ok(res)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn connection(&self) -> &sqlite::Connection {
+        &self.db
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ChunkMeta {
     pub series_key: i64,
     pub start: i64,
@@ -59,6 +460,13 @@ pub struct ChunkMeta {
 }
 
 impl KelpieChunkStore for SqliteChunkStore {
+    /// Moves the content of the write-ahead log, if any, into the database file.
+    fn checkpoint(&mut self) -> Result<(), SetChunkError> {
+        self.db
+            .execute("PRAGMA wal_checkpoint(TRUNCATE)")
+            .map_err(|e| SetChunkError::Driver(e.into()))
+    }
+
     fn get_chunk(
         &self,
         series_key: i64,
@@ -68,7 +476,7 @@ impl KelpieChunkStore for SqliteChunkStore {
         fn driver(e: sqlite::Error) -> GetChunkError {
             GetChunkError::Driver(e.into())
         }
-        let mut statement = self.db.prepare("SELECT start, stop, chunk from chunks WHERE series == ? AND start <= ? AND stop >= ? ORDER BY start DESC LIMIT 1").map_err(driver)?;
+        let mut statement = self.db.prepare(GET_CHUNK).map_err(driver)?;
 
         statement.bind((1, series_key)).map_err(driver)?;
         statement.bind((2, start)).map_err(driver)?;
@@ -78,16 +486,14 @@ impl KelpieChunkStore for SqliteChunkStore {
         if let sqlite::State::Row = statement.next().map_err(driver)? {
             let res_start: i64 = statement.read::<i64, _>("start").map_err(driver)?;
             let res_stop: i64 = statement.read("stop").map_err(driver)?;
-            let res_chunk: Vec<u8> = statement.read("chunk").map_err(driver)?;
+            let res_times: Vec<u8> = statement.read("times").map_err(driver)?;
+            let res_values: Vec<u8> = statement.read("values").map_err(driver)?;
             let meta = ChunkMeta {
                 series_key,
                 start: res_start,
                 stop: res_stop,
             };
-            let chunk = Chunk {
-                compressed_data: res_chunk,
-            };
-            res = Some((meta, chunk));
+            res = Some((meta, Chunk::from_columns(&res_times, &res_values)));
         }
 
         statement.reset().map_err(driver)?;
@@ -104,15 +510,22 @@ impl KelpieChunkStore for SqliteChunkStore {
         fn driver(e: sqlite::Error) -> SetChunkError {
             SetChunkError::Driver(e.into())
         }
+        let (times, values) = chunk.columns().map_err(SetChunkError::Corrupt)?;
+        let range = chunk.value_range().map_err(SetChunkError::Corrupt)?;
         let mut statement = self
             .db
-            .prepare("INSERT OR REPLACE INTO chunks VALUES (?, ?, ?, ?)")
+            .prepare("INSERT OR REPLACE INTO chunks VALUES (?, ?, ?, ?, ?, ?, ?)")
             .map_err(driver)?;
         statement.bind((1, series_key)).map_err(driver)?;
         statement.bind((2, start)).map_err(driver)?;
         statement.bind((3, stop)).map_err(driver)?;
+        statement.bind((4, times)).map_err(driver)?;
+        statement.bind((5, values)).map_err(driver)?;
+        statement
+            .bind((6, range.map(|(min, _max)| min)))
+            .map_err(driver)?;
         statement
-            .bind((4, chunk.compressed_data.as_slice()))
+            .bind((7, range.map(|(_min, max)| max)))
             .map_err(driver)?;
         loop {
             let state = statement.next().map_err(driver)?;
@@ -124,134 +537,1048 @@ impl KelpieChunkStore for SqliteChunkStore {
         statement.reset().map_err(driver)?;
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::{Chunk, KelpieChunkStore};
+    fn set_chunks(&mut self, chunks: &[(ChunkMeta, Chunk)]) -> Result<(), SetChunkError> {
+        self.replace_chunks(&[], chunks)
+    }
 
-    #[test]
-    fn should_create_sqlite_chunk_store() -> Result<(), Box<dyn std::error::Error>> {
-        let _store = super::SqliteChunkStore::new_memory()?;
-        Ok(())
+    fn replace_chunks(
+        &mut self,
+        remove: &[ChunkMeta],
+        add: &[(ChunkMeta, Chunk)],
+    ) -> Result<(), SetChunkError> {
+        fn driver(e: sqlite::Error) -> SetChunkError {
+            SetChunkError::Driver(e.into())
+        }
+        self.transaction(|store| {
+            for meta in remove {
+                let mut statement = store
+                    .db
+                    .prepare("DELETE FROM chunks WHERE series == ? AND start == ? AND stop == ?")
+                    .map_err(driver)?;
+                statement.bind((1, meta.series_key)).map_err(driver)?;
+                statement.bind((2, meta.start)).map_err(driver)?;
+                statement.bind((3, meta.stop)).map_err(driver)?;
+                while let sqlite::State::Row = statement.next().map_err(driver)? {}
+            }
+            for (meta, chunk) in add {
+                store.set_chunk(meta.series_key, meta.start, meta.stop, chunk)?;
+            }
+            Ok(())
+        })
     }
 
-    #[test]
-    fn should_store_chunk() -> Result<(), Box<dyn std::error::Error>> {
-        let mut store = super::SqliteChunkStore::new_memory()?;
-        let chunk = Chunk {
-            compressed_data: vec![],
-        };
-        store.set_chunk(0, 10, 100, &chunk)?;
-        Ok(())
+    fn truncate(&mut self) -> Result<(), SetChunkError> {
+        fn driver(e: sqlite::Error) -> SetChunkError {
+            SetChunkError::Driver(e.into())
+        }
+        self.transaction(|store| {
+            store.db.execute("DELETE FROM chunks").map_err(driver)?;
+            store
+                .db
+                .execute("DELETE FROM series_meta")
+                .map_err(driver)?;
+            store
+                .db
+                .execute("DELETE FROM series_names")
+                .map_err(driver)?;
+            store
+                .db
+                .execute("DELETE FROM series_schedules")
+                .map_err(driver)?;
+            Ok(())
+        })
     }
 
-    #[test]
-    fn should_not_find_nonexisting_chunk() -> Result<(), Box<dyn std::error::Error>> {
-        let store = super::SqliteChunkStore::new_memory()?;
-        let res = store.get_chunk(0, 10, 100)?;
-        if res.is_some() {
-            Err("there should be no chunk")?;
+    fn delete_series(&mut self, series_key: i64) -> Result<(), SetChunkError> {
+        fn driver(e: sqlite::Error) -> SetChunkError {
+            SetChunkError::Driver(e.into())
+        }
+        self.transaction(|store| {
+            for query in [
+                "DELETE FROM chunks WHERE series == ?",
+                "DELETE FROM series_meta WHERE series == ?",
+                "DELETE FROM series_names WHERE series == ?",
+                "DELETE FROM series_schedules WHERE series == ?",
+            ] {
+                let mut statement = store.db.prepare(query).map_err(driver)?;
+                statement.bind((1, series_key)).map_err(driver)?;
+                while let sqlite::State::Row = statement.next().map_err(driver)? {}
+            }
+            Ok(())
+        })
+    }
+
+    fn delete_chunks_before(&mut self, series_key: i64, time: i64) -> Result<usize, SetChunkError> {
+        fn driver(e: sqlite::Error) -> SetChunkError {
+            SetChunkError::Driver(e.into())
+        }
+        let mut statement = self
+            .db
+            .prepare("DELETE FROM chunks WHERE series == ? AND stop <= ?")
+            .map_err(driver)?;
+        statement.bind((1, series_key)).map_err(driver)?;
+        statement.bind((2, time)).map_err(driver)?;
+        while let sqlite::State::Row = statement.next().map_err(driver)? {}
+        Ok(self.db.change_count())
+    }
+
+    fn set_series_metadata(&mut self, series_key: i64, json: &str) -> Result<(), SetChunkError> {
+        fn driver(e: sqlite::Error) -> SetChunkError {
+            SetChunkError::Driver(e.into())
         }
+        let mut statement = self
+            .db
+            .prepare("INSERT OR REPLACE INTO series_meta VALUES (?, ?)")
+            .map_err(driver)?;
+        statement.bind((1, series_key)).map_err(driver)?;
+        statement.bind((2, json)).map_err(driver)?;
+        while let sqlite::State::Row = statement.next().map_err(driver)? {}
+        statement.reset().map_err(driver)?;
         Ok(())
     }
 
-    #[test]
-    fn should_retrive_chunk() -> Result<(), Box<dyn std::error::Error>> {
-        let mut store = super::SqliteChunkStore::new_memory()?;
-        let chunk = Chunk {
-            compressed_data: vec![],
-        };
-        store.set_chunk(0, 10, 100, &chunk)?;
-        let (_, stored) = store.get_chunk(0, 10, 100)?.ok_or("no chunk found")?;
-        if chunk.compressed_data != stored.compressed_data {
-            Err("chunks don't match")?;
+    fn get_series_metadata(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+        fn driver(e: sqlite::Error) -> GetChunkError {
+            GetChunkError::Driver(e.into())
+        }
+        let mut statement = self
+            .db
+            .prepare("SELECT json FROM series_meta WHERE series == ?")
+            .map_err(driver)?;
+        statement.bind((1, series_key)).map_err(driver)?;
+
+        let mut res = None;
+        if let sqlite::State::Row = statement.next().map_err(driver)? {
+            res = statement.read("json").map_err(driver)?;
+        }
+
+        statement.reset().map_err(driver)?;
+        Ok(res)
+    }
+
+    fn set_series_name(&mut self, series_key: i64, name: &str) -> Result<(), SetChunkError> {
+        fn driver(e: sqlite::Error) -> SetChunkError {
+            SetChunkError::Driver(e.into())
         }
+        let mut statement = self
+            .db
+            .prepare("INSERT OR REPLACE INTO series_names VALUES (?, ?)")
+            .map_err(driver)?;
+        statement.bind((1, series_key)).map_err(driver)?;
+        statement.bind((2, name)).map_err(driver)?;
+        while let sqlite::State::Row = statement.next().map_err(driver)? {}
+        statement.reset().map_err(driver)?;
         Ok(())
     }
 
-    #[test]
-    fn should_retrive_smallest_chunk() -> Result<(), Box<dyn std::error::Error>> {
-        let mut store = super::SqliteChunkStore::new_memory()?;
+    fn get_series_name(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+        fn driver(e: sqlite::Error) -> GetChunkError {
+            GetChunkError::Driver(e.into())
+        }
+        let mut statement = self
+            .db
+            .prepare("SELECT name FROM series_names WHERE series == ?")
+            .map_err(driver)?;
+        statement.bind((1, series_key)).map_err(driver)?;
 
-        store.set_chunk(
-            0,
-            1,
-            9,
-            &Chunk {
-                compressed_data: vec![2],
-            },
-        )?;
-        store.set_chunk(
-            0,
-            0,
-            50,
-            &Chunk {
-                compressed_data: vec![5, 6],
-            },
-        )?;
-        store.set_chunk(
-            0,
-            50,
-            200,
-            &Chunk {
-                compressed_data: vec![5, 6],
-            },
-        )?;
-        store.set_chunk(
-            0,
-            10,
-            100,
-            &Chunk {
-                compressed_data: vec![],
-            },
-        )?;
-        store.set_chunk(
-            0,
-            0,
-            1000,
-            &Chunk {
-                compressed_data: vec![1],
-            },
-        )?;
-        let (_, stored) = store.get_chunk(0, 10, 100)?.ok_or("no chunk found")?;
-        if stored.compressed_data != vec![] {
-            Err("chunks don't match")?;
+        let mut res = None;
+        if let sqlite::State::Row = statement.next().map_err(driver)? {
+            res = Some(statement.read("name").map_err(driver)?);
+        }
+
+        statement.reset().map_err(driver)?;
+        Ok(res)
+    }
+
+    fn set_series_schedule(
+        &mut self,
+        series_key: i64,
+        schedule: &str,
+    ) -> Result<(), SetChunkError> {
+        fn driver(e: sqlite::Error) -> SetChunkError {
+            SetChunkError::Driver(e.into())
         }
+        let mut statement = self
+            .db
+            .prepare("INSERT OR REPLACE INTO series_schedules VALUES (?, ?)")
+            .map_err(driver)?;
+        statement.bind((1, series_key)).map_err(driver)?;
+        statement.bind((2, schedule)).map_err(driver)?;
+        while let sqlite::State::Row = statement.next().map_err(driver)? {}
+        statement.reset().map_err(driver)?;
         Ok(())
     }
 
-    #[test]
-    fn should_allow_storing_dupes() -> Result<(), Box<dyn std::error::Error>> {
-        let mut store = super::SqliteChunkStore::new_memory()?;
+    fn get_series_schedule(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+        fn driver(e: sqlite::Error) -> GetChunkError {
+            GetChunkError::Driver(e.into())
+        }
+        if !self.has_schedules {
+            return Ok(None);
+        }
+        let mut statement = self
+            .db
+            .prepare("SELECT schedule FROM series_schedules WHERE series == ?")
+            .map_err(driver)?;
+        statement.bind((1, series_key)).map_err(driver)?;
 
-        store.set_chunk(
-            0,
-            0,
-            100,
-            &Chunk {
-                compressed_data: vec![0],
-            },
-        )?;
-        store.set_chunk(
-            0,
-            0,
-            100,
-            &Chunk {
-                compressed_data: vec![1],
-            },
-        )?;
-        store.set_chunk(
-            0,
-            0,
-            100,
-            &Chunk {
-                compressed_data: vec![2],
-            },
-        )?;
-        let (_, stored) = store.get_chunk(0, 10, 100)?.ok_or("no chunk found")?;
-        if stored.compressed_data != vec![2] {
-            Err("chunks don't match")?;
+        let mut res = None;
+        if let sqlite::State::Row = statement.next().map_err(driver)? {
+            res = Some(statement.read("schedule").map_err(driver)?);
+        }
+
+        statement.reset().map_err(driver)?;
+        Ok(res)
+    }
+
+    fn list_all_chunks(&self) -> Result<Vec<ChunkMeta>, GetChunkError> {
+        fn driver(e: sqlite::Error) -> GetChunkError {
+            GetChunkError::Driver(e.into())
+        }
+        let mut statement = self
+            .db
+            .prepare("SELECT series, start, stop FROM chunks ORDER BY series, start, stop")
+            .map_err(driver)?;
+
+        let mut res = vec![];
+        while let sqlite::State::Row = statement.next().map_err(driver)? {
+            res.push(ChunkMeta {
+                series_key: statement.read("series").map_err(driver)?,
+                start: statement.read("start").map_err(driver)?,
+                stop: statement.read("stop").map_err(driver)?,
+            });
+        }
+        statement.reset().map_err(driver)?;
+        Ok(res)
+    }
+
+    fn list_series(&self) -> Result<Vec<i64>, GetChunkError> {
+        fn driver(e: sqlite::Error) -> GetChunkError {
+            GetChunkError::Driver(e.into())
+        }
+        let mut statement = self
+            .db
+            .prepare("SELECT DISTINCT series FROM chunks ORDER BY series")
+            .map_err(driver)?;
+
+        let mut res = vec![];
+        while let sqlite::State::Row = statement.next().map_err(driver)? {
+            res.push(statement.read("series").map_err(driver)?);
+        }
+        statement.reset().map_err(driver)?;
+        Ok(res)
+    }
+
+    fn get_chunks_in_range(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(ChunkMeta, Chunk)>, GetChunkError> {
+        fn driver(e: sqlite::Error) -> GetChunkError {
+            GetChunkError::Driver(e.into())
+        }
+        let mut statement = self.db.prepare(GET_CHUNKS_IN_RANGE).map_err(driver)?;
+        statement.bind((1, series_key)).map_err(driver)?;
+        statement.bind((2, stop)).map_err(driver)?;
+        statement.bind((3, start)).map_err(driver)?;
+        self.read_chunks(series_key, statement)
+    }
+
+    /// Skips chunks by their indexed smallest and largest value, without reading them.
+    fn get_chunks_in_value_range(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<(ChunkMeta, Chunk)>, GetChunkError> {
+        fn driver(e: sqlite::Error) -> GetChunkError {
+            GetChunkError::Driver(e.into())
+        }
+        let mut statement = self.db.prepare(GET_CHUNKS_IN_VALUE_RANGE).map_err(driver)?;
+        statement.bind((1, series_key)).map_err(driver)?;
+        statement.bind((2, stop)).map_err(driver)?;
+        statement.bind((3, start)).map_err(driver)?;
+        statement.bind((4, min)).map_err(driver)?;
+        statement.bind((5, max)).map_err(driver)?;
+        self.read_chunks(series_key, statement)
+    }
+
+    fn list_chunks(&self, series_key: i64) -> Result<Vec<ChunkMeta>, GetChunkError> {
+        fn driver(e: sqlite::Error) -> GetChunkError {
+            GetChunkError::Driver(e.into())
+        }
+        let mut statement = self
+            .db
+            .prepare("SELECT start, stop FROM chunks WHERE series == ? ORDER BY start, stop")
+            .map_err(driver)?;
+        statement.bind((1, series_key)).map_err(driver)?;
+
+        let mut res = vec![];
+        while let sqlite::State::Row = statement.next().map_err(driver)? {
+            res.push(ChunkMeta {
+                series_key,
+                start: statement.read("start").map_err(driver)?,
+                stop: statement.read("stop").map_err(driver)?,
+            });
+        }
+
+        statement.reset().map_err(driver)?;
+        Ok(res)
+    }
+
+    fn time_bounds(&self, series_key: i64) -> Result<Option<(i64, i64)>, GetChunkError> {
+        fn driver(e: sqlite::Error) -> GetChunkError {
+            GetChunkError::Driver(e.into())
+        }
+        let mut statement = self
+            .db
+            .prepare("SELECT MIN(start) AS start, MAX(stop) AS stop FROM chunks WHERE series == ?")
+            .map_err(driver)?;
+        statement.bind((1, series_key)).map_err(driver)?;
+
+        let mut res = None;
+        if let sqlite::State::Row = statement.next().map_err(driver)? {
+            let start: Option<i64> = statement.read("start").map_err(driver)?;
+            let stop: Option<i64> = statement.read("stop").map_err(driver)?;
+            res = start.zip(stop);
+        }
+
+        statement.reset().map_err(driver)?;
+        Ok(res)
+    }
+}
+
+/// Chunk store kept entirely in process memory, without any dependency.
+///
+/// Everything is lost when it is dropped. It also serves as the reference for how a
+/// `KelpieChunkStore` is expected to behave.
+#[derive(Default)]
+pub struct MemoryChunkStore {
+    chunks: BTreeMap<(i64, i64, i64), Vec<u8>>,
+    metadata: HashMap<i64, String>,
+    names: HashMap<i64, String>,
+    schedules: HashMap<i64, String>,
+}
+
+impl MemoryChunkStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn series_chunks(&self, series_key: i64) -> impl Iterator<Item = (ChunkMeta, &Vec<u8>)> {
+        self.chunks
+            .range((series_key, i64::MIN, i64::MIN)..=(series_key, i64::MAX, i64::MAX))
+            .map(|(&(series_key, start, stop), data)| {
+                let meta = ChunkMeta {
+                    series_key,
+                    start,
+                    stop,
+                };
+                (meta, data)
+            })
+    }
+}
+
+fn to_chunk(data: &[u8]) -> Chunk {
+    Chunk::from_bytes(data.to_vec())
+}
+
+impl KelpieChunkStore for MemoryChunkStore {
+    fn get_chunk(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<Option<(ChunkMeta, Chunk)>, GetChunkError> {
+        // the containing chunk with the latest start, then the earliest stop
+        let res = self
+            .series_chunks(series_key)
+            .filter(|(meta, _)| meta.start <= start && stop <= meta.stop)
+            .min_by_key(|(meta, _)| (std::cmp::Reverse(meta.start), meta.stop));
+        Ok(res.map(|(meta, data)| (meta, to_chunk(data))))
+    }
+
+    fn get_chunks_in_range(
+        &self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(ChunkMeta, Chunk)>, GetChunkError> {
+        Ok(self
+            .series_chunks(series_key)
+            .filter(|(meta, _)| meta.start < stop && start < meta.stop)
+            .map(|(meta, data)| (meta, to_chunk(data)))
+            .collect())
+    }
+
+    fn set_chunk(
+        &mut self,
+        series_key: i64,
+        start: i64,
+        stop: i64,
+        chunk: &Chunk,
+    ) -> Result<(), SetChunkError> {
+        self.chunks
+            .insert((series_key, start, stop), chunk.compressed_data.clone());
+        Ok(())
+    }
+
+    fn set_chunks(&mut self, chunks: &[(ChunkMeta, Chunk)]) -> Result<(), SetChunkError> {
+        self.replace_chunks(&[], chunks)
+    }
+
+    fn replace_chunks(
+        &mut self,
+        remove: &[ChunkMeta],
+        add: &[(ChunkMeta, Chunk)],
+    ) -> Result<(), SetChunkError> {
+        // nothing here can fail, so applying in order is atomic
+        for meta in remove {
+            self.chunks
+                .remove(&(meta.series_key, meta.start, meta.stop));
+        }
+        for (meta, chunk) in add {
+            self.set_chunk(meta.series_key, meta.start, meta.stop, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn truncate(&mut self) -> Result<(), SetChunkError> {
+        self.chunks.clear();
+        self.metadata.clear();
+        self.names.clear();
+        self.schedules.clear();
+        Ok(())
+    }
+
+    fn delete_series(&mut self, series_key: i64) -> Result<(), SetChunkError> {
+        self.chunks
+            .retain(|&(chunk_series, _, _), _| chunk_series != series_key);
+        self.metadata.remove(&series_key);
+        self.names.remove(&series_key);
+        self.schedules.remove(&series_key);
+        Ok(())
+    }
+
+    fn delete_chunks_before(&mut self, series_key: i64, time: i64) -> Result<usize, SetChunkError> {
+        let before = self.chunks.len();
+        self.chunks
+            .retain(|&(chunk_series, _, stop), _| chunk_series != series_key || stop > time);
+        Ok(before - self.chunks.len())
+    }
+
+    fn set_series_metadata(&mut self, series_key: i64, json: &str) -> Result<(), SetChunkError> {
+        self.metadata.insert(series_key, json.to_string());
+        Ok(())
+    }
+
+    fn get_series_metadata(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+        Ok(self.metadata.get(&series_key).cloned())
+    }
+
+    fn set_series_name(&mut self, series_key: i64, name: &str) -> Result<(), SetChunkError> {
+        self.names.insert(series_key, name.to_string());
+        Ok(())
+    }
+
+    fn get_series_name(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+        Ok(self.names.get(&series_key).cloned())
+    }
+
+    fn set_series_schedule(
+        &mut self,
+        series_key: i64,
+        schedule: &str,
+    ) -> Result<(), SetChunkError> {
+        self.schedules.insert(series_key, schedule.to_string());
+        Ok(())
+    }
+
+    fn get_series_schedule(&self, series_key: i64) -> Result<Option<String>, GetChunkError> {
+        Ok(self.schedules.get(&series_key).cloned())
+    }
+
+    fn list_all_chunks(&self) -> Result<Vec<ChunkMeta>, GetChunkError> {
+        Ok(self
+            .chunks
+            .keys()
+            .map(|&(series_key, start, stop)| ChunkMeta {
+                series_key,
+                start,
+                stop,
+            })
+            .collect())
+    }
+
+    fn list_chunks(&self, series_key: i64) -> Result<Vec<ChunkMeta>, GetChunkError> {
+        Ok(self
+            .series_chunks(series_key)
+            .map(|(meta, _)| meta)
+            .collect())
+    }
+
+    fn list_series(&self) -> Result<Vec<i64>, GetChunkError> {
+        let mut res: Vec<i64> = self
+            .chunks
+            .keys()
+            .map(|&(series_key, _, _)| series_key)
+            .collect();
+        res.dedup();
+        Ok(res)
+    }
+
+    fn time_bounds(&self, series_key: i64) -> Result<Option<(i64, i64)>, GetChunkError> {
+        let start = self
+            .series_chunks(series_key)
+            .map(|(meta, _)| meta.start)
+            .min();
+        let stop = self
+            .series_chunks(series_key)
+            .map(|(meta, _)| meta.stop)
+            .max();
+        Ok(start.zip(stop))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryChunkStore, SqliteChunkStore};
+    use crate::{Chunk, ChunkMeta, KelpieChunkStore};
+
+    // a chunk told apart by its times column, which doesn't decompress, with an empty
+    // values column, as storing a chunk reads its values
+    fn test_chunk(times: &[u8]) -> Chunk {
+        let empty = Chunk::compress_series(&crate::RawSeries::new());
+        let (_times, values) = empty.columns().expect("compressed chunks are framed");
+        Chunk::from_columns(times, values)
+    }
+
+    #[test]
+    fn should_create_sqlite_chunk_store() -> Result<(), Box<dyn std::error::Error>> {
+        let _store = super::SqliteChunkStore::new_memory()?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_store_chunk() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = super::SqliteChunkStore::new_memory()?;
+        let chunk = test_chunk(&[]);
+        store.set_chunk(0, 10, 100, &chunk)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_find_nonexisting_chunk() -> Result<(), Box<dyn std::error::Error>> {
+        let store = super::SqliteChunkStore::new_memory()?;
+        let res = store.get_chunk(0, 10, 100)?;
+        if res.is_some() {
+            Err("there should be no chunk")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_retrive_chunk() -> Result<(), Box<dyn std::error::Error>> {
+        retrive_chunk(SqliteChunkStore::new_memory()?)?;
+        retrive_chunk(MemoryChunkStore::new())
+    }
+
+    fn retrive_chunk(mut store: impl KelpieChunkStore) -> Result<(), Box<dyn std::error::Error>> {
+        if store.get_chunk(0, 10, 100)?.is_some() {
+            Err("there should be no chunk")?;
+        }
+        let chunk = test_chunk(&[]);
+        store.set_chunk(0, 10, 100, &chunk)?;
+        let (_, stored) = store.get_chunk(0, 10, 100)?.ok_or("no chunk found")?;
+        if chunk.as_bytes() != stored.as_bytes() {
+            Err("chunks don't match")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_retrive_smallest_chunk() -> Result<(), Box<dyn std::error::Error>> {
+        retrive_smallest_chunk(SqliteChunkStore::new_memory()?)?;
+        retrive_smallest_chunk(MemoryChunkStore::new())
+    }
+
+    fn retrive_smallest_chunk(
+        mut store: impl KelpieChunkStore,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        store.set_chunk(0, 1, 9, &test_chunk(&[2]))?;
+        store.set_chunk(0, 0, 50, &test_chunk(&[5, 6]))?;
+        store.set_chunk(0, 50, 200, &test_chunk(&[5, 6]))?;
+        store.set_chunk(0, 10, 100, &test_chunk(&[]))?;
+        store.set_chunk(0, 0, 1000, &test_chunk(&[1]))?;
+        let (_, stored) = store.get_chunk(0, 10, 100)?.ok_or("no chunk found")?;
+        if stored.as_bytes() != test_chunk(&[]).as_bytes() {
+            Err("chunks don't match")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_store_series_metadata() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = super::SqliteChunkStore::new_memory()?;
+        if store.get_series_metadata(0)?.is_some() {
+            Err("there should be no metadata")?;
+        }
+        store.set_series_metadata(0, r#"{"unit": "C"}"#)?;
+        store.set_series_metadata(0, r#"{"unit": "K"}"#)?;
+        if store.get_series_metadata(0)?.as_deref() != Some(r#"{"unit": "K"}"#) {
+            Err("metadata doesn't match")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_delete_series() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = super::SqliteChunkStore::new_memory()?;
+        let chunk = test_chunk(&[]);
+        store.set_chunk(0, 0, 100, &chunk)?;
+        store.set_chunk(1, 0, 100, &chunk)?;
+        store.set_series_metadata(0, "{}")?;
+        store.delete_series(0)?;
+        if store.get_chunk(0, 0, 100)?.is_some() || store.get_series_metadata(0)?.is_some() {
+            Err("series was not deleted")?;
+        }
+        if store.get_chunk(1, 0, 100)?.is_none() {
+            Err("other series was deleted")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_delete_chunks_before() -> Result<(), Box<dyn std::error::Error>> {
+        delete_chunks_before(SqliteChunkStore::new_memory()?)?;
+        delete_chunks_before(MemoryChunkStore::new())
+    }
+
+    fn delete_chunks_before(
+        mut store: impl KelpieChunkStore,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let chunk = test_chunk(&[]);
+        store.set_chunk(0, 0, 100, &chunk)?;
+        store.set_chunk(0, 100, 200, &chunk)?;
+        store.set_chunk(0, 200, 300, &chunk)?;
+        store.set_chunk(1, 0, 100, &chunk)?;
+        assert_eq!(store.delete_chunks_before(0, 250)?, 2);
+        let starts: Vec<i64> = store.list_chunks(0)?.iter().map(|m| m.start).collect();
+        assert_eq!(starts, vec![200]);
+        assert_eq!(store.list_chunks(1)?.len(), 1);
+        assert_eq!(store.delete_chunks_before(0, 250)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn should_list_chunks_in_order() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = super::SqliteChunkStore::new_memory()?;
+        let chunk = test_chunk(&[]);
+        store.set_chunk(0, 100, 200, &chunk)?;
+        store.set_chunk(0, 0, 100, &chunk)?;
+        store.set_chunk(1, 50, 60, &chunk)?;
+        let starts: Vec<i64> = store.list_chunks(0)?.iter().map(|m| m.start).collect();
+        if starts != vec![0, 100] {
+            Err("wrong chunks listed")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_list_series() -> Result<(), Box<dyn std::error::Error>> {
+        list_series(SqliteChunkStore::new_memory()?)?;
+        list_series(MemoryChunkStore::new())
+    }
+
+    fn list_series(mut store: impl KelpieChunkStore) -> Result<(), Box<dyn std::error::Error>> {
+        let chunk = test_chunk(&[]);
+        store.set_chunk(7, 0, 100, &chunk)?;
+        store.set_chunk(-3, 0, 100, &chunk)?;
+        store.set_chunk(7, 100, 200, &chunk)?;
+        // metadata alone doesn't make a series
+        store.set_series_metadata(9, "{}")?;
+        assert_eq!(store.list_series()?, vec![-3, 7]);
+        store.delete_series(7)?;
+        assert_eq!(store.list_series()?, vec![-3]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_get_chunks_overlapping_range() -> Result<(), Box<dyn std::error::Error>> {
+        get_chunks_overlapping_range(SqliteChunkStore::new_memory()?)?;
+        get_chunks_overlapping_range(MemoryChunkStore::new())
+    }
+
+    fn get_chunks_overlapping_range(
+        mut store: impl KelpieChunkStore,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let chunk = test_chunk(&[]);
+        store.set_chunk(0, 300, 310, &chunk)?;
+        store.set_chunk(0, 0, 100, &chunk)?;
+        store.set_chunk(0, 100, 250, &chunk)?;
+        store.set_chunk(1, 120, 130, &chunk)?;
+        let bounds: Vec<(i64, i64)> = store
+            .get_chunks_in_range(0, 99, 301)?
+            .iter()
+            .map(|(meta, _)| (meta.start, meta.stop))
+            .collect();
+        if bounds != vec![(0, 100), (100, 250), (300, 310)] {
+            Err("wrong chunks returned")?;
+        }
+        if !store.get_chunks_in_range(0, 250, 300)?.is_empty() {
+            Err("gap should have no chunks")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_apply_busy_timeout() -> Result<(), Box<dyn std::error::Error>> {
+        let busy_timeout = |store: &super::SqliteChunkStore| -> Result<i64, sqlite::Error> {
+            let mut statement = store.connection().prepare("PRAGMA busy_timeout")?;
+            statement.next()?;
+            statement.read::<i64, _>(0)
+        };
+        let temp = crate::tests::TempPath::new();
+        let options = super::StoreOptions {
+            busy_timeout: std::time::Duration::from_millis(1234),
+            ..Default::default()
+        };
+        let store = super::SqliteChunkStore::new_path_with(temp.path(), options)?;
+        assert_eq!(busy_timeout(&store)?, 1234);
+        drop(store);
+        let store = super::SqliteChunkStore::new_path(temp.path())?;
+        assert_eq!(busy_timeout(&store)?, 5000);
+        Ok(())
+    }
+
+    #[test]
+    fn should_apply_pragmas() -> Result<(), Box<dyn std::error::Error>> {
+        let pragma =
+            |store: &super::SqliteChunkStore, name: &str| -> Result<String, sqlite::Error> {
+                let mut statement = store.connection().prepare(format!("PRAGMA {name}"))?;
+                statement.next()?;
+                statement.read::<String, _>(0)
+            };
+        let temp = crate::tests::TempPath::new();
+        let store = super::SqliteChunkStore::new_path(temp.path())?;
+        assert_eq!(pragma(&store, "journal_mode")?, "delete");
+        assert_eq!(pragma(&store, "synchronous")?, "2");
+        drop(store);
+
+        let options = super::StoreOptions {
+            cache_size: Some(-4096),
+            mmap_size: Some(1 << 20),
+            ..super::StoreOptions::bulk_load()
+        };
+        let store = super::SqliteChunkStore::new_path_with(temp.path(), options)?;
+        assert_eq!(pragma(&store, "journal_mode")?, "wal");
+        assert_eq!(pragma(&store, "synchronous")?, "1");
+        assert_eq!(pragma(&store, "cache_size")?, "-4096");
+        assert_eq!(pragma(&store, "mmap_size")?, (1 << 20).to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn should_open_read_only() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = crate::tests::TempPath::new();
+        let chunk = Chunk::compress_series(&crate::RawSeries::new());
+        {
+            let mut store = super::SqliteChunkStore::new_path(temp.path())?;
+            store.set_chunk(0, 0, 100, &chunk)?;
+        }
+        let mut store = super::SqliteChunkStore::new_path_read_only(temp.path())?;
+        if store.get_chunk(0, 0, 100)?.is_none() {
+            Err("stored chunk should be readable")?;
+        }
+        if store.set_chunk(0, 100, 200, &chunk).is_ok() {
+            Err("read-only store should reject writes")?;
+        }
+
+        let missing = crate::tests::TempPath::new();
+        if super::SqliteChunkStore::new_path_read_only(missing.path()).is_ok() {
+            Err("missing store should not open")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_open_read_only_without_schedules() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = crate::tests::TempPath::new();
+        let chunk = Chunk::compress_series(&crate::RawSeries::new());
+        {
+            let mut store = super::SqliteChunkStore::new_path(temp.path())?;
+            store.set_chunk(0, 0, 100, &chunk)?;
+            // as written before series could have their own schedule
+            store.connection().execute("DROP TABLE series_schedules")?;
+        }
+        let store = super::SqliteChunkStore::new_path_read_only(temp.path())?;
+        assert_eq!(store.get_series_schedule(0)?, None);
+        assert!(store.get_chunk(0, 0, 100)?.is_some());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_fall_back_to_read_only() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = crate::tests::TempPath::new();
+        let chunk = Chunk::compress_series(&crate::RawSeries::new());
+        {
+            let mut store = super::SqliteChunkStore::new_path(temp.path())?;
+            store.set_chunk(0, 0, 100, &chunk)?;
+        }
+        std::fs::set_permissions(temp.path(), std::fs::Permissions::from_mode(0o444))?;
+        if std::fs::OpenOptions::new()
+            .write(true)
+            .open(temp.path())
+            .is_ok()
+        {
+            // permissions don't apply, e.g. when running as root
+            return Ok(());
+        }
+        let mut store = super::SqliteChunkStore::new_path(temp.path())?;
+        if store.get_chunk(0, 0, 100)?.is_none() {
+            Err("stored chunk should be readable")?;
+        }
+        if store.set_chunk(0, 100, 200, &chunk).is_ok() {
+            Err("read-only store should reject writes")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_split_legacy_chunk_blobs() -> Result<(), Box<dyn std::error::Error>> {
+        let legacy_store = |blob: &[u8]| -> Result<_, Box<dyn std::error::Error>> {
+            let temp = crate::tests::TempPath::new();
+            let db = sqlite::open(temp.path())?;
+            db.execute("CREATE TABLE chunks (series INTEGER, start INTEGER, stop INTEGER, chunk BLOB, UNIQUE (series, start, stop))")?;
+            let mut statement = db.prepare("INSERT INTO chunks VALUES (3, 0, 100, ?)")?;
+            statement.bind((1, blob))?;
+            statement.next()?;
+            Ok(temp)
+        };
+        let series: crate::RawSeries = (0..10)
+            .map(|time| crate::DataPoint {
+                time,
+                value: time as f64 / 2.0,
+            })
+            .collect();
+        let chunk = Chunk::compress_series(&series);
+
+        let temp = legacy_store(chunk.as_bytes())?;
+        if super::SqliteChunkStore::new_path_read_only(temp.path()).is_ok() {
+            Err("unmigrated store should not open read-only")?;
+        }
+        let store = super::SqliteChunkStore::new_path(temp.path())?;
+        let (_, stored) = store.get_chunk(3, 0, 100)?.ok_or("no chunk found")?;
+        assert_eq!(stored.as_bytes(), chunk.as_bytes());
+        drop(store);
+        // migrated once, the store opens read-only as well
+        let store = super::SqliteChunkStore::new_path_read_only(temp.path())?;
+        assert_eq!(store.list_series()?, vec![3]);
+
+        let temp = legacy_store(&[1, 2, 3])?;
+        if super::SqliteChunkStore::new_path(temp.path()).is_ok() {
+            Err("malformed blob should abort the migration")?;
+        }
+        let db = sqlite::open(temp.path())?;
+        db.execute("SELECT chunk FROM chunks LIMIT 0")?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_index_value_ranges_of_existing_chunks() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = crate::tests::TempPath::new();
+        let db = sqlite::open(temp.path())?;
+        db.execute("CREATE TABLE chunks (series INTEGER, start INTEGER, stop INTEGER, times BLOB, \"values\" BLOB, UNIQUE (series, start, stop))")?;
+        let mut statement = db.prepare("INSERT INTO chunks VALUES (3, ?, ?, ?, ?)")?;
+        for (start, values) in [(0, vec![2.5, -1.0, 4.0]), (100, vec![]), (200, vec![10.0])] {
+            let series: crate::RawSeries = values
+                .into_iter()
+                .enumerate()
+                .map(|(i, value)| crate::DataPoint {
+                    time: start + i as i64,
+                    value,
+                })
+                .collect();
+            let chunk = Chunk::compress_series(&series);
+            let (times, values) = chunk.columns()?;
+            statement.bind((1, start))?;
+            statement.bind((2, start + 100))?;
+            statement.bind((3, times))?;
+            statement.bind((4, values))?;
+            statement.next()?;
+            statement.reset()?;
+        }
+        drop(statement);
+        drop(db);
+
+        let store = super::SqliteChunkStore::new_path(temp.path())?;
+        let mut ranges = vec![];
+        let mut statement = store
+            .connection()
+            .prepare("SELECT min_val, max_val FROM chunks ORDER BY start")?;
+        while let sqlite::State::Row = statement.next()? {
+            ranges.push((
+                statement.read::<Option<f64>, _>("min_val")?,
+                statement.read::<Option<f64>, _>("max_val")?,
+            ));
+        }
+        assert_eq!(
+            ranges,
+            [
+                (Some(-1.0), Some(4.0)),
+                (None, None),
+                (Some(10.0), Some(10.0))
+            ]
+        );
+
+        // only the matching chunk is read
+        let chunks = store.get_chunks_in_value_range(3, 0, 300, 4.0, 9.0)?;
+        let starts: Vec<i64> = chunks.iter().map(|(meta, _chunk)| meta.start).collect();
+        assert_eq!(starts, [0]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_malformed_chunk() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = super::SqliteChunkStore::new_memory()?;
+        match store.set_chunk(0, 0, 100, &Chunk::from_bytes(vec![1, 2])) {
+            Err(super::SetChunkError::Corrupt(_)) => Ok(()),
+            res => Err(format!("malformed chunk should be rejected, got {res:?}"))?,
+        }
+    }
+
+    #[test]
+    fn should_report_time_bounds() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = super::SqliteChunkStore::new_memory()?;
+        if store.time_bounds(0)?.is_some() {
+            Err("empty series should have no bounds")?;
+        }
+        let chunk = test_chunk(&[]);
+        store.set_chunk(0, 100, 200, &chunk)?;
+        store.set_chunk(0, 0, 100, &chunk)?;
+        store.set_chunk(1, 500, 600, &chunk)?;
+        if store.time_bounds(0)? != Some((0, 200)) {
+            Err("wrong bounds")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_roll_back_failed_chunk_batch() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = super::SqliteChunkStore::new_memory()?;
+        store.connection().execute(
+            "CREATE TRIGGER fail BEFORE INSERT ON chunks WHEN NEW.series = 99 BEGIN SELECT RAISE(ABORT, 'fail'); END",
+        )?;
+        let meta = |series_key| ChunkMeta {
+            series_key,
+            start: 0,
+            stop: 100,
+        };
+        let chunk = test_chunk(&[1]);
+        store.set_chunks(&[(meta(0), chunk.clone()), (meta(1), chunk.clone())])?;
+        let res = store.set_chunks(&[(meta(1), test_chunk(&[2])), (meta(99), chunk)]);
+        if res.is_ok() {
+            Err("batch should fail")?;
+        }
+        let (_, stored) = store.get_chunk(1, 0, 100)?.ok_or("no chunk found")?;
+        if stored.as_bytes() != test_chunk(&[1]).as_bytes() {
+            Err("failed batch was not rolled back")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_replace_chunks() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = super::SqliteChunkStore::new_memory()?;
+        let meta = |start, stop| ChunkMeta {
+            series_key: 0,
+            start,
+            stop,
+        };
+        let chunk = test_chunk(&[1]);
+        store.set_chunks(&[
+            (meta(0, 100), chunk.clone()),
+            (meta(100, 200), chunk.clone()),
+        ])?;
+        store.replace_chunks(&[meta(0, 100), meta(100, 200)], &[(meta(0, 200), chunk)])?;
+        let chunks: Vec<(i64, i64)> = store
+            .list_chunks(0)?
+            .iter()
+            .map(|m| (m.start, m.stop))
+            .collect();
+        if chunks != vec![(0, 200)] {
+            Err("chunks were not replaced")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_allow_storing_dupes() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = super::SqliteChunkStore::new_memory()?;
+
+        store.set_chunk(0, 0, 100, &test_chunk(&[0]))?;
+        store.set_chunk(0, 0, 100, &test_chunk(&[1]))?;
+        store.set_chunk(0, 0, 100, &test_chunk(&[2]))?;
+        let (_, stored) = store.get_chunk(0, 10, 100)?.ok_or("no chunk found")?;
+        if stored.as_bytes() != test_chunk(&[2]).as_bytes() {
+            Err("chunks don't match")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_keep_one_row_per_chunk() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = super::SqliteChunkStore::new_memory()?;
+        for data in [vec![0], vec![1]] {
+            store.set_chunk(0, 0, 100, &test_chunk(&data))?;
+        }
+        let mut statement = store
+            .connection()
+            .prepare("SELECT COUNT(*) FROM chunks WHERE series == 0")?;
+        statement.next()?;
+        assert_eq!(statement.read::<i64, _>(0)?, 1);
+        let (_, stored) = store.get_chunk(0, 0, 100)?.ok_or("no chunk found")?;
+        assert_eq!(stored.as_bytes(), test_chunk(&[1]).as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn should_look_up_chunks_by_index() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = super::SqliteChunkStore::new_memory()?;
+        let chunk = test_chunk(&[]);
+        let chunks: Vec<(ChunkMeta, Chunk)> = (0..10_000)
+            .map(|i| {
+                let meta = ChunkMeta {
+                    series_key: i % 10,
+                    start: i * 100,
+                    stop: (i + 1) * 100,
+                };
+                (meta, chunk.clone())
+            })
+            .collect();
+        store.set_chunks(&chunks)?;
+        store.connection().execute("ANALYZE")?;
+        for query in [super::GET_CHUNK, super::GET_CHUNKS_IN_RANGE] {
+            let mut statement = store
+                .connection()
+                .prepare(format!("EXPLAIN QUERY PLAN {query}"))?;
+            let mut plan = vec![];
+            while let sqlite::State::Row = statement.next()? {
+                plan.push(statement.read::<String, _>("detail")?);
+            }
+            if !plan
+                .iter()
+                .any(|step| step.starts_with("SEARCH chunks USING INDEX"))
+            {
+                Err(format!("chunks are scanned: {plan:?}"))?;
+            }
         }
         Ok(())
     }
@@ -0,0 +1,14 @@
+use kelpie::{DataPoint, InsertStatus, Kelpie};
+
+#[test]
+fn should_expose_schedule_of_rolled_chunk() -> Result<(), Box<dyn std::error::Error>> {
+    let hour = 3_600_000;
+    let mut kelpie = Kelpie::new_memory()?;
+    let at = |time| DataPoint { time, value: 1.0 };
+    kelpie.insert(0, at(10))?;
+    let InsertStatus::CompactmentPending(schedule) = kelpie.insert(0, at(hour + 10))? else {
+        return Err("inserting into the next chunk should roll the cached one".into());
+    };
+    assert_eq!((schedule.start(), schedule.stop()), (0, hour));
+    Ok(())
+}